@@ -1,10 +1,93 @@
 mod builder;
+mod compress;
+mod delta;
 mod iterator;
+mod pool;
+mod xxhash;
 
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
 pub use builder::BlockBuilder;
 /// You may want to check `bytes::BufMut` out when manipulating continuous chunks of memory
 use bytes::Bytes;
-pub use iterator::BlockIterator;
+use compress::{decompress, try_decompress, COMPRESSED_VALUE_FLAG};
+pub use iterator::{BlockIterator, OwnedBlockIter};
+pub use pool::BlockPool;
+
+use crate::table::TableError;
+
+/// Get the key of an entry laid out as | key_len (u16) | key | value_len (u16) | value |, e.g.
+/// from `Block::raw_entry`, without decoding its value. Fails with `TableError::Corruption` if the
+/// entry is too short for its `key_len`.
+pub(crate) fn decode_raw_key(entry: &[u8]) -> Result<&[u8], TableError> {
+    if entry.len() < 2 {
+        return Err(TableError::corruption(
+            None,
+            "entry too short for its key length",
+        ));
+    }
+    let key_len = u16::from_be_bytes([entry[0], entry[1]]) as usize;
+    entry.get(2..2 + key_len).ok_or_else(|| {
+        TableError::corruption(
+            None,
+            format!("key of {} bytes past an entry of {}", key_len, entry.len()),
+        )
+    })
+}
+
+/// Decode an entry laid out like for `decode_raw_key` into its key and its value, decompressing
+/// the value if needed. Fails with `TableError::Corruption` if the entry is too short for its
+/// lengths or the value is not validly compressed.
+pub(crate) fn decode_raw_entry(entry: &[u8]) -> Result<(&[u8], Bytes), TableError> {
+    let key = decode_raw_key(entry)?;
+    let rest = &entry[2 + key.len()..];
+    if rest.len() < 2 {
+        return Err(TableError::corruption(
+            None,
+            "entry too short for its value length",
+        ));
+    }
+    let val_len = u16::from_be_bytes([rest[0], rest[1]]);
+    let Some(value) = rest.get(2..2 + (val_len & !COMPRESSED_VALUE_FLAG) as usize) else {
+        return Err(TableError::corruption(
+            None,
+            format!("value past an entry of {} bytes", entry.len()),
+        ));
+    };
+    if val_len & COMPRESSED_VALUE_FLAG != 0 {
+        let value = try_decompress(value)
+            .ok_or_else(|| TableError::corruption(None, "value is not validly compressed"))?;
+        Ok((key, Bytes::from(value)))
+    } else {
+        Ok((key, Bytes::copy_from_slice(value)))
+    }
+}
+
+/// Check whether the encoded block `data` has its keys delta-encoded, see `delta::encode`.
+pub(crate) fn is_delta_encoded(data: &[u8]) -> bool {
+    data.len() >= 2 && data[data.len() - 2] as u16 & (delta::DELTA_KEYS_FLAG >> 8) != 0
+}
+
+/// Set in the `num_of_elements` of a block whose entries end with a sequence number, see
+/// `BlockBuilder::set_seqs`. Blocks are bounded by the block size, so the count never reaches it.
+const SEQ_FLAG: u16 = 0x4000;
+
+/// The length of the sequence number ending each entry of a block with sequence numbers.
+const SEQ_LEN: usize = 8;
+
+/// Check whether the entries of the encoded block `data` end with a sequence number.
+pub(crate) fn has_seqs(data: &[u8]) -> bool {
+    data.len() >= 2 && data[data.len() - 2] as u16 & (SEQ_FLAG >> 8) != 0
+}
+
+/// Get the number of entries of the encoded block `data` from its `num_of_elements`, without the
+/// flags set in it.
+pub(crate) fn num_of_elements(data: &[u8]) -> u16 {
+    u16::from_be_bytes([data[data.len() - 2], data[data.len() - 1]])
+        & !(delta::DELTA_KEYS_FLAG | SEQ_FLAG)
+}
 
 /// A block is the smallest unit of read and caching in LSM tree.
 /// It is a collection of sorted key-value pairs.
@@ -15,31 +98,255 @@ pub use iterator::BlockIterator;
 /// ----------------------------------------------------------------------------------------------------
 /// | Entry #1 | Entry #2 | ... | Entry #N | Offset #1 | Offset #2 | ... | Offset #N | num_of_elements |
 /// ----------------------------------------------------------------------------------------------------
+///
+/// A block built with `BlockBuilder::set_delta_keys` whose keys allow it is encoded with its keys
+/// delta-encoded instead, see `delta::encode`. Either way the block is held in memory as above.
+/// A block built with `BlockBuilder::set_seqs` has a sequence number (u64) after the value of each
+/// entry, and `SEQ_FLAG` set in its `num_of_elements`.
 pub struct Block {
-    data: Vec<u8>,
+    data: BlockData,
     offsets: Vec<u16>,
+    /// The pool `data` is handed back to when the block is dropped, if it was taken from one.
+    pool: Option<Arc<BlockPool>>,
+    /// Whether the block is encoded with its keys delta-encoded.
+    delta_keys: bool,
+    /// Whether each entry ends with a sequence number.
+    seqs: bool,
+}
+
+/// The data section of a block, shared so that entries can be handed out without copying them,
+/// see `BlockIterator::current_entry`, unless it lives in a buffer taken from a `BlockPool`.
+enum BlockData {
+    Shared(Bytes),
+    Pooled(Vec<u8>),
+}
+
+impl std::ops::Deref for BlockData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            BlockData::Shared(data) => data,
+            BlockData::Pooled(data) => data,
+        }
+    }
+}
+
+impl PartialEq for BlockData {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl std::fmt::Debug for BlockData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl Drop for Block {
+    fn drop(&mut self) {
+        if let (Some(pool), BlockData::Pooled(data)) = (self.pool.take(), &mut self.data) {
+            pool.put(std::mem::take(data));
+        }
+    }
 }
 
 impl Block {
     /// Encode the internal data to the data layout illustrated in the tutorial
     /// Note: You may want to recheck if any of the expected field is missing from your output
     pub fn encode(&self) -> Bytes {
+        if self.delta_keys {
+            return Bytes::from(delta::encode(self));
+        }
         let mut bytes: Vec<u8> = Vec::with_capacity(self.data.len() + self.offsets.len() * 2 + 2);
         bytes.extend_from_slice(&self.data);
         for &offset in self.offsets.iter().rev() {
             bytes.push((offset >> 8) as u8);
             bytes.push(offset as u8);
         }
-        let num_of_elements = self.offsets.len() as u16;
+        let mut num_of_elements = self.offsets.len() as u16;
+        if self.seqs {
+            num_of_elements |= SEQ_FLAG;
+        }
         bytes.push((num_of_elements >> 8) as u8);
         bytes.push(num_of_elements as u8);
         Bytes::from(bytes)
     }
 
+    /// Get a hash of the content of the block, its entries and their offsets, e.g. to tell two
+    /// blocks apart without comparing them byte by byte, or to find identical blocks across
+    /// tables. The hash is XXH64, so it is stable across builds and platforms. A block with
+    /// delta-encoded keys is hashed as rebuilt with its keys in full, so it hashes the same as the
+    /// block of the same entries with plain keys. Values are hashed as stored, so a block with
+    /// compressed values does not hash like the same entries with plain values.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = xxhash::Xxh64::new(0);
+        hasher.update(&self.data);
+        for offset in &self.offsets {
+            hasher.update(&offset.to_be_bytes());
+        }
+        hasher.finish()
+    }
+
+    /// Get the number of entries in the block.
+    pub fn num_of_entries(&self) -> usize {
+        self.offsets.len()
+    }
+
+    /// Whether each entry of the block ends with a sequence number, see `BlockIterator::seq`.
+    pub fn has_seqs(&self) -> bool {
+        self.seqs
+    }
+
+    /// Get the encoded bytes of the entry at `idx`, laid out as
+    /// | key_len (u16) | key | value_len (u16) | value |, followed by | seq (u64) | in a block with
+    /// sequence numbers, e.g. to copy it with `BlockBuilder::append_raw`.
+    pub fn raw_entry(&self, idx: usize) -> &[u8] {
+        let start = self.offsets[idx] as usize;
+        let end = self
+            .offsets
+            .get(idx + 1)
+            .map_or(self.data.len(), |&offset| offset as usize);
+        &self.data[start..end]
+    }
+
+    /// Create an iterator over the block, seeked to the first entry, like
+    /// `BlockIterator::create_and_seek_to_first`.
+    pub fn iter(self: &Arc<Block>) -> BlockIterator {
+        BlockIterator::create_and_seek_to_first(self.clone())
+    }
+
+    /// Get the entries of the block in order as `(key, value)` pairs. The keys and the plain
+    /// values are borrowed from the block; a compressed value is decompressed into a new buffer,
+    /// as it has no plain bytes to borrow.
+    pub fn entries(&self) -> impl Iterator<Item = (&[u8], Cow<'_, [u8]>)> {
+        (0..self.num_of_entries()).map(|idx| {
+            let entry = self.raw_entry(idx);
+            let key_len = u16::from_be_bytes([entry[0], entry[1]]) as usize;
+            let val_len = u16::from_be_bytes([entry[2 + key_len], entry[3 + key_len]]);
+            let value =
+                &entry[4 + key_len..4 + key_len + (val_len & !COMPRESSED_VALUE_FLAG) as usize];
+            let value = if val_len & COMPRESSED_VALUE_FLAG != 0 {
+                Cow::Owned(decompress(value))
+            } else {
+                Cow::Borrowed(value)
+            };
+            (&entry[2..2 + key_len], value)
+        })
+    }
+
+    /// Get the bytes at `range` of the data section, sharing them with the block unless the block
+    /// lives in a buffer of a `BlockPool`, which goes back to the pool, so they are copied.
+    pub(crate) fn data_slice(&self, range: std::ops::Range<usize>) -> Bytes {
+        match &self.data {
+            BlockData::Shared(data) => data.slice(range),
+            BlockData::Pooled(data) => Bytes::copy_from_slice(&data[range]),
+        }
+    }
+
+    /// Returns the key of the entry at `idx` without copying it.
+    pub(crate) fn key_at(&self, idx: usize) -> &[u8] {
+        let offset = self.offsets[idx] as usize;
+        let key_len = u16::from_be_bytes([self.data[offset], self.data[offset + 1]]);
+        &self.data[offset + 2..offset + 2 + key_len as usize]
+    }
+
+    /// Binary search the block for `key`, like `slice::binary_search`: returns `Ok` with the index
+    /// of the entry holding `key`, or `Err` with the index where `key` would be inserted to keep
+    /// the entries sorted, which is the number of entries if `key` is past the last one.
+    pub fn search(&self, key: &[u8]) -> Result<usize, usize> {
+        let mut low = 0;
+        let mut high = self.offsets.len();
+        while low < high {
+            let mid = (low + high) / 2;
+            match self.key_at(mid).cmp(key) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return Ok(mid),
+            }
+        }
+        Err(low)
+    }
+
+    /// Get the number of bytes the block occupies once encoded, without encoding it.
+    pub fn encoded_len(&self) -> usize {
+        if self.delta_keys {
+            return delta::encoded_len(self);
+        }
+        self.data.len() + self.offsets.len() * 2 + 2
+    }
+
+    /// Get the number of bytes the block would occupy once encoded with every value stored plain,
+    /// which is `encoded_len` unless some values are compressed.
+    pub fn uncompressed_len(&self) -> usize {
+        let mut len = self.encoded_len();
+        for idx in 0..self.offsets.len() {
+            let entry = self.raw_entry(idx);
+            let key_len = u16::from_be_bytes([entry[0], entry[1]]) as usize;
+            let val_len = u16::from_be_bytes([entry[2 + key_len], entry[3 + key_len]]);
+            if val_len & COMPRESSED_VALUE_FLAG != 0 {
+                let value =
+                    &entry[4 + key_len..4 + key_len + (val_len & !COMPRESSED_VALUE_FLAG) as usize];
+                len = len - value.len() + decompress(value).len();
+            }
+        }
+        len
+    }
+
     /// Decode from the data layout, transform the input `data` to a single `Block`
+    /// `data` must be a well-formed block, e.g. one just encoded: bytes read from the disk go
+    /// through `try_decode`, which reports a malformed block as an error. A malformed block with
+    /// delta-encoded keys decodes to a block without entries.
     pub fn decode(data: &[u8]) -> Self {
+        if is_delta_encoded(data) {
+            let (data, offsets) = delta::decode(data).unwrap_or_default();
+            return Self {
+                data: BlockData::Shared(Bytes::from(data)),
+                offsets,
+                pool: None,
+                delta_keys: true,
+                seqs: false,
+            };
+        }
+        let offsets = Self::decode_offsets(data);
+        let seqs = has_seqs(data);
+        let data = BlockData::Shared(Bytes::copy_from_slice(
+            &data[0..data.len() - 2 - offsets.len() * 2],
+        ));
+        Self {
+            data,
+            offsets,
+            pool: None,
+            delta_keys: false,
+            seqs,
+        }
+    }
+
+    /// Decode from the data layout like `decode`, keeping the data section in place in `data`,
+    /// which is handed back to `pool` when the block is dropped. A delta-encoded block is rebuilt
+    /// in a new buffer, so `data` goes back to `pool` right away.
+    pub(crate) fn decode_pooled(mut data: Vec<u8>, pool: Arc<BlockPool>) -> Self {
+        if is_delta_encoded(&data) {
+            let block = Self::decode(&data);
+            pool.put(data);
+            return block;
+        }
+        let offsets = Self::decode_offsets(&data);
+        let seqs = has_seqs(&data);
+        data.truncate(data.len() - 2 - offsets.len() * 2);
+        Self {
+            data: BlockData::Pooled(data),
+            offsets,
+            pool: Some(pool),
+            delta_keys: false,
+            seqs,
+        }
+    }
+
+    fn decode_offsets(data: &[u8]) -> Vec<u16> {
         let size = data.len();
-        let num_of_elements = (data[size - 2] as u16) << 8 | data[size - 1] as u16;
+        let num_of_elements = num_of_elements(data);
 
         let mut offsets: Vec<u16> = Vec::with_capacity(num_of_elements as usize);
         for i in 0..num_of_elements {
@@ -47,9 +354,91 @@ impl Block {
                 | data[size - 3 - (i as usize) * 2] as u16;
             offsets.push(offset);
         }
+        offsets
+    }
 
-        let data = data[0..size - 2 - (num_of_elements as usize) * 2].to_vec();
-        Self { data, offsets }
+    /// Decode from the data layout like `decode`, but check that every entry lies within the data
+    /// section first, so a corrupted block is reported as an error instead of panicking later.
+    pub fn try_decode(data: &[u8]) -> Result<Self> {
+        if data.len() < 2 {
+            bail!("block of {} bytes is too small", data.len());
+        }
+        if is_delta_encoded(data) {
+            let (data, offsets) = delta::decode(data)?;
+            return Self::check_entries(Self {
+                data: BlockData::Shared(Bytes::from(data)),
+                offsets,
+                pool: None,
+                delta_keys: true,
+                seqs: false,
+            });
+        }
+        Self::check_offsets_len(data)?;
+        Self::check_entries(Self::decode(data))
+    }
+
+    /// Decode from the data layout like `decode_pooled`, checking every entry like `try_decode`.
+    /// `data` is handed back to `pool` whether it decodes or not.
+    pub(crate) fn try_decode_pooled(data: Vec<u8>, pool: Arc<BlockPool>) -> Result<Self> {
+        if data.len() < 2 || is_delta_encoded(&data) {
+            let block = Self::try_decode(&data);
+            pool.put(data);
+            return block;
+        }
+        if let Err(err) = Self::check_offsets_len(&data) {
+            pool.put(data);
+            return Err(err);
+        }
+        // a block failing the check is dropped, which hands `data` back to `pool`
+        Self::check_entries(Self::decode_pooled(data, pool))
+    }
+
+    /// Check that the offset section of the encoded block `data` fits in it.
+    fn check_offsets_len(data: &[u8]) -> Result<()> {
+        let num_of_elements = num_of_elements(data);
+        let data_len = data.len() as isize - 2 - num_of_elements as isize * 2;
+        if data_len < 0 {
+            bail!(
+                "block of {} bytes is too small for {} entries",
+                data.len(),
+                num_of_elements
+            );
+        }
+        Ok(())
+    }
+
+    /// Check that every entry of `block` lies within its data section, see `try_decode`.
+    fn check_entries(block: Self) -> Result<Self> {
+        let read_u16 = |pos: usize| -> Result<u16> {
+            match block.data.get(pos..pos + 2) {
+                Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+                None => bail!("entry out of range at {}", pos),
+            }
+        };
+        for (idx, &offset) in block.offsets.iter().enumerate() {
+            if offset as usize >= block.data.len() {
+                bail!(
+                    "offset {} of entry {} is beyond the data section of {} bytes",
+                    offset,
+                    idx,
+                    block.data.len()
+                );
+            }
+            let key_offset = offset as usize + 2;
+            let val_offset = key_offset + read_u16(offset as usize)? as usize;
+            let val_len = read_u16(val_offset)?;
+            let value_range =
+                val_offset + 2..val_offset + 2 + (val_len & !COMPRESSED_VALUE_FLAG) as usize;
+            let seq_len = if block.seqs { SEQ_LEN } else { 0 };
+            if block.data.len() < value_range.end + seq_len {
+                bail!("value of entry {} out of range", idx);
+            }
+            let value = &block.data[value_range];
+            if val_len & COMPRESSED_VALUE_FLAG != 0 && try_decompress(value).is_none() {
+                bail!("value of entry {} is not validly compressed", idx);
+            }
+        }
+        Ok(block)
     }
 }
 
@@ -1,20 +1,29 @@
-use super::Block;
+use super::compress::{compress, try_decompress, COMPRESSED_VALUE_FLAG};
+use super::delta::{self, MAX_KEY_WIDTH};
+use bytes::Bytes;
+
+use super::{Block, BlockData, SEQ_FLAG, SEQ_LEN};
 
 const KEY_LEN_SIZE: usize = 2;
 const VAL_LEN_SIZE: usize = 2;
 const OFFSET_SIZE: usize = 2;
 
-struct Entry {
-    key: Vec<u8>,
-    val: Vec<u8>,
-    total_size: u16,
-}
-
 /// Builds a block.
 pub struct BlockBuilder {
-    kvs: Vec<Entry>,
+    /// The encoded entries, each laid out as | key_len (u16) | key | value_len (u16) | value |,
+    /// followed by | seq (u64) | if the block has sequence numbers.
+    kvs: Vec<Vec<u8>>,
     current_size: usize,
     target_size: usize,
+    /// Whether to compress the values of the entries, keys are always stored plain.
+    compress_values: bool,
+    /// The size of the entries with their keys delta-encoded, `None` unless the keys are
+    /// delta-encoded, see `set_delta_keys`.
+    delta_size: Option<usize>,
+    /// How many more bytes the compressed values take once decompressed.
+    decompressed_extra: usize,
+    /// Whether each entry ends with a sequence number, see `set_seqs`.
+    seqs: bool,
 }
 
 impl BlockBuilder {
@@ -24,25 +33,193 @@ impl BlockBuilder {
             kvs: Vec::new(),
             current_size: 0,
             target_size: block_size,
+            compress_values: false,
+            delta_size: None,
+            decompressed_extra: 0,
+            seqs: false,
+        }
+    }
+
+    /// Creates a new block builder with room for `expected_entries` entries, so that adding that
+    /// many entries does not grow the list of entries along the way, e.g. for workloads with a
+    /// predictable number of entries per block. The output is the same as with `new`.
+    pub fn with_capacity(block_size: usize, expected_entries: usize) -> Self {
+        Self {
+            kvs: Vec::with_capacity(expected_entries),
+            ..Self::new(block_size)
+        }
+    }
+
+    /// Returns how many entries the builder holds without growing its list of entries.
+    #[cfg(test)]
+    pub(crate) fn entries_capacity(&self) -> usize {
+        self.kvs.capacity()
+    }
+
+    /// Compress the value of each entry added from now on, off by default.
+    /// Keys and length prefixes stay plain, so seeking never decompresses anything; a value is
+    /// only stored compressed if that makes it smaller. Values are compressed as they are added,
+    /// so that the size of the block accounts for the compressed values.
+    pub fn set_compress_values(&mut self, compress_values: bool) {
+        self.compress_values = compress_values;
+    }
+
+    /// Delta-encode the keys of the block, off by default, see `delta::encode`. This only pays off
+    /// for keys like big-endian counters, e.g. of time-series or sequences, so it is only done if
+    /// all the keys have the same width of at most 8 bytes and are strictly increasing: once a key
+    /// breaks that, the block goes back to storing its keys plain. The size of the block accounts
+    /// for the delta-encoded keys, so that the block holds more entries. Must be set on an empty
+    /// block.
+    pub fn set_delta_keys(&mut self, delta_keys: bool) {
+        assert!(self.is_empty(), "delta keys set on a non-empty block");
+        assert!(
+            !delta_keys || !self.seqs,
+            "delta keys set on a block with sequence numbers"
+        );
+        self.delta_size = delta_keys.then_some(0);
+    }
+
+    /// Store a sequence number (u64) after the value of each entry, off by default, e.g. a
+    /// monotonic version of the key for conflict detection, lighter than MVCC keys as the key
+    /// stays as is. Entries are then added with `add_with_seq`, and the sequence numbers read back
+    /// with `BlockIterator::seq`. Cannot be combined with delta keys. Must be set on an empty
+    /// block.
+    pub fn set_seqs(&mut self, seqs: bool) {
+        assert!(self.is_empty(), "sequence numbers set on a non-empty block");
+        assert!(
+            !seqs || self.delta_size.is_none(),
+            "sequence numbers set on a block with delta keys"
+        );
+        self.seqs = seqs;
+    }
+
+    /// Get the length of `key` once delta-encoded as the next entry, `None` if the keys cannot be
+    /// delta-encoded with it.
+    fn delta_key_len(&self, key: &[u8]) -> Option<usize> {
+        self.delta_size?;
+        let Some(prev_entry) = self.kvs.last() else {
+            return (1..=MAX_KEY_WIDTH).contains(&key.len()).then_some(key.len());
+        };
+        let prev_key_len = u16::from_be_bytes([prev_entry[0], prev_entry[1]]) as usize;
+        let prev_key = &prev_entry[KEY_LEN_SIZE..KEY_LEN_SIZE + prev_key_len];
+        if prev_key.len() != key.len() || key <= prev_key {
+            return None;
         }
+        Some(delta::key_part_len(self.kvs.len(), key, prev_key))
     }
 
-    /// Adds a key-value pair to the block. Returns false when the block is full.
+    /// Check whether an entry with `key` that takes `entry_len` bytes plain fits in the block, and
+    /// account for it if so.
+    fn reserve(&mut self, key: &[u8], entry_len: usize) -> bool {
+        let delta_size = self
+            .delta_size
+            .zip(self.delta_key_len(key))
+            .map(|(size, key_len)| {
+                size + entry_len - KEY_LEN_SIZE - key.len() + key_len + OFFSET_SIZE
+            });
+        let size = delta_size.unwrap_or(self.current_size + entry_len + OFFSET_SIZE);
+        if size > self.target_size {
+            return false;
+        }
+        self.delta_size = delta_size;
+        self.current_size += entry_len + OFFSET_SIZE;
+        true
+    }
+
+    /// Adds a key-value pair to the block. Returns false when the block is full, or when the value
+    /// is too large to be stored, of `COMPRESSED_VALUE_FLAG` (32 KiB) bytes or more.
+    /// A block with sequence numbers takes its entries with `add_with_seq` instead.
     #[must_use]
     pub fn add(&mut self, key: &[u8], value: &[u8]) -> bool {
-        let pair_size = KEY_LEN_SIZE + VAL_LEN_SIZE + key.len() + value.len();
-        if self.current_size + pair_size + OFFSET_SIZE > self.target_size {
+        assert!(
+            !self.seqs,
+            "entries of a block with sequence numbers need one"
+        );
+        self.add_entry(key, value, None)
+    }
+
+    /// Adds a key-value pair with its sequence number to a block with sequence numbers, see
+    /// `set_seqs`. Returns false when the block is full or the value too large, like `add`.
+    #[must_use]
+    pub fn add_with_seq(&mut self, key: &[u8], value: &[u8], seq: u64) -> bool {
+        assert!(self.seqs, "the block has no sequence numbers");
+        self.add_entry(key, value, Some(seq))
+    }
+
+    fn add_entry(&mut self, key: &[u8], value: &[u8], seq: Option<u64>) -> bool {
+        // the top bit of the stored value length flags a compressed value
+        if value.len() >= COMPRESSED_VALUE_FLAG as usize {
             return false;
         }
-
-        let entry = Entry {
-            key: key.to_vec(),
-            val: value.to_vec(),
-            total_size: pair_size as u16,
+        let compressed_value = if self.compress_values {
+            Some(compress(value)).filter(|compressed| compressed.len() < value.len())
+        } else {
+            None
         };
+        let mut val_len = compressed_value.as_ref().map_or(value.len(), Vec::len) as u16;
+        if compressed_value.is_some() {
+            val_len |= COMPRESSED_VALUE_FLAG;
+        }
+        let plain_len = value.len();
+        let value = compressed_value.as_deref().unwrap_or(value);
+        let pair_size =
+            KEY_LEN_SIZE + VAL_LEN_SIZE + key.len() + value.len() + seq.map_or(0, |_| SEQ_LEN);
+        if !self.reserve(key, pair_size) {
+            return false;
+        }
+        self.decompressed_extra += plain_len - value.len();
 
+        let mut entry = Vec::with_capacity(pair_size);
+        entry.extend_from_slice(&(key.len() as u16).to_be_bytes());
+        entry.extend_from_slice(key);
+        entry.extend_from_slice(&val_len.to_be_bytes());
+        entry.extend_from_slice(value);
+        if let Some(seq) = seq {
+            entry.extend_from_slice(&seq.to_be_bytes());
+        }
         self.kvs.push(entry);
-        self.current_size += pair_size + OFFSET_SIZE;
+        true
+    }
+
+    /// Appends an already encoded `| key_len | key | value_len | value |` entry of `entry_len`
+    /// bytes, e.g. one taken from another block with `Block::raw_entry`, without re-encoding it.
+    /// In a block with sequence numbers, the entry must end with its sequence number.
+    /// The caller must keep the entries sorted. Returns false when the block is full, or when the
+    /// entry is malformed: `entry_bytes` is not `entry_len` bytes long, the lengths in it do not
+    /// add up to `entry_len`, or the value is flagged as compressed but is not validly compressed.
+    #[must_use]
+    pub fn append_raw(&mut self, entry_bytes: &[u8], entry_len: u16) -> bool {
+        let entry_len = entry_len as usize;
+        if entry_bytes.len() != entry_len || entry_len < KEY_LEN_SIZE {
+            return false;
+        }
+        let key_len = u16::from_be_bytes([entry_bytes[0], entry_bytes[1]]) as usize;
+        if entry_len < KEY_LEN_SIZE + key_len + VAL_LEN_SIZE {
+            return false;
+        }
+        let val_len_offset = KEY_LEN_SIZE + key_len;
+        let val_len =
+            u16::from_be_bytes([entry_bytes[val_len_offset], entry_bytes[val_len_offset + 1]]);
+        let compressed = val_len & COMPRESSED_VALUE_FLAG != 0;
+        let val_len = val_len & !COMPRESSED_VALUE_FLAG;
+        let seq_len = if self.seqs { SEQ_LEN } else { 0 };
+        if entry_len != KEY_LEN_SIZE + key_len + VAL_LEN_SIZE + val_len as usize + seq_len {
+            return false;
+        }
+        let decompressed_extra = if compressed {
+            let value = &entry_bytes[val_len_offset + VAL_LEN_SIZE..entry_len - seq_len];
+            let Some(decompressed) = try_decompress(value) else {
+                return false;
+            };
+            decompressed.len() - value.len()
+        } else {
+            0
+        };
+        if !self.reserve(&entry_bytes[KEY_LEN_SIZE..val_len_offset], entry_len) {
+            return false;
+        }
+        self.decompressed_extra += decompressed_extra;
+        self.kvs.push(entry_bytes.to_vec());
         true
     }
 
@@ -51,24 +228,65 @@ impl BlockBuilder {
         self.kvs.is_empty()
     }
 
+    /// Get the encoded size of the block so far.
     pub fn size(&self) -> usize {
-        self.current_size + 2 // for num of offsets
+        match self.delta_size {
+            Some(delta_size) if !self.is_empty() => delta_size + 3, // for the key width too
+            _ => self.current_size + 2,                             // for num of offsets
+        }
+    }
+
+    /// Get the number of entries added to the block.
+    pub fn num_of_entries(&self) -> usize {
+        self.kvs.len()
+    }
+
+    /// Get the encoded size of the block so far with every value stored plain, like
+    /// `Block::uncompressed_len`.
+    pub fn uncompressed_size(&self) -> usize {
+        self.size() + self.decompressed_extra
+    }
+
+    /// Finalize the block and append its encoding to `buf`, like `build` then `Block::encode`
+    /// would, but without building the block in between.
+    pub fn encode_into(self, buf: &mut Vec<u8>) {
+        buf.reserve(self.size());
+        if self.delta_size.is_some() && !self.kvs.is_empty() {
+            delta::encode_into(self.kvs.iter().map(Vec::as_slice), buf);
+            return;
+        }
+        let data_start = buf.len();
+        for kv in &self.kvs {
+            buf.extend_from_slice(kv);
+        }
+        // the offsets are stored in reverse order
+        let mut offset = buf.len() - data_start;
+        for kv in self.kvs.iter().rev() {
+            offset -= kv.len();
+            buf.extend_from_slice(&(offset as u16).to_be_bytes());
+        }
+        let mut num_of_elements = self.kvs.len() as u16;
+        if self.seqs {
+            num_of_elements |= SEQ_FLAG;
+        }
+        buf.extend_from_slice(&num_of_elements.to_be_bytes());
     }
 
     /// Finalize the block.
     pub fn build(self) -> Block {
         let mut offsets = vec![0u16; self.kvs.len()];
         let mut data: Vec<u8> = Vec::with_capacity(self.current_size - 2 * self.kvs.len());
-        let mut cur = 0u16;
         for (i, kv) in self.kvs.iter().enumerate() {
-            offsets[i] = cur;
-            cur += kv.total_size;
-            data.extend_from_slice(&(kv.key.len() as u16).to_be_bytes());
-            data.extend_from_slice(kv.key.as_slice());
-            data.extend_from_slice(&(kv.val.len() as u16).to_be_bytes());
-            data.extend_from_slice(kv.val.as_slice());
+            offsets[i] = data.len() as u16;
+            data.extend_from_slice(kv);
         }
 
-        Block { data, offsets }
+        Block {
+            data: BlockData::Shared(Bytes::from(data)),
+            offsets,
+            pool: None,
+            delta_keys: self.delta_size.is_some() && !self.kvs.is_empty(),
+            seqs: self.seqs,
+        }
     }
 }
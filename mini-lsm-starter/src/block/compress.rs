@@ -0,0 +1,84 @@
+/// Set in the `value_len` of an entry whose value is stored compressed.
+/// Values are bounded by the block size, so the top bit of the length is otherwise always clear.
+pub(super) const COMPRESSED_VALUE_FLAG: u16 = 0x8000;
+
+const MIN_MATCH: usize = 4;
+const MAX_MATCH: usize = MIN_MATCH + 0x7f;
+const MAX_LITERALS: usize = 0x80;
+const HASH_BITS: u32 = 12;
+
+/// Compress `data` with a small LZ77 scheme. The output is a sequence of tokens:
+/// * `0x00..=0x7f`: a literal run of `token + 1` bytes, followed by the bytes.
+/// * `0x80..=0xff`: a match of `(token & 0x7f) + 4` bytes, followed by the distance back to the
+///   match (u16), which may overlap the bytes being copied.
+pub(super) fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let mut literal_start = 0;
+    let mut pos = 0;
+    while pos + MIN_MATCH <= data.len() {
+        let word = u32::from_le_bytes(data[pos..pos + MIN_MATCH].try_into().unwrap());
+        let hash = (word.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize;
+        let candidate = std::mem::replace(&mut table[hash], pos);
+        if candidate == usize::MAX
+            || pos - candidate > u16::MAX as usize
+            || data[candidate..candidate + MIN_MATCH] != data[pos..pos + MIN_MATCH]
+        {
+            pos += 1;
+            continue;
+        }
+        let mut len = MIN_MATCH;
+        while len < MAX_MATCH && pos + len < data.len() && data[candidate + len] == data[pos + len]
+        {
+            len += 1;
+        }
+        push_literals(&mut out, &data[literal_start..pos]);
+        out.push(0x80 | (len - MIN_MATCH) as u8);
+        out.extend_from_slice(&((pos - candidate) as u16).to_be_bytes());
+        pos += len;
+        literal_start = pos;
+    }
+    push_literals(&mut out, &data[literal_start..]);
+    out
+}
+
+fn push_literals(out: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(MAX_LITERALS) {
+        out.push((chunk.len() - 1) as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+/// Decompress data produced by `compress`, for the values of a decoded `Block`, which are trusted
+/// like its offsets. Bytes that have not been checked, e.g. entries read from the disk on their
+/// own or handed in by a caller, go through `try_decompress` instead.
+pub(super) fn decompress(data: &[u8]) -> Vec<u8> {
+    try_decompress(data).expect("invalid compressed value")
+}
+
+/// Decompress data produced by `compress`, or `None` if `data` is not a valid token sequence.
+pub(super) fn try_decompress(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len() * 2);
+    let mut pos = 0;
+    while pos < data.len() {
+        let token = data[pos] as usize;
+        pos += 1;
+        if token < 0x80 {
+            out.extend_from_slice(data.get(pos..pos + token + 1)?);
+            pos += token + 1;
+        } else {
+            let len = (token & 0x7f) + MIN_MATCH;
+            let distance = data.get(pos..pos + 2)?;
+            let distance = u16::from_be_bytes([distance[0], distance[1]]) as usize;
+            pos += 2;
+            if distance == 0 || distance > out.len() {
+                return None;
+            }
+            let start = out.len() - distance;
+            for idx in start..start + len {
+                out.push(out[idx]);
+            }
+        }
+    }
+    Some(out)
+}
@@ -0,0 +1,185 @@
+use anyhow::{bail, Result};
+
+use super::Block;
+
+/// Set in the `num_of_elements` of a block whose keys are delta-encoded.
+/// Blocks are bounded by the block size, so the top bit of the count is otherwise always clear.
+pub(super) const DELTA_KEYS_FLAG: u16 = 0x8000;
+
+/// Every how many entries a delta-encoded block stores the key in full, so that any key can be
+/// rebuilt from at most `RESTART_INTERVAL` entries instead of from the start of the block.
+pub(super) const RESTART_INTERVAL: usize = 16;
+
+/// The widest key that can be delta-encoded, as keys are read as big-endian u64s.
+pub(super) const MAX_KEY_WIDTH: usize = 8;
+
+/// Read a key of at most `MAX_KEY_WIDTH` bytes as a big-endian integer.
+pub(super) fn key_to_u64(key: &[u8]) -> u64 {
+    key.iter().fold(0, |acc, &byte| acc << 8 | byte as u64)
+}
+
+pub(super) fn varint_len(mut value: u64) -> usize {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Decode a varint from the start of `buf`, returning it along with its length.
+fn get_varint(buf: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (idx, &byte) in buf.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (idx * 7);
+        if byte & 0x80 == 0 {
+            return Some((value, idx + 1));
+        }
+    }
+    None
+}
+
+/// Get the length of the key of entry `idx` once delta-encoded, `prev_key` being the key of the
+/// entry before it. The keys must have the same width and be increasing.
+pub(super) fn key_part_len(idx: usize, key: &[u8], prev_key: &[u8]) -> usize {
+    if idx % RESTART_INTERVAL == 0 {
+        key.len()
+    } else {
+        varint_len(key_to_u64(key) - key_to_u64(prev_key))
+    }
+}
+
+/// Get the length of the data section of `block` once delta-encoded.
+fn data_len(block: &Block) -> usize {
+    (0..block.num_of_entries())
+        .map(|idx| {
+            let key = block.key_at(idx);
+            let prev_key = if idx == 0 { key } else { block.key_at(idx - 1) };
+            block.raw_entry(idx).len() - 2 - key.len() + key_part_len(idx, key, prev_key)
+        })
+        .sum()
+}
+
+/// Get the length of `block` once delta-encoded, see `encode`.
+pub(super) fn encoded_len(block: &Block) -> usize {
+    data_len(block) + block.num_of_entries() * 2 + 3
+}
+
+/// Get the key of an entry laid out as | key_len (u16) | key | value_len (u16) | value |.
+fn entry_key(entry: &[u8]) -> &[u8] {
+    &entry[2..2 + u16::from_be_bytes([entry[0], entry[1]]) as usize]
+}
+
+/// Encode `block`, whose keys must all have the same width of at most `MAX_KEY_WIDTH` bytes and
+/// be strictly increasing, with its keys delta-encoded:
+/// ---------------------------------------------------------------------------------------------
+/// | Entry #1 | ... | Entry #N | Offset #1 | ... | Offset #N | key_width (u8) | num_of_elements |
+/// ---------------------------------------------------------------------------------------------
+/// where `num_of_elements` has `DELTA_KEYS_FLAG` set, and each entry is laid out as
+/// | key or key delta | value_len (u16) | value |. Every `RESTART_INTERVAL`-th entry, starting
+/// with the first one, holds its key in full, the others the varint difference between their key
+/// and the key before it, both keys read as big-endian integers.
+pub(super) fn encode(block: &Block) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(encoded_len(block));
+    encode_into(
+        (0..block.num_of_entries()).map(|idx| block.raw_entry(idx)),
+        &mut buf,
+    );
+    buf
+}
+
+/// Append the encoding of the plain `entries` to `buf`, like `encode`.
+pub(super) fn encode_into<'a>(entries: impl Iterator<Item = &'a [u8]>, buf: &mut Vec<u8>) {
+    let start = buf.len();
+    let mut offsets = Vec::new();
+    let mut prev_key: &[u8] = &[];
+    for (idx, entry) in entries.enumerate() {
+        offsets.push((buf.len() - start) as u16);
+        let key = entry_key(entry);
+        if idx % RESTART_INTERVAL == 0 {
+            buf.extend_from_slice(key);
+        } else {
+            put_varint(buf, key_to_u64(key) - key_to_u64(prev_key));
+        }
+        buf.extend_from_slice(&entry[2 + key.len()..]);
+        prev_key = key;
+    }
+    for offset in offsets.iter().rev() {
+        buf.extend_from_slice(&offset.to_be_bytes());
+    }
+    buf.push(prev_key.len() as u8);
+    buf.extend_from_slice(&(offsets.len() as u16 | DELTA_KEYS_FLAG).to_be_bytes());
+}
+
+/// Decode a block written by `encode` back to the data section and the offsets of a plain block,
+/// rebuilding each key from the closest restart before it. Fails if the block is malformed.
+pub(super) fn decode(data: &[u8]) -> Result<(Vec<u8>, Vec<u16>)> {
+    if data.len() < 3 {
+        bail!("delta-encoded block of {} bytes is too small", data.len());
+    }
+    let num_of_elements = (u16::from_be_bytes([data[data.len() - 2], data[data.len() - 1]])
+        & !DELTA_KEYS_FLAG) as usize;
+    if num_of_elements == 0 {
+        bail!("delta-encoded block without any entry");
+    }
+    let key_width = data[data.len() - 3] as usize;
+    if !(1..=MAX_KEY_WIDTH).contains(&key_width) {
+        bail!("invalid key width {} of a delta-encoded block", key_width);
+    }
+    let Some(data_len) = data.len().checked_sub(3 + num_of_elements * 2) else {
+        bail!(
+            "block of {} bytes is too small for {} entries",
+            data.len(),
+            num_of_elements
+        );
+    };
+    // the offsets are stored in reverse order
+    let offset_at = |idx: usize| {
+        let pos = data_len + (num_of_elements - 1 - idx) * 2;
+        u16::from_be_bytes([data[pos], data[pos + 1]]) as usize
+    };
+    let max_key = u64::MAX >> (64 - 8 * key_width);
+    let mut expanded = Vec::with_capacity(data_len + num_of_elements * (2 + key_width));
+    let mut offsets = Vec::with_capacity(num_of_elements);
+    let mut key = 0u64;
+    for idx in 0..num_of_elements {
+        let start = offset_at(idx);
+        let end = if idx + 1 < num_of_elements {
+            offset_at(idx + 1)
+        } else {
+            data_len
+        };
+        let Some(entry) = data[..data_len].get(start..end) else {
+            bail!("entry {} out of range", idx);
+        };
+        let key_part_len = if idx % RESTART_INTERVAL == 0 {
+            let Some(full_key) = entry.get(..key_width) else {
+                bail!("key of entry {} out of range", idx);
+            };
+            key = key_to_u64(full_key);
+            key_width
+        } else {
+            let Some((delta, len)) = get_varint(entry) else {
+                bail!("key delta of entry {} out of range", idx);
+            };
+            match key.checked_add(delta) {
+                Some(next) if delta > 0 && next <= max_key => key = next,
+                _ => bail!("invalid key delta {} of entry {}", delta, idx),
+            }
+            len
+        };
+        offsets.push(expanded.len() as u16);
+        expanded.extend_from_slice(&(key_width as u16).to_be_bytes());
+        expanded.extend_from_slice(&key.to_be_bytes()[8 - key_width..]);
+        expanded.extend_from_slice(&entry[key_part_len..]);
+    }
+    Ok((expanded, offsets))
+}
@@ -1,6 +1,12 @@
+use std::cell::OnceCell;
+use std::ops::Range;
 use std::sync::Arc;
 
-use super::Block;
+use bytes::Bytes;
+
+use super::compress::{decompress, COMPRESSED_VALUE_FLAG};
+use super::{Block, SEQ_LEN};
+use crate::key::{self, TS_LEN};
 
 /// Iterates on a block.
 pub struct BlockIterator {
@@ -8,8 +14,18 @@ pub struct BlockIterator {
     block: Arc<Block>,
     /// The current key, empty represents the iterator is invalid
     key: Vec<u8>,
-    /// The corresponding value, can be empty
+    /// The corresponding value as stored in the block, can be empty. Left empty in lazy value mode
     value: Vec<u8>,
+    /// The range of the stored value in the data section of the block
+    value_range: Range<usize>,
+    /// Whether `value` is read from the block on demand instead of being copied on every move
+    lazy_value: bool,
+    /// Whether `value` is compressed
+    value_compressed: bool,
+    /// Whether the keys are MVCC keys, so that `timestamp` decodes their suffix
+    mvcc_keys: bool,
+    /// The decompressed value, only filled in the first time `value` is called
+    decompressed_value: OnceCell<Vec<u8>>,
     /// Current index of the key-value pair, should be in range of [0, num_of_elements)
     idx: usize,
 }
@@ -20,84 +36,27 @@ impl BlockIterator {
             block,
             key: Vec::new(),
             value: Vec::new(),
+            value_range: 0..0,
+            lazy_value: false,
+            value_compressed: false,
+            mvcc_keys: false,
+            decompressed_value: OnceCell::new(),
             idx: 0,
         }
     }
 
     /// Creates a block iterator and seek to the first entry.
     pub fn create_and_seek_to_first(block: Arc<Block>) -> Self {
-        let offset = block.offsets[0];
-        let key_len =
-            (block.data[offset as usize] as u16) << 8 | block.data[1 + offset as usize] as u16;
-        let key = block.data[offset as usize + 2..offset as usize + 2 + key_len as usize].to_vec();
-        let val_offset = offset + 2 + key_len;
-        let val_len = (block.data[val_offset as usize] as u16) << 8
-            | block.data[1 + val_offset as usize] as u16;
-        let value = block.data[val_offset as usize + 2..val_offset as usize + 2 + val_len as usize]
-            .to_vec();
-        Self {
-            block,
-            key,
-            value,
-            idx: 0,
-        }
+        let mut iter = Self::new(block);
+        iter.seek_to_first();
+        iter
     }
 
     /// Creates a block iterator and seek to the first key that >= `key`.
     pub fn create_and_seek_to_key(block: Arc<Block>, key: &[u8]) -> Self {
-        let len = block.offsets.len();
-        let mut low = 0;
-        let mut high = len;
-        while low < high {
-            let mid = (low + high) / 2;
-            let offset = block.offsets[mid];
-            let key_len =
-                (block.data[offset as usize] as u16) << 8 | block.data[1 + offset as usize] as u16;
-            let mid_key = &block.data[offset as usize + 2..offset as usize + 2 + key_len as usize];
-            if mid_key < key {
-                low = mid + 1;
-            } else if mid_key == key {
-                let val_offset = offset + 2 + key_len;
-                let val_len = (block.data[val_offset as usize] as u16) << 8
-                    | block.data[1 + val_offset as usize] as u16;
-                let value = block.data
-                    [val_offset as usize + 2..val_offset as usize + 2 + val_len as usize]
-                    .to_vec();
-                let mid_key = mid_key.to_vec();
-                return Self {
-                    block,
-                    key: mid_key,
-                    value,
-                    idx: mid,
-                };
-            } else {
-                high = mid;
-            }
-        }
-
-        if low == block.offsets.len() {
-            return Self {
-                block,
-                key: Vec::new(),
-                value: Vec::new(),
-                idx: len,
-            };
-        }
-        let offset = block.offsets[low];
-        let key_len =
-            (block.data[offset as usize] as u16) << 8 | block.data[1 + offset as usize] as u16;
-        let key = block.data[offset as usize + 2..offset as usize + 2 + key_len as usize].to_vec();
-        let val_offset = offset + 2 + key_len;
-        let val_len = (block.data[val_offset as usize] as u16) << 8
-            | block.data[1 + val_offset as usize] as u16;
-        let value = block.data[val_offset as usize + 2..val_offset as usize + 2 + val_len as usize]
-            .to_vec();
-        Self {
-            block,
-            key,
-            value,
-            idx: low,
-        }
+        let mut iter = Self::new(block);
+        iter.seek_to_key(key);
+        iter
     }
 
     /// Returns the key of the current entry.
@@ -105,9 +64,85 @@ impl BlockIterator {
         &self.key
     }
 
+    /// Returns the timestamp of the current entry, whose key is an MVCC key (see `crate::key`).
+    /// Blocks do not record whether their keys are MVCC keys, so this returns 0 unless enabled
+    /// with `set_mvcc_keys`, as well as for keys shorter than `TS_LEN` and when the iterator is
+    /// invalid.
+    pub fn timestamp(&self) -> u64 {
+        if !self.mvcc_keys || self.key.len() < TS_LEN {
+            return 0;
+        }
+        key::split_ts(&self.key).1
+    }
+
+    /// Returns the sequence number of the current entry, see `BlockBuilder::set_seqs`, or `None`
+    /// if the block has no sequence numbers or the iterator is invalid.
+    pub fn seq(&self) -> Option<u64> {
+        if !self.block.seqs || !self.is_valid() {
+            return None;
+        }
+        let seq = &self.block.data[self.value_range.end..self.value_range.end + SEQ_LEN];
+        Some(u64::from_be_bytes(seq.try_into().unwrap()))
+    }
+
+    /// Set whether moving the iterator skips copying the value, off by default.
+    /// In lazy value mode, `value` reads the value from the block when it is called, so a scan that
+    /// filters on keys only pays for the values it reads. Takes effect from the next move.
+    pub fn set_lazy_value(&mut self, lazy_value: bool) {
+        self.lazy_value = lazy_value;
+    }
+
+    /// Set whether the keys of the block are MVCC keys, off by default, see `timestamp`.
+    pub fn set_mvcc_keys(&mut self, mvcc_keys: bool) {
+        self.mvcc_keys = mvcc_keys;
+    }
+
     /// Returns the value of the current entry.
+    /// A compressed value is decompressed on the first call for each entry.
     pub fn value(&self) -> &[u8] {
-        &self.value
+        // an empty `value` is either an empty value or a value left in the block, the range covers both
+        let value = if self.value.is_empty() {
+            &self.block.data[self.value_range.clone()]
+        } else {
+            &self.value
+        };
+        if !self.value_compressed {
+            return value;
+        }
+        self.decompressed_value.get_or_init(|| decompress(value))
+    }
+
+    /// Returns the key and the value of the current entry as `Bytes` sharing the buffer of the
+    /// block, so that they can be kept across moves of the iterator without copying them. Only a
+    /// compressed value, or an entry of a block read through a `BlockPool`, is copied.
+    /// The iterator must be valid.
+    pub fn current_entry(&self) -> (Bytes, Bytes) {
+        let key_start = self.block.offsets[self.idx] as usize + 2;
+        let key = self.block.data_slice(key_start..key_start + self.key.len());
+        let value = if self.value_compressed {
+            Bytes::copy_from_slice(self.value())
+        } else {
+            self.block.data_slice(self.value_range.clone())
+        };
+        (key, value)
+    }
+
+    /// Returns the length of the value of the current entry.
+    /// A plain value's length is taken from its length prefix without touching the value, so this
+    /// is free in lazy value mode. A compressed value's prefix holds the compressed length, so its
+    /// length is only known once it is decompressed, as `value` would.
+    pub fn value_len(&self) -> usize {
+        if self.value_compressed {
+            self.value().len()
+        } else {
+            self.value_range.len()
+        }
+    }
+
+    /// Returns true if the value of the current entry has been decompressed.
+    #[cfg(test)]
+    pub(crate) fn value_decompressed(&self) -> bool {
+        self.decompressed_value.get().is_some()
     }
 
     /// Returns true if the iterator is valid.
@@ -116,98 +151,143 @@ impl BlockIterator {
         !self.key.is_empty()
     }
 
+    /// Turn the iterator into a `std::iter::Iterator` of owned `(key, value)` pairs, starting from
+    /// the current entry, e.g. to `collect` a block. Moving within a block never fails, so the
+    /// items are not wrapped in a `Result`.
+    pub fn into_owned_iter(self) -> OwnedBlockIter {
+        OwnedBlockIter { iter: self }
+    }
+
     /// Seeks to the first key in the block.
     pub fn seek_to_first(&mut self) {
-        let offset = self.block.offsets[0];
-        let key_len = (self.block.data[offset as usize] as u16) << 8
-            | self.block.data[1 + offset as usize] as u16;
-        let key =
-            self.block.data[offset as usize + 2..offset as usize + 2 + key_len as usize].to_vec();
-        let val_offset = offset + 2 + key_len;
-        let val_len = (self.block.data[val_offset as usize] as u16) << 8
-            | self.block.data[1 + val_offset as usize] as u16;
-        let value = self.block.data
-            [val_offset as usize + 2..val_offset as usize + 2 + val_len as usize]
-            .to_vec();
-        self.key = key;
-        self.value = value;
-        self.idx = 0;
+        self.seek_to_idx(0);
     }
 
     /// Move to the next key in the block.
     pub fn next(&mut self) {
-        self.idx += 1;
-        if self.idx == self.block.offsets.len() {
-            self.key = Vec::new();
-            self.value = Vec::new();
-            return;
-        }
-        let offset = self.block.offsets[self.idx];
-        let key_len = (self.block.data[offset as usize] as u16) << 8
-            | self.block.data[1 + offset as usize] as u16;
-        let key =
-            self.block.data[offset as usize + 2..offset as usize + 2 + key_len as usize].to_vec();
-        let val_offset = offset + 2 + key_len;
-        let val_len = (self.block.data[val_offset as usize] as u16) << 8
-            | self.block.data[1 + val_offset as usize] as u16;
-        let value = self.block.data
-            [val_offset as usize + 2..val_offset as usize + 2 + val_len as usize]
-            .to_vec();
-        self.key = key;
-        self.value = value;
+        self.seek_to_idx(self.idx + 1);
     }
 
     /// Seek to the first key that >= `key`.
     /// Note: You should assume the key-value pairs in the block are sorted when being added by callers.
     pub fn seek_to_key(&mut self, key: &[u8]) {
-        let len = self.block.offsets.len();
         let mut low = 0;
-        let mut high = len;
+        let mut high = self.block.offsets.len();
         while low < high {
             let mid = (low + high) / 2;
-            let offset = self.block.offsets[mid];
-            let key_len = (self.block.data[offset as usize] as u16) << 8
-                | self.block.data[1 + offset as usize] as u16;
-            let mid_key =
-                &self.block.data[offset as usize + 2..offset as usize + 2 + key_len as usize];
-            if mid_key < key {
+            if self.key_at(mid) < key {
                 low = mid + 1;
-            } else if mid_key == key {
-                let val_offset = offset + 2 + key_len;
-                let val_len = (self.block.data[val_offset as usize] as u16) << 8
-                    | self.block.data[1 + val_offset as usize] as u16;
-                let value = self.block.data
-                    [val_offset as usize + 2..val_offset as usize + 2 + val_len as usize]
-                    .to_vec();
-
-                self.key = mid_key.to_vec();
-                self.value = value;
-                self.idx = mid;
-                return;
             } else {
                 high = mid;
             }
         }
+        self.seek_to_idx(low);
+    }
 
-        if low == self.block.offsets.len() {
+    /// Seek to the first key that >= `key` among the current entry and the ones after it, which
+    /// are all the candidates if `key` >= the current key. Invalidates the iterator if every key
+    /// left is smaller than `key`.
+    pub(crate) fn seek_forward_to_key(&mut self, key: &[u8]) {
+        let mut low = self.idx;
+        let mut high = self.block.offsets.len();
+        while low < high {
+            let mid = (low + high) / 2;
+            if self.key_at(mid) < key {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        self.seek_to_idx(low);
+    }
+
+    /// Returns the number of entries in the block.
+    pub(crate) fn num_of_entries(&self) -> usize {
+        self.block.offsets.len()
+    }
+
+    /// Returns the index of the current entry, or the number of entries if the iterator is invalid.
+    pub(crate) fn idx(&self) -> usize {
+        self.idx
+    }
+
+    /// Returns the number of entries left to iterate over, the current one included, or 0 if the
+    /// iterator is invalid, e.g. to decide whether to finish the block or to skip it.
+    pub fn remaining(&self) -> usize {
+        if !self.is_valid() {
+            return 0;
+        }
+        self.block.offsets.len() - self.idx
+    }
+
+    /// Position the iterator at the entry at `idx`, or invalidate it if `idx` is past the end.
+    pub(crate) fn seek_to_idx(&mut self, idx: usize) {
+        self.idx = idx;
+        self.value_compressed = false;
+        self.decompressed_value = OnceCell::new();
+        if idx >= self.block.offsets.len() {
+            self.idx = self.block.offsets.len();
             self.key = Vec::new();
             self.value = Vec::new();
-            self.idx = len;
+            self.value_range = 0..0;
             return;
         }
-        let offset = self.block.offsets[low];
-        let key_len = (self.block.data[offset as usize] as u16) << 8
-            | self.block.data[1 + offset as usize] as u16;
-        let key =
-            self.block.data[offset as usize + 2..offset as usize + 2 + key_len as usize].to_vec();
-        let val_offset = offset + 2 + key_len;
-        let val_len = (self.block.data[val_offset as usize] as u16) << 8
-            | self.block.data[1 + val_offset as usize] as u16;
-        let value = self.block.data
-            [val_offset as usize + 2..val_offset as usize + 2 + val_len as usize]
-            .to_vec();
+        let (key, value_range, value_compressed) = self.decode_entry(idx);
         self.key = key;
-        self.value = value;
-        self.idx = low;
+        self.value = if self.lazy_value {
+            Vec::new()
+        } else {
+            self.block.data[value_range.clone()].to_vec()
+        };
+        self.value_range = value_range;
+        self.value_compressed = value_compressed;
+    }
+
+    /// Returns the key of the entry at `idx` without copying it.
+    fn key_at(&self, idx: usize) -> &[u8] {
+        self.block.key_at(idx)
+    }
+
+    /// Decodes the key of the entry at `idx`, the range of its stored value in the data section, and
+    /// whether the value is compressed.
+    /// Each entry is laid out as | key_len (u16) | key | value_len (u16) | value |, with the top bit
+    /// of `value_len` set if the value is compressed.
+    fn decode_entry(&self, idx: usize) -> (Vec<u8>, Range<usize>, bool) {
+        let key = self.key_at(idx);
+        let val_offset = self.block.offsets[idx] as usize + 2 + key.len();
+        let val_len =
+            u16::from_be_bytes([self.block.data[val_offset], self.block.data[val_offset + 1]]);
+        let compressed = val_len & COMPRESSED_VALUE_FLAG != 0;
+        let val_len = (val_len & !COMPRESSED_VALUE_FLAG) as usize;
+        (
+            key.to_vec(),
+            val_offset + 2..val_offset + 2 + val_len,
+            compressed,
+        )
+    }
+}
+
+/// An iterator over the entries of a block as owned `(key, value)` pairs, see
+/// `BlockIterator::into_owned_iter`.
+pub struct OwnedBlockIter {
+    iter: BlockIterator,
+}
+
+impl Iterator for OwnedBlockIter {
+    type Item = (Vec<u8>, Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.iter.is_valid() {
+            return None;
+        }
+        let entry = (self.iter.key().to_vec(), self.iter.value().to_vec());
+        self.iter.next();
+        Some(entry)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.iter.remaining(), Some(self.iter.remaining()))
     }
 }
+
+impl ExactSizeIterator for OwnedBlockIter {}
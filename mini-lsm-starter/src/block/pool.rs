@@ -0,0 +1,60 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use parking_lot::Mutex;
+
+/// The capacity of the buffers handed out by the pool, the alignment of data blocks in an SSTable,
+/// so that any data block fits without growing the buffer.
+const BUFFER_SIZE: usize = 4196;
+
+/// A pool of block buffers. A block read through the pool decodes into a recycled buffer instead of
+/// a freshly allocated one, and hands the buffer back when the block is dropped, which keeps the
+/// allocator out of the way of read-heavy workloads.
+pub struct BlockPool {
+    buffers: Mutex<Vec<Vec<u8>>>,
+    /// The maximum number of idle buffers kept, buffers returned beyond it are freed.
+    max_idle: usize,
+    /// The number of buffers allocated because the pool was empty.
+    allocations: AtomicUsize,
+}
+
+impl BlockPool {
+    /// Create an empty pool keeping at most `max_idle` idle buffers.
+    pub fn new(max_idle: usize) -> Self {
+        Self {
+            buffers: Mutex::new(Vec::new()),
+            max_idle,
+            allocations: AtomicUsize::new(0),
+        }
+    }
+
+    /// Take an empty buffer from the pool, allocating one if there is no idle buffer.
+    pub(crate) fn get(&self) -> Vec<u8> {
+        if let Some(buffer) = self.buffers.lock().pop() {
+            return buffer;
+        }
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        Vec::with_capacity(BUFFER_SIZE)
+    }
+
+    /// Give a buffer back to the pool.
+    pub(crate) fn put(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() < BUFFER_SIZE {
+            return;
+        }
+        buffer.clear();
+        let mut buffers = self.buffers.lock();
+        if buffers.len() < self.max_idle {
+            buffers.push(buffer);
+        }
+    }
+
+    /// Get the number of buffers the pool has allocated so far.
+    pub fn allocations(&self) -> usize {
+        self.allocations.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of buffers waiting in the pool to be reused.
+    pub fn idle_buffers(&self) -> usize {
+        self.buffers.lock().len()
+    }
+}
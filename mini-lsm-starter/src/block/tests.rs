@@ -121,3 +121,647 @@ fn test_block_seek_key() {
         iter.seek_to_key(b"k");
     }
 }
+
+#[test]
+fn test_block_encoded_len() {
+    for num in [1, 7, num_of_keys()] {
+        let mut builder = BlockBuilder::new(10000);
+        for idx in 0..num {
+            assert!(builder.add(&key_of(idx), &value_of(idx)));
+        }
+        let block = builder.build();
+        assert_eq!(block.encoded_len(), block.encode().len());
+        let decoded_block = Block::decode(&block.encode());
+        assert_eq!(decoded_block.encoded_len(), block.encoded_len());
+    }
+}
+
+#[test]
+fn test_block_seek_first_entry() {
+    let block = Arc::new(generate_block());
+    let mut iters = vec![
+        BlockIterator::create_and_seek_to_first(block.clone()),
+        BlockIterator::create_and_seek_to_key(block.clone(), b""),
+        BlockIterator::create_and_seek_to_key(block.clone(), &key_of(0)),
+    ];
+    let mut iter = BlockIterator::new(block.clone());
+    iter.seek_to_first();
+    iters.push(iter);
+    let mut iter = BlockIterator::create_and_seek_to_key(block, &key_of(50));
+    iter.seek_to_key(b"");
+    iters.push(iter);
+    for iter in iters {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), key_of(0));
+        assert_eq!(iter.value(), value_of(0));
+    }
+}
+
+fn compressible_value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:05}_", idx).repeat(20).into_bytes()
+}
+
+#[test]
+fn test_block_compress_values() {
+    let mut plain = BlockBuilder::new(100000);
+    let mut compressed = BlockBuilder::new(100000);
+    compressed.set_compress_values(true);
+    for idx in 0..num_of_keys() {
+        assert!(plain.add(&key_of(idx), &compressible_value_of(idx)));
+        assert!(compressed.add(&key_of(idx), &compressible_value_of(idx)));
+    }
+    let plain = plain.build();
+    let compressed = compressed.build();
+    assert!(compressed.encoded_len() * 4 < plain.encoded_len());
+    let block = Arc::new(Block::decode(&compressed.encode()));
+    let mut iter = BlockIterator::create_and_seek_to_key(block, &key_of(30));
+    assert_eq!(iter.key(), key_of(30));
+    assert!(!iter.value_decompressed());
+    assert_eq!(iter.value(), compressible_value_of(30));
+    assert!(iter.value_decompressed());
+    iter.next();
+    assert!(!iter.value_decompressed());
+    assert_eq!(iter.value_len(), compressible_value_of(31).len());
+    assert!(iter.value_decompressed());
+    iter.seek_to_first();
+    for idx in 0..num_of_keys() {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), compressible_value_of(idx));
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_block_compress_values_incompressible() {
+    let mut builder = BlockBuilder::new(10000);
+    builder.set_compress_values(true);
+    assert!(builder.add(b"key", b"value"));
+    let block = Arc::new(builder.build());
+    let iter = BlockIterator::create_and_seek_to_first(block);
+    assert_eq!(iter.value(), b"value");
+    assert!(!iter.value_decompressed());
+}
+
+#[test]
+fn test_block_value_too_large() {
+    // the top bit of the value length flags compression, so 32 KiB values cannot be stored
+    for compress_values in [false, true] {
+        let mut builder = BlockBuilder::new(100000);
+        builder.set_compress_values(compress_values);
+        assert!(!builder.add(b"key", &vec![b'x'; 0x8000]));
+        assert!(builder.is_empty());
+        assert!(builder.add(b"key", &vec![b'x'; 0x7fff]));
+    }
+
+    // an entry whose value is flagged as compressed but is not is rejected
+    let mut builder = BlockBuilder::new(10000);
+    let mut entry = 3u16.to_be_bytes().to_vec();
+    entry.extend_from_slice(b"key");
+    entry.extend_from_slice(&(0x8000u16 | 2).to_be_bytes());
+    entry.extend_from_slice(&[0x85, 0]);
+    assert!(!builder.append_raw(&entry, entry.len() as u16));
+    assert!(builder.is_empty());
+    assert!(decode_raw_entry(&entry).is_err());
+
+    // as are entries whose lengths do not add up, without touching the block
+    let malformed: [&[u8]; 4] = [
+        &[0],
+        &[0, 9, b'k'],
+        &[0, 1, b'k', 0, 5, b'v'],
+        &[0, 1, b'k', 0, 0, 0],
+    ];
+    for entry in malformed {
+        assert!(!builder.append_raw(entry, entry.len() as u16));
+    }
+    assert!(!builder.append_raw(&[0, 1, b'k', 0, 0], 4));
+    assert!(builder.is_empty());
+
+    // as are entries too short for the lengths they hold
+    for entry in [
+        &[0u8][..],
+        &[0, 9, b'k'],
+        &[0, 1, b'k', 0],
+        &[0, 1, b'k', 0, 5, b'v'],
+    ] {
+        assert!(matches!(
+            decode_raw_entry(entry),
+            Err(TableError::Corruption { .. })
+        ));
+    }
+    assert_eq!(decode_raw_key(&[0, 1, b'k', 0x80, 2]).unwrap(), b"k");
+}
+
+/// A xorshift generator, so that the randomized tests below are reproducible from their seed.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// Generate a key over a small alphabet, so that keys often share prefixes.
+    fn key(&mut self) -> Vec<u8> {
+        let len = 1 + self.below(12);
+        (0..len).map(|_| b'a' + self.below(4) as u8).collect()
+    }
+
+    /// Generate a value that is sometimes a repeated pattern, so that it compresses.
+    fn value(&mut self) -> Vec<u8> {
+        let len = self.below(80);
+        if self.below(2) == 0 {
+            (0..len).map(|_| self.next() as u8).collect()
+        } else {
+            let pattern = [self.next() as u8, self.next() as u8, b'_'];
+            pattern.iter().copied().cycle().take(len).collect()
+        }
+    }
+}
+
+type Entries = Vec<(Vec<u8>, Vec<u8>)>;
+
+/// Build a block from random sorted entries, returning the block and the entries that fit.
+fn generate_random_block(rng: &mut Rng) -> (Block, Entries) {
+    let mut keys: Vec<Vec<u8>> = (0..rng.below(200)).map(|_| rng.key()).collect();
+    keys.sort();
+    keys.dedup();
+    let mut builder = BlockBuilder::new(64 + rng.below(4096 - 64));
+    builder.set_compress_values(rng.below(2) == 0);
+    let mut entries = Vec::new();
+    for key in keys {
+        let value = rng.value();
+        if !builder.add(&key, &value) {
+            break;
+        }
+        entries.push((key, value));
+    }
+    (builder.build(), entries)
+}
+
+#[test]
+fn test_block_random_round_trip() {
+    for seed in 0..500 {
+        let mut rng = Rng::new(seed);
+        let (block, entries) = generate_random_block(&mut rng);
+        let encoded = block.encode();
+        let block = Arc::new(Block::try_decode(&encoded).unwrap());
+        assert_eq!(block.encode(), encoded, "seed {}", seed);
+
+        let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+        for (key, value) in &entries {
+            assert_eq!(iter.key(), key.as_slice(), "seed {}", seed);
+            assert_eq!(iter.value(), value.as_slice(), "seed {}", seed);
+            iter.next();
+        }
+        assert!(!iter.is_valid(), "seed {}", seed);
+
+        let mut probes: Vec<Vec<u8>> = entries.iter().map(|(key, _)| key.clone()).collect();
+        probes.extend((0..50).map(|_| rng.key()));
+        probes.push(Vec::new());
+        probes.push(b"z".to_vec());
+        for probe in probes {
+            iter.seek_to_key(&probe);
+            let expected = entries.partition_point(|(key, _)| key < &probe);
+            match entries.get(expected) {
+                Some((key, value)) => {
+                    assert_eq!(
+                        iter.key(),
+                        key.as_slice(),
+                        "seed {} probe {:?}",
+                        seed,
+                        probe
+                    );
+                    assert_eq!(iter.value(), value.as_slice(), "seed {}", seed);
+                }
+                None => assert!(!iter.is_valid(), "seed {} probe {:?}", seed, probe),
+            }
+        }
+    }
+}
+
+#[test]
+fn test_block_try_decode_corrupted() {
+    for seed in 0..500 {
+        let mut rng = Rng::new(seed);
+        let (block, _) = generate_random_block(&mut rng);
+        let mut data = block.encode().to_vec();
+        match rng.below(3) {
+            0 => data.truncate(rng.below(data.len() + 1)),
+            1 => {
+                for _ in 0..1 + rng.below(4) {
+                    let pos = rng.below(data.len());
+                    data[pos] = rng.next() as u8;
+                }
+            }
+            _ => data = (0..rng.below(64)).map(|_| rng.next() as u8).collect(),
+        }
+        // a corrupted block may still decode, but then it must be safe to iterate
+        if let Ok(block) = Block::try_decode(&data) {
+            let mut iter = BlockIterator::create_and_seek_to_first(Arc::new(block));
+            while iter.is_valid() {
+                iter.value();
+                iter.next();
+            }
+            iter.seek_to_key(&rng.key());
+        }
+    }
+    assert!(Block::try_decode(&[]).is_err());
+    assert!(Block::try_decode(&[0, 5]).is_err());
+}
+
+#[test]
+fn test_block_append_raw() {
+    let block = generate_block();
+    let mut builder = BlockBuilder::new(10000);
+    for idx in 0..block.num_of_entries() {
+        let entry = block.raw_entry(idx);
+        assert!(builder.append_raw(entry, entry.len() as u16));
+    }
+    let copied = builder.build();
+    assert_eq!(copied.encode(), block.encode());
+    let decoded = Arc::new(Block::decode(&copied.encode()));
+    let mut iter = BlockIterator::create_and_seek_to_first(decoded);
+    for idx in 0..num_of_keys() {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+
+    // copy into blocks of limited capacity, splitting where `append_raw` reports a full block
+    let mut builder = BlockBuilder::new(128);
+    let mut num_of_copied = 0;
+    for idx in 0..block.num_of_entries() {
+        let entry = block.raw_entry(idx);
+        if !builder.append_raw(entry, entry.len() as u16) {
+            num_of_copied += builder.build().num_of_entries();
+            builder = BlockBuilder::new(128);
+            assert!(builder.append_raw(entry, entry.len() as u16));
+        }
+    }
+    num_of_copied += builder.build().num_of_entries();
+    assert_eq!(num_of_copied, num_of_keys());
+}
+
+#[test]
+fn test_block_try_decode_offset_out_of_range() {
+    let block = generate_block();
+    let mut data = block.encode().to_vec();
+    let data_len = block.data.len() as u16;
+    // the offsets are stored in reverse, so the one right before `num_of_elements` is the first
+    let pos = data.len() - 4;
+    data[pos..pos + 2].copy_from_slice(&(data_len + 10).to_be_bytes());
+    let err = Block::try_decode(&data).err().unwrap().to_string();
+    assert!(err.contains("beyond the data section"), "{}", err);
+    data[pos..pos + 2].copy_from_slice(&data_len.to_be_bytes());
+    assert!(Block::try_decode(&data).is_err());
+    data[pos..pos + 2].copy_from_slice(&(data_len - 1).to_be_bytes());
+    assert!(Block::try_decode(&data).is_err());
+}
+
+#[test]
+fn test_block_search() {
+    let block = generate_block();
+    for idx in 0..num_of_keys() {
+        assert_eq!(block.search(&key_of(idx)), Ok(idx));
+        // keys between `key_of(idx)` and `key_of(idx + 1)`
+        assert_eq!(
+            block.search(format!("key_{:03}", idx * 5 + 1).as_bytes()),
+            Err(idx + 1)
+        );
+        assert_eq!(
+            block.search(&[key_of(idx), b"0".to_vec()].concat()),
+            Err(idx + 1)
+        );
+    }
+    assert_eq!(block.search(b""), Err(0));
+    assert_eq!(block.search(b"a"), Err(0));
+    assert_eq!(block.search(b"key_"), Err(0));
+    assert_eq!(block.search(b"z"), Err(num_of_keys()));
+    assert_eq!(Block::decode(&[0, 0]).search(b"a"), Err(0));
+}
+
+#[test]
+fn test_block_iterator_timestamp() {
+    use crate::key::{key_with_ts, split_ts};
+
+    let versions: Vec<(&[u8], u64)> = vec![
+        (b"a", u64::MAX),
+        (b"a", 7),
+        (b"a", 1),
+        (b"b", 1 << 40),
+        (b"b", 3),
+        (b"c", 0),
+    ];
+    let mut builder = BlockBuilder::new(10000);
+    for (user_key, ts) in &versions {
+        assert!(builder.add(&key_with_ts(user_key, *ts), b"value"));
+    }
+    let block = Arc::new(builder.build());
+    // the timestamp is only decoded once the keys are known to be MVCC keys
+    let iter = BlockIterator::create_and_seek_to_first(block.clone());
+    assert_eq!(iter.timestamp(), 0);
+    let mut iter = BlockIterator::create_and_seek_to_first(block);
+    iter.set_mvcc_keys(true);
+    for (user_key, ts) in &versions {
+        assert_eq!(split_ts(iter.key()).0, *user_key);
+        assert_eq!(iter.timestamp(), *ts);
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+    assert_eq!(iter.timestamp(), 0);
+
+    // keys too short to be MVCC keys
+    let mut builder = BlockBuilder::new(10000);
+    assert!(builder.add(b"short", b"value"));
+    let mut iter = BlockIterator::create_and_seek_to_first(Arc::new(builder.build()));
+    iter.set_mvcc_keys(true);
+    assert_eq!(iter.timestamp(), 0);
+}
+
+#[test]
+fn test_block_iterator_remaining() {
+    let block = Arc::new(generate_block());
+    assert_eq!(BlockIterator::new(block.clone()).remaining(), 0);
+    let mut iter = BlockIterator::create_and_seek_to_first(block);
+    for idx in 0..num_of_keys() {
+        assert_eq!(iter.remaining(), num_of_keys() - idx);
+        iter.next();
+    }
+    assert!(!iter.is_valid());
+    assert_eq!(iter.remaining(), 0);
+
+    iter.seek_to_key(&key_of(num_of_keys() - 2));
+    assert_eq!(iter.remaining(), 2);
+    iter.seek_to_key(b"z");
+    assert_eq!(iter.remaining(), 0);
+}
+
+#[test]
+fn test_block_delta_keys() {
+    // sequence numbers with a few gaps, one too large for a single varint byte
+    let keys: Vec<u64> = (0..240u64)
+        .map(|idx| 1_000_000 + idx * 3 + if idx >= 120 { 1000 } else { 0 })
+        .collect();
+    let build = |delta_keys: bool| {
+        let mut builder = BlockBuilder::new(4096);
+        builder.set_delta_keys(delta_keys);
+        for (idx, key) in keys.iter().enumerate() {
+            assert!(builder.add(&key.to_be_bytes(), &(idx as u16).to_be_bytes()));
+        }
+        let size = builder.size();
+        let block = builder.build();
+        assert_eq!(block.encoded_len(), size);
+        block
+    };
+    let plain = build(false);
+    let delta = build(true);
+    let encoded = delta.encode();
+    assert!(is_delta_encoded(&encoded));
+    assert_eq!(encoded.len(), delta.encoded_len());
+    assert!(encoded.len() * 2 < plain.encoded_len());
+
+    let decoded = Block::decode(&encoded);
+    assert_eq!(decoded.data, plain.data);
+    assert_eq!(decoded.offsets, plain.offsets);
+    assert_eq!(decoded.encode(), encoded);
+    let decoded = Arc::new(Block::try_decode(&encoded).unwrap());
+    for (idx, key) in keys.iter().enumerate() {
+        let iter = BlockIterator::create_and_seek_to_key(decoded.clone(), &key.to_be_bytes());
+        assert_eq!(iter.key(), key.to_be_bytes());
+        assert_eq!(iter.value(), (idx as u16).to_be_bytes());
+        // a missing key seeks to the next one
+        let iter = BlockIterator::create_and_seek_to_key(decoded.clone(), &(key - 1).to_be_bytes());
+        assert_eq!(iter.key(), key.to_be_bytes());
+    }
+    let mut corrupted = encoded.to_vec();
+    corrupted[encoded.len() - 3] = 9;
+    assert!(Block::try_decode(&corrupted).is_err());
+    assert_eq!(Block::decode(&corrupted).num_of_entries(), 0);
+
+    // keys of another width fall back to the plain encoding
+    let mut builder = BlockBuilder::new(4096);
+    builder.set_delta_keys(true);
+    assert!(builder.add(&1u64.to_be_bytes(), b"a"));
+    assert!(builder.add(&2u32.to_be_bytes(), b"b"));
+    assert!(!is_delta_encoded(&builder.build().encode()));
+}
+
+#[test]
+fn test_block_encode_into() {
+    let build = |compress_values: bool, delta_keys: bool| {
+        let mut builder = BlockBuilder::new(4096);
+        builder.set_compress_values(compress_values);
+        builder.set_delta_keys(delta_keys);
+        for idx in 0..40 {
+            let key = (idx as u64 * 7).to_be_bytes();
+            assert!(builder.add(&key, &value_of(idx).repeat(3)));
+        }
+        builder
+    };
+    for (compress_values, delta_keys) in [(false, false), (true, false), (false, true)] {
+        let expected = build(compress_values, delta_keys).build();
+        let builder = build(compress_values, delta_keys);
+        assert_eq!(builder.uncompressed_size(), expected.uncompressed_len());
+        // the block is appended after what the buffer already holds
+        let mut buf = b"prefix".to_vec();
+        builder.encode_into(&mut buf);
+        assert_eq!(&buf[..6], b"prefix");
+        assert_eq!(buf[6..], expected.encode());
+    }
+    let mut buf = Vec::new();
+    BlockBuilder::new(4096).encode_into(&mut buf);
+    assert_eq!(buf, BlockBuilder::new(4096).build().encode());
+}
+
+#[test]
+fn test_block_content_hash() {
+    // the reference values of XXH64 with seed 0
+    assert_eq!(xxhash::xxh64(b""), 0xef46_db37_51d8_e999);
+    assert_eq!(xxhash::xxh64(b"abc"), 0x44bc_2cf5_ad77_0999);
+    let data: Vec<u8> = (0..100u8).collect();
+    let mut hasher = xxhash::Xxh64::new(0);
+    for chunk in data.chunks(7) {
+        hasher.update(chunk);
+    }
+    assert_eq!(hasher.finish(), xxhash::xxh64(&data));
+
+    let block = generate_block();
+    assert_eq!(block.content_hash(), generate_block().content_hash());
+    assert_eq!(
+        block.content_hash(),
+        Block::decode(&block.encode()).content_hash()
+    );
+    let mut builder = BlockBuilder::new(10000);
+    for idx in 0..num_of_keys() {
+        let value = if idx == 42 {
+            b"other".to_vec()
+        } else {
+            value_of(idx)
+        };
+        assert!(builder.add(&key_of(idx), &value));
+    }
+    assert_ne!(block.content_hash(), builder.build().content_hash());
+
+    // delta-encoded keys hash like plain keys, compressed values unlike plain values
+    let build = |delta_keys: bool, compress_values: bool| {
+        let mut builder = BlockBuilder::new(10000);
+        builder.set_delta_keys(delta_keys);
+        builder.set_compress_values(compress_values);
+        for idx in 0..50u64 {
+            assert!(builder.add(&(idx * 3).to_be_bytes(), &[b'v'; 100]));
+        }
+        Block::decode(&builder.build().encode())
+    };
+    let plain = build(false, false);
+    assert!(is_delta_encoded(&build(true, false).encode()));
+    assert_eq!(build(true, false).content_hash(), plain.content_hash());
+    assert_ne!(build(false, true).content_hash(), plain.content_hash());
+}
+
+#[test]
+fn test_block_iter_and_entries() {
+    let block = Arc::new(generate_block());
+    let mut iter = block.iter();
+    let mut collected = Vec::new();
+    while iter.is_valid() {
+        collected.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.next();
+    }
+    let expected: Vec<(Vec<u8>, Vec<u8>)> = (0..num_of_keys())
+        .map(|idx| (key_of(idx), value_of(idx)))
+        .collect();
+    assert_eq!(collected, expected);
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = block
+        .entries()
+        .map(|(key, value)| (key.to_vec(), value.into_owned()))
+        .collect();
+    assert_eq!(entries, expected);
+
+    // compressed values are decompressed, plain ones borrowed
+    let mut builder = BlockBuilder::new(10000);
+    builder.set_compress_values(true);
+    assert!(builder.add(b"a", &[b'x'; 200]));
+    assert!(builder.add(b"b", b"plain"));
+    let block = builder.build();
+    let entries: Vec<_> = block.entries().collect();
+    assert_eq!(entries[0].0, b"a");
+    assert!(matches!(&entries[0].1, std::borrow::Cow::Owned(value) if value == &[b'x'; 200]));
+    assert!(matches!(entries[1].1, std::borrow::Cow::Borrowed(b"plain")));
+}
+
+#[test]
+fn test_block_seqs() {
+    let mut builder = BlockBuilder::new(10000);
+    builder.set_seqs(true);
+    builder.set_compress_values(true);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add_with_seq(&key_of(idx), &value_of(idx), idx as u64 * 1000));
+    }
+    let block = builder.build();
+    assert!(block.has_seqs());
+    let encoded = block.encode();
+    assert!(has_seqs(&encoded));
+    assert_eq!(num_of_elements(&encoded) as usize, num_of_keys());
+    let block = Block::try_decode(&encoded).unwrap();
+    assert!(block.has_seqs());
+    assert_eq!(block.encoded_len(), encoded.len());
+
+    let mut iter = BlockIterator::create_and_seek_to_first(Arc::new(block));
+    for idx in 0..num_of_keys() {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        assert_eq!(iter.seq(), Some(idx as u64 * 1000));
+        iter.next();
+    }
+    assert_eq!(iter.seq(), None);
+    iter.seek_to_key(&key_of(42));
+    assert_eq!(iter.seq(), Some(42000));
+
+    // raw entries carry their sequence numbers along
+    let block = Arc::new(Block::decode(&encoded));
+    let mut copy = BlockBuilder::new(10000);
+    copy.set_seqs(true);
+    let entry = block.raw_entry(7);
+    assert!(copy.append_raw(entry, entry.len() as u16));
+    let iter = BlockIterator::create_and_seek_to_first(Arc::new(copy.build()));
+    assert_eq!(iter.key(), key_of(7));
+    assert_eq!(iter.seq(), Some(7000));
+
+    // blocks without sequence numbers have none
+    let iter = BlockIterator::create_and_seek_to_first(Arc::new(generate_block()));
+    assert_eq!(iter.seq(), None);
+
+    // a block with sequence numbers whose last entry is cut short is rejected
+    let mut builder = BlockBuilder::new(10000);
+    builder.set_seqs(true);
+    assert!(builder.add_with_seq(b"key", b"value", 1));
+    let mut truncated = builder.build().encode().to_vec();
+    let len = truncated.len();
+    truncated.remove(len - 5);
+    assert!(Block::try_decode(&truncated).is_err());
+}
+
+#[test]
+fn test_block_builder_with_capacity() {
+    let mut plain = BlockBuilder::new(10000);
+    let mut reserved = BlockBuilder::with_capacity(10000, num_of_keys());
+    let capacity = reserved.entries_capacity();
+    assert!(capacity >= num_of_keys());
+    let mut plain_growths = 0;
+    for idx in 0..num_of_keys() {
+        let before = plain.entries_capacity();
+        assert!(plain.add(&key_of(idx), &value_of(idx)));
+        plain_growths += (plain.entries_capacity() != before) as usize;
+        assert!(reserved.add(&key_of(idx), &value_of(idx)));
+        // the reserved builder never grows
+        assert_eq!(reserved.entries_capacity(), capacity);
+    }
+    assert!(plain_growths > 1);
+    assert_eq!(reserved.build().encode(), plain.build().encode());
+}
+
+#[test]
+fn test_block_into_owned_iter() {
+    let block = Arc::new(generate_block());
+    let mut iter = block.iter();
+    let mut expected = Vec::new();
+    while iter.is_valid() {
+        expected.push((iter.key().to_vec(), iter.value().to_vec()));
+        iter.next();
+    }
+    let owned = block.iter().into_owned_iter();
+    assert_eq!(owned.len(), num_of_keys());
+    let collected: Vec<(Vec<u8>, Vec<u8>)> = owned.collect();
+    assert_eq!(collected, expected);
+
+    // starting from the current entry, with lazy and compressed values
+    let iter = BlockIterator::create_and_seek_to_key(block, &key_of(90));
+    let collected: Vec<_> = iter.into_owned_iter().collect();
+    assert_eq!(collected, expected[90..]);
+    let mut builder = BlockBuilder::new(10000);
+    builder.set_compress_values(true);
+    assert!(builder.add(b"a", &[b'x'; 200]));
+    assert!(builder.add(b"b", b"plain"));
+    let mut iter = Arc::new(builder.build()).iter();
+    iter.set_lazy_value(true);
+    let collected: Vec<_> = iter.into_owned_iter().collect();
+    assert_eq!(
+        collected,
+        vec![
+            (b"a".to_vec(), vec![b'x'; 200]),
+            (b"b".to_vec(), b"plain".to_vec())
+        ]
+    );
+}
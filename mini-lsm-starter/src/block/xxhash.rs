@@ -0,0 +1,130 @@
+const PRIME_1: u64 = 0x9e37_79b1_85eb_ca87;
+const PRIME_2: u64 = 0xc2b2_ae3d_27d4_eb4f;
+const PRIME_3: u64 = 0x1656_67b1_9e37_79f9;
+const PRIME_4: u64 = 0x85eb_ca77_c2b2_ae63;
+const PRIME_5: u64 = 0x27d4_eb2f_1656_67c5;
+
+fn round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(PRIME_2))
+        .rotate_left(31)
+        .wrapping_mul(PRIME_1)
+}
+
+fn merge_round(acc: u64, val: u64) -> u64 {
+    (acc ^ round(0, val))
+        .wrapping_mul(PRIME_1)
+        .wrapping_add(PRIME_4)
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    u64::from_le_bytes(bytes[..8].try_into().unwrap())
+}
+
+/// A streaming XXH64 hasher, so that several slices are hashed as if they were concatenated
+/// without copying them together.
+pub(super) struct Xxh64 {
+    seed: u64,
+    acc: [u64; 4],
+    /// The bytes not yet consumed, shorter than a 32-byte stripe.
+    buf: [u8; 32],
+    buf_len: usize,
+    total_len: u64,
+}
+
+impl Xxh64 {
+    pub(super) fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            acc: [
+                seed.wrapping_add(PRIME_1).wrapping_add(PRIME_2),
+                seed.wrapping_add(PRIME_2),
+                seed,
+                seed.wrapping_sub(PRIME_1),
+            ],
+            buf: [0; 32],
+            buf_len: 0,
+            total_len: 0,
+        }
+    }
+
+    fn consume_stripe(acc: &mut [u64; 4], stripe: &[u8]) {
+        for (lane, acc) in acc.iter_mut().enumerate() {
+            *acc = round(*acc, read_u64(&stripe[lane * 8..]));
+        }
+    }
+
+    pub(super) fn update(&mut self, mut data: &[u8]) {
+        self.total_len += data.len() as u64;
+        if self.buf_len > 0 {
+            let fill = (32 - self.buf_len).min(data.len());
+            self.buf[self.buf_len..self.buf_len + fill].copy_from_slice(&data[..fill]);
+            self.buf_len += fill;
+            data = &data[fill..];
+            if self.buf_len < 32 {
+                return;
+            }
+            Self::consume_stripe(&mut self.acc, &self.buf);
+            self.buf_len = 0;
+        }
+        let mut stripes = data.chunks_exact(32);
+        for stripe in &mut stripes {
+            Self::consume_stripe(&mut self.acc, stripe);
+        }
+        let rest = stripes.remainder();
+        self.buf[..rest.len()].copy_from_slice(rest);
+        self.buf_len = rest.len();
+    }
+
+    pub(super) fn finish(&self) -> u64 {
+        let mut hash = if self.total_len >= 32 {
+            let [v1, v2, v3, v4] = self.acc;
+            let mut hash = v1
+                .rotate_left(1)
+                .wrapping_add(v2.rotate_left(7))
+                .wrapping_add(v3.rotate_left(12))
+                .wrapping_add(v4.rotate_left(18));
+            for acc in self.acc {
+                hash = merge_round(hash, acc);
+            }
+            hash
+        } else {
+            self.seed.wrapping_add(PRIME_5)
+        };
+        hash = hash.wrapping_add(self.total_len);
+        let mut rest = &self.buf[..self.buf_len];
+        while rest.len() >= 8 {
+            hash ^= round(0, read_u64(rest));
+            hash = hash
+                .rotate_left(27)
+                .wrapping_mul(PRIME_1)
+                .wrapping_add(PRIME_4);
+            rest = &rest[8..];
+        }
+        if rest.len() >= 4 {
+            let word = u32::from_le_bytes(rest[..4].try_into().unwrap()) as u64;
+            hash ^= word.wrapping_mul(PRIME_1);
+            hash = hash
+                .rotate_left(23)
+                .wrapping_mul(PRIME_2)
+                .wrapping_add(PRIME_3);
+            rest = &rest[4..];
+        }
+        for &byte in rest {
+            hash ^= (byte as u64).wrapping_mul(PRIME_5);
+            hash = hash.rotate_left(11).wrapping_mul(PRIME_1);
+        }
+        hash ^= hash >> 33;
+        hash = hash.wrapping_mul(PRIME_2);
+        hash ^= hash >> 29;
+        hash = hash.wrapping_mul(PRIME_3);
+        hash ^ (hash >> 32)
+    }
+}
+
+/// Get the XXH64 hash of `data` with seed 0.
+#[cfg(test)]
+pub(super) fn xxh64(data: &[u8]) -> u64 {
+    let mut hasher = Xxh64::new(0);
+    hasher.update(data);
+    hasher.finish()
+}
@@ -1,3 +1,5 @@
+pub mod concat_iterator;
+pub mod gc_iterator;
 pub mod merge_iterator;
 pub mod two_merge_iterator;
 
@@ -0,0 +1,117 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::StorageIterator;
+use crate::table::{SsTable, SsTableIterator};
+
+/// Concatenates SSTables whose key ranges are sorted and do not overlap, e.g. the tables of one
+/// level. Only the iterator of the table being scanned is kept open.
+pub struct SstConcatIterator {
+    current: Option<SsTableIterator>,
+    next_sst_idx: usize,
+    sstables: Vec<Arc<SsTable>>,
+    /// The iterator becomes invalid after passing this bound.
+    upper: Bound<Bytes>,
+}
+
+impl SstConcatIterator {
+    /// Create a new iterator and seek to the first key-value pair of the first table.
+    pub fn create_and_seek_to_first(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
+        Self::create_with_bounds(sstables, Bound::Unbounded, Bound::Unbounded)
+    }
+
+    /// Create a new iterator and seek to the first key-value pair which >= `key`.
+    pub fn create_and_seek_to_key(sstables: Vec<Arc<SsTable>>, key: &[u8]) -> Result<Self> {
+        Self::create_with_bounds(sstables, Bound::Included(key), Bound::Unbounded)
+    }
+
+    /// Create a new iterator over the key-value pairs in the range of `lower` and `upper`.
+    pub fn create_with_bounds(
+        sstables: Vec<Arc<SsTable>>,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<Self> {
+        let mut next_sst_idx = match lower {
+            Bound::Included(key) | Bound::Excluded(key) => sstables
+                .partition_point(|sst| sst.last_key().map_or(true, |last_key| last_key < key)),
+            Bound::Unbounded => 0,
+        };
+        while next_sst_idx < sstables.len() && sstables[next_sst_idx].num_of_blocks() == 0 {
+            next_sst_idx += 1;
+        }
+        let mut iter = Self {
+            current: None,
+            next_sst_idx,
+            sstables,
+            upper: match upper {
+                Bound::Included(key) => Bound::Included(Bytes::copy_from_slice(key)),
+                Bound::Excluded(key) => Bound::Excluded(Bytes::copy_from_slice(key)),
+                Bound::Unbounded => Bound::Unbounded,
+            },
+        };
+        if let Some(sst) = iter.sstables.get(next_sst_idx).cloned() {
+            iter.next_sst_idx += 1;
+            iter.current = Some(match lower {
+                Bound::Included(key) | Bound::Excluded(key) => {
+                    SsTableIterator::create_and_seek_to_key(sst, key)?
+                }
+                Bound::Unbounded => SsTableIterator::create_and_seek_to_first(sst)?,
+            });
+        }
+        iter.move_until_valid()?;
+        if let Bound::Excluded(key) = lower {
+            if iter.is_valid() && iter.key() == key {
+                iter.next()?;
+            }
+        }
+        Ok(iter)
+    }
+
+    /// Open the following tables until the current iterator is valid or all tables are consumed.
+    fn move_until_valid(&mut self) -> Result<()> {
+        while let Some(iter) = &self.current {
+            if iter.is_valid() {
+                break;
+            }
+            self.current = None;
+            while self.next_sst_idx < self.sstables.len() {
+                let sst = self.sstables[self.next_sst_idx].clone();
+                self.next_sst_idx += 1;
+                if sst.num_of_blocks() > 0 {
+                    self.current = Some(SsTableIterator::create_and_seek_to_first(sst)?);
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl StorageIterator for SstConcatIterator {
+    fn key(&self) -> &[u8] {
+        self.current.as_ref().unwrap().key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.current.as_ref().unwrap().value()
+    }
+
+    fn is_valid(&self) -> bool {
+        match &self.current {
+            Some(iter) if iter.is_valid() => match &self.upper {
+                Bound::Included(upper) => iter.key() <= upper.as_ref(),
+                Bound::Excluded(upper) => iter.key() < upper.as_ref(),
+                Bound::Unbounded => true,
+            },
+            _ => false,
+        }
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.current.as_mut().unwrap().next()?;
+        self.move_until_valid()
+    }
+}
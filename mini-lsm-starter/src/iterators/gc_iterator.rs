@@ -0,0 +1,72 @@
+use anyhow::Result;
+
+use super::StorageIterator;
+use crate::key;
+
+/// Drops the versions that no reader can observe any more from a merged iterator over MVCC keys.
+/// For each user key, all versions newer than `watermark` are kept, as well as the newest version
+/// at or below `watermark`. Older versions are dropped, and so is the newest version at or below
+/// `watermark` if it is a tombstone (empty value), since nothing below it remains to be covered.
+/// This assumes the inputs are the bottommost data of their key range.
+pub struct GcIterator<I: StorageIterator> {
+    iter: I,
+    watermark: u64,
+    /// The user key of the current entry.
+    user_key: Vec<u8>,
+    /// Whether a version at or below the watermark has been seen for `user_key`.
+    below_watermark: bool,
+}
+
+impl<I: StorageIterator> GcIterator<I> {
+    pub fn create(iter: I, watermark: u64) -> Result<Self> {
+        let mut iter = Self {
+            iter,
+            watermark,
+            user_key: Vec::new(),
+            below_watermark: false,
+        };
+        iter.skip_dropped()?;
+        Ok(iter)
+    }
+
+    /// Move the inner iterator to the next version that has to be retained.
+    fn skip_dropped(&mut self) -> Result<()> {
+        while self.iter.is_valid() {
+            let (user_key, ts) = key::split_ts(self.iter.key());
+            if user_key != self.user_key {
+                self.user_key = user_key.to_vec();
+                self.below_watermark = false;
+            }
+            if ts > self.watermark {
+                return Ok(());
+            }
+            if !self.below_watermark {
+                self.below_watermark = true;
+                if !self.iter.value().is_empty() {
+                    return Ok(());
+                }
+            }
+            self.iter.next()?;
+        }
+        Ok(())
+    }
+}
+
+impl<I: StorageIterator> StorageIterator for GcIterator<I> {
+    fn key(&self) -> &[u8] {
+        self.iter.key()
+    }
+
+    fn value(&self) -> &[u8] {
+        self.iter.value()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.iter.is_valid()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.iter.next()?;
+        self.skip_dropped()
+    }
+}
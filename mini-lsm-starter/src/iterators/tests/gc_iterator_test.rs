@@ -0,0 +1,83 @@
+use super::*;
+use crate::iterators::gc_iterator::GcIterator;
+use crate::key::{key_with_ts, split_ts};
+
+fn versions(entries: &[(&str, u64, &str)]) -> Vec<(Bytes, Bytes)> {
+    entries
+        .iter()
+        .map(|(key, ts, value)| {
+            (
+                Bytes::from(key_with_ts(key.as_bytes(), *ts)),
+                Bytes::copy_from_slice(value.as_bytes()),
+            )
+        })
+        .collect()
+}
+
+fn check_gc_result(iter: impl StorageIterator, expected: &[(&str, u64, &str)]) {
+    let mut iter = iter;
+    for (key, ts, value) in expected {
+        assert!(iter.is_valid());
+        assert_eq!(split_ts(iter.key()), (key.as_bytes(), *ts));
+        assert_eq!(iter.value(), value.as_bytes());
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_key_with_ts_order() {
+    assert!(key_with_ts(b"a", 2) < key_with_ts(b"a", 1));
+    assert!(key_with_ts(b"a", 1) < key_with_ts(b"b", 2));
+    assert_eq!(split_ts(&key_with_ts(b"a", 233)), (&b"a"[..], 233));
+}
+
+#[test]
+fn test_gc_versions() {
+    let iter = MockIterator::new(versions(&[
+        ("a", 10, "a10"),
+        ("a", 8, "a8"),
+        ("a", 5, "a5"),
+        ("a", 2, "a2"),
+        ("b", 7, "b7"),
+        ("b", 4, ""),
+        ("b", 1, "b1"),
+        ("c", 3, ""),
+        ("c", 1, "c1"),
+        ("d", 9, ""),
+        ("d", 5, "d5"),
+        ("d", 3, "d3"),
+    ]));
+    let iter = GcIterator::create(iter, 6).unwrap();
+    check_gc_result(
+        iter,
+        &[
+            ("a", 10, "a10"),
+            ("a", 8, "a8"),
+            ("a", 5, "a5"),
+            ("b", 7, "b7"),
+            ("d", 9, ""),
+            ("d", 5, "d5"),
+        ],
+    );
+}
+
+#[test]
+fn test_gc_all_above_watermark() {
+    let entries = [("a", 3, "a3"), ("a", 2, ""), ("b", 2, "b2")];
+    let iter = GcIterator::create(MockIterator::new(versions(&entries)), 1).unwrap();
+    check_gc_result(iter, &entries);
+}
+
+#[test]
+fn test_gc_all_below_watermark() {
+    let iter = MockIterator::new(versions(&[
+        ("a", 3, "a3"),
+        ("a", 2, "a2"),
+        ("b", 2, ""),
+        ("b", 1, "b1"),
+        ("c", 1, "c1"),
+    ]));
+    let iter = GcIterator::create(iter, 100).unwrap();
+    check_gc_result(iter, &[("a", 3, "a3"), ("c", 1, "c1")]);
+}
@@ -0,0 +1,22 @@
+//! Helpers for MVCC keys, which are a user key followed by an 8-byte timestamp.
+//!
+//! The timestamp is stored bit-inverted in big-endian, so that versions of the same user key are
+//! ordered from the newest to the oldest when the keys are compared as plain bytes.
+
+/// The length of the timestamp suffix of an MVCC key.
+pub const TS_LEN: usize = std::mem::size_of::<u64>();
+
+/// Build an MVCC key from a user key and a timestamp.
+pub fn key_with_ts(user_key: &[u8], ts: u64) -> Vec<u8> {
+    let mut key = Vec::with_capacity(user_key.len() + TS_LEN);
+    key.extend_from_slice(user_key);
+    key.extend_from_slice(&(!ts).to_be_bytes());
+    key
+}
+
+/// Split an MVCC key into the user key and the timestamp.
+pub fn split_ts(key: &[u8]) -> (&[u8], u64) {
+    assert!(key.len() >= TS_LEN, "key is too short to hold a timestamp");
+    let (user_key, ts) = key.split_at(key.len() - TS_LEN);
+    (user_key, !u64::from_be_bytes(ts.try_into().unwrap()))
+}
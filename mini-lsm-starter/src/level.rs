@@ -0,0 +1,58 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+use crate::iterators::concat_iterator::SstConcatIterator;
+use crate::table::SsTable;
+
+/// A level of L1+ in leveled compaction, holding SSTables sorted by key range.
+/// The key ranges of the tables never overlap, so a point lookup reads at most one table.
+pub struct Level {
+    sstables: Vec<Arc<SsTable>>,
+}
+
+impl Level {
+    /// Create a level from tables sorted by key range. Returns an error if a table is empty or if
+    /// the tables are not sorted or their ranges overlap.
+    pub fn new(sstables: Vec<Arc<SsTable>>) -> Result<Self> {
+        for (idx, sst) in sstables.iter().enumerate() {
+            if sst.num_of_blocks() == 0 {
+                bail!("sst {} in level is empty", idx);
+            }
+            if idx > 0 && sstables[idx - 1].last_key() >= sst.first_key() {
+                bail!(
+                    "sst {} in level overlaps with or precedes sst {}",
+                    idx,
+                    idx - 1
+                );
+            }
+        }
+        Ok(Self { sstables })
+    }
+
+    /// Get the tables of the level.
+    pub fn sstables(&self) -> &[Arc<SsTable>] {
+        &self.sstables
+    }
+
+    /// Get the value of `key`, reading only the table whose range may contain the key.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        let idx = self
+            .sstables
+            .partition_point(|sst| sst.last_key().unwrap().as_ref() < key);
+        match self.sstables.get(idx) {
+            Some(sst) => sst.get(key),
+            None => Ok(None),
+        }
+    }
+
+    /// Create an iterator over a range of keys.
+    pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<SstConcatIterator> {
+        SstConcatIterator::create_with_bounds(self.sstables.clone(), lower, upper)
+    }
+}
+
+#[cfg(test)]
+mod tests;
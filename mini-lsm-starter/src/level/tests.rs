@@ -0,0 +1,99 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use tempfile::{tempdir, TempDir};
+
+use super::*;
+use crate::iterators::StorageIterator;
+use crate::table::SsTableBuilder;
+
+fn key_of(sst_idx: usize, idx: usize) -> Vec<u8> {
+    format!("key_{}_{:03}", sst_idx, idx * 5).into_bytes()
+}
+
+fn value_of(sst_idx: usize, idx: usize) -> Vec<u8> {
+    format!("value_{}_{:010}", sst_idx, idx).into_bytes()
+}
+
+fn num_of_keys() -> usize {
+    100
+}
+
+fn generate_sst(dir: &TempDir, sst_idx: usize) -> Arc<SsTable> {
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_of(sst_idx, idx), &value_of(sst_idx, idx)));
+    }
+    let path = dir.path().join(format!("{}.sst", sst_idx));
+    Arc::new(builder.build(sst_idx, None, path).unwrap())
+}
+
+fn generate_level(dir: &TempDir) -> Level {
+    Level::new((0..3).map(|sst_idx| generate_sst(dir, sst_idx)).collect()).unwrap()
+}
+
+#[test]
+fn test_level_overlap() {
+    let dir = tempdir().unwrap();
+    let sst0 = generate_sst(&dir, 0);
+    let sst1 = generate_sst(&dir, 1);
+    assert!(Level::new(vec![sst1.clone(), sst0.clone()]).is_err());
+    assert!(Level::new(vec![sst0.clone(), sst0.clone()]).is_err());
+    assert!(Level::new(vec![sst0, sst1]).is_ok());
+}
+
+#[test]
+fn test_level_get() {
+    let dir = tempdir().unwrap();
+    let level = generate_level(&dir);
+    for sst_idx in 0..3 {
+        for idx in 0..num_of_keys() {
+            assert_eq!(
+                level.get(&key_of(sst_idx, idx)).unwrap(),
+                Some(Bytes::from(value_of(sst_idx, idx)))
+            );
+        }
+    }
+    assert_eq!(level.get(b"key_1_001").unwrap(), None);
+    assert_eq!(level.get(b"key_1_999").unwrap(), None);
+    assert_eq!(level.get(b"key_3").unwrap(), None);
+    assert_eq!(level.get(b"a").unwrap(), None);
+}
+
+#[test]
+fn test_level_scan() {
+    let dir = tempdir().unwrap();
+    let level = generate_level(&dir);
+    let expected: Vec<(Vec<u8>, Vec<u8>)> = (0..3)
+        .flat_map(|sst_idx| {
+            (0..num_of_keys()).map(move |idx| (key_of(sst_idx, idx), value_of(sst_idx, idx)))
+        })
+        .collect();
+    let check_scan = |lower: Bound<&[u8]>, upper: Bound<&[u8]>, expected: &[(Vec<u8>, Vec<u8>)]| {
+        let mut iter = level.scan(lower, upper).unwrap();
+        for (key, value) in expected {
+            assert!(iter.is_valid());
+            assert_eq!(iter.key(), key);
+            assert_eq!(iter.value(), value);
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    };
+    check_scan(Bound::Unbounded, Bound::Unbounded, &expected);
+    check_scan(
+        Bound::Included(&key_of(0, 50)),
+        Bound::Included(&key_of(2, 50)),
+        &expected[50..251],
+    );
+    check_scan(
+        Bound::Excluded(&key_of(0, 99)),
+        Bound::Excluded(&key_of(2, 0)),
+        &expected[100..200],
+    );
+    check_scan(
+        Bound::Included(b"key_1_999"),
+        Bound::Unbounded,
+        &expected[200..],
+    );
+    check_scan(Bound::Included(b"key_3"), Bound::Unbounded, &[]);
+}
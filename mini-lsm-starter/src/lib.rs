@@ -1,8 +1,11 @@
 pub mod block;
 pub mod iterators;
+pub mod key;
+pub mod level;
 pub mod lsm_iterator;
 pub mod lsm_storage;
 pub mod mem_table;
+pub mod sorted_run;
 pub mod table;
 
 #[cfg(test)]
@@ -0,0 +1,62 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::iterators::concat_iterator::SstConcatIterator;
+use crate::level::Level;
+use crate::table::SsTable;
+
+/// A sorted run: SSTables whose key ranges do not overlap, so that together they hold a single
+/// sorted sequence of keys, the unit a compaction reads and writes. It is a `Level` whose tables
+/// may be given in any order, and are sorted by key range.
+pub struct SortedRun {
+    level: Level,
+}
+
+impl SortedRun {
+    /// Create a run from `sstables`, sorting them by key range. Returns an error if a table is
+    /// empty or if the ranges of two tables overlap, see `Level::new`.
+    pub fn new(mut sstables: Vec<Arc<SsTable>>) -> Result<Self> {
+        sstables.sort_by(|a, b| a.first_key().cmp(&b.first_key()));
+        Ok(Self {
+            level: Level::new(sstables)?,
+        })
+    }
+
+    /// Get the run as a level.
+    pub fn level(&self) -> &Level {
+        &self.level
+    }
+
+    /// Get the tables of the run, sorted by key range.
+    pub fn sstables(&self) -> &[Arc<SsTable>] {
+        self.level.sstables()
+    }
+
+    /// Get the value of `key`, see `Level::get`.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.level.get(key)
+    }
+
+    /// Create an iterator over a range of keys, see `Level::scan`.
+    pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<SstConcatIterator> {
+        self.level.scan(lower, upper)
+    }
+
+    /// Get the smallest and the largest key of the run, `None` if the run has no table.
+    pub fn key_range(&self) -> Option<(Bytes, Bytes)> {
+        let first_key = self.sstables().first()?.first_key()?.clone();
+        let last_key = self.sstables().last()?.last_key()?.clone();
+        Some((first_key, last_key))
+    }
+
+    /// Get the total size of the files of the tables of the run.
+    pub fn total_size(&self) -> u64 {
+        self.sstables().iter().map(|sst| sst.table_size()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests;
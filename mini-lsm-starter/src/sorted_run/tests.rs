@@ -0,0 +1,106 @@
+use std::ops::Bound;
+use std::sync::Arc;
+
+use tempfile::{tempdir, TempDir};
+
+use super::*;
+use crate::iterators::StorageIterator;
+use crate::table::SsTableBuilder;
+
+fn key_of(idx: usize) -> Vec<u8> {
+    format!("key_{:05}", idx).into_bytes()
+}
+
+fn value_of(idx: usize) -> Vec<u8> {
+    format!("value_{:010}", idx).into_bytes()
+}
+
+/// The keys of table `sst_idx`, which follow the keys of the table before it.
+fn keys_of_sst(sst_idx: usize) -> std::ops::Range<usize> {
+    sst_idx * 100..(sst_idx + 1) * 100
+}
+
+fn generate_sst(dir: &TempDir, sst_idx: usize) -> Arc<SsTable> {
+    let mut builder = SsTableBuilder::new(128);
+    for idx in keys_of_sst(sst_idx) {
+        assert!(builder.add(&key_of(idx), &value_of(idx)));
+    }
+    let path = dir.path().join(format!("{}.sst", sst_idx));
+    Arc::new(builder.build(sst_idx, None, path).unwrap())
+}
+
+/// A run of 3 tables, given out of order.
+fn generate_run(dir: &TempDir) -> SortedRun {
+    SortedRun::new([2, 0, 1].map(|sst_idx| generate_sst(dir, sst_idx)).to_vec()).unwrap()
+}
+
+#[test]
+fn test_sorted_run_new() {
+    let dir = tempdir().unwrap();
+    let run = generate_run(&dir);
+    let ids: Vec<usize> = run.sstables().iter().map(|sst| sst.id()).collect();
+    assert_eq!(ids, vec![0, 1, 2]);
+    assert_eq!(
+        run.key_range(),
+        Some((Bytes::from(key_of(0)), Bytes::from(key_of(299))))
+    );
+    assert_eq!(
+        run.total_size(),
+        run.sstables()
+            .iter()
+            .map(|sst| sst.table_size())
+            .sum::<u64>()
+    );
+    assert!(run.total_size() > 3 * 4196);
+
+    let sst0 = generate_sst(&dir, 0);
+    assert!(SortedRun::new(vec![sst0.clone(), sst0]).is_err());
+    let empty = SortedRun::new(Vec::new()).unwrap();
+    assert_eq!(empty.key_range(), None);
+    assert_eq!(empty.total_size(), 0);
+    assert_eq!(empty.get(&key_of(0)).unwrap(), None);
+}
+
+#[test]
+fn test_sorted_run_get() {
+    let dir = tempdir().unwrap();
+    let run = generate_run(&dir);
+    for idx in 0..300 {
+        assert_eq!(
+            run.get(&key_of(idx)).unwrap(),
+            Some(Bytes::from(value_of(idx)))
+        );
+    }
+    assert_eq!(run.get(b"a").unwrap(), None);
+    assert_eq!(run.get(b"key_00099_").unwrap(), None);
+    assert_eq!(run.get(&key_of(300)).unwrap(), None);
+}
+
+#[test]
+fn test_sorted_run_scan_across_tables() {
+    let dir = tempdir().unwrap();
+    let run = generate_run(&dir);
+    let lower = key_of(50);
+    let upper = key_of(250);
+    let mut iter = run
+        .scan(Bound::Excluded(&lower), Bound::Included(&upper))
+        .unwrap();
+    for idx in 51..=250 {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    // a scan starting right at a table boundary
+    let mut iter = run
+        .scan(Bound::Included(&key_of(200)), Bound::Unbounded)
+        .unwrap();
+    let mut count = 0;
+    while iter.is_valid() {
+        count += 1;
+        iter.next().unwrap();
+    }
+    assert_eq!(count, 100);
+}
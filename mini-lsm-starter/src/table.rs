@@ -2,149 +2,2149 @@
 #![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
 
 mod builder;
+mod compact;
+mod compat;
+mod cursor;
+mod dedup;
+mod disk_index;
+mod error;
+mod file_cache;
+mod footer;
+mod index;
 mod iterator;
+mod multi_sst;
+mod properties;
 
-use std::path::Path;
-use std::sync::Arc;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
 
-use anyhow::Result;
+use parking_lot::Mutex;
+
+use anyhow::{anyhow, bail, Result};
 pub use builder::SsTableBuilder;
 use bytes::{Buf, Bytes};
+pub use compact::{
+    compact, compact_with_dedup, estimate_compaction_output, CompactionEstimate, CompactionStats,
+};
+pub use cursor::SsTableCursor;
+pub use dedup::DedupStore;
+pub use disk_index::{DiskIndex, DiskIndexOptions};
+pub use error::TableError;
+pub use file_cache::FileHandleCache;
+use footer::FOOTER_SIZE;
+pub use footer::{Footer, RawFooter};
+pub use index::BlockIndex;
 pub use iterator::SsTableIterator;
+pub use multi_sst::{MultiSstFile, MultiSstWriter};
+use properties::{decode_properties, BLOCK_CHECKSUMS_PROPERTY, LEVEL_PROPERTY};
 
-use crate::block::Block;
+use crate::block::{self, Block, BlockIterator, BlockPool};
+use crate::iterators::two_merge_iterator::TwoMergeIterator;
+use crate::iterators::StorageIterator;
 use crate::lsm_storage::BlockCache;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct BlockMeta {
     /// Offset of this data block.
-    /// It marks the end of the data block, as each data block is aligned to 4KB.
+    /// It marks the end of the data block, as each data block is aligned to 4KB unless packed.
     pub offset: u32,
+    /// The number of entries in this data block.
+    pub num_entries: u16,
     key_len: u16,
     /// The first key of the data block, mainly used for index purpose.
+    /// Empty for the blocks left out of a sparse index, see `SsTable::is_indexed`.
     pub first_key: Bytes,
+    /// The last key of the data block, empty like `first_key` for the blocks left out of a sparse
+    /// index.
+    pub last_key: Bytes,
+    /// The block of another table this block is a copy of, in which case its bytes are not stored
+    /// in this table at all, see `SsTableBuilder::add_block_reference`.
+    pub reference: Option<BlockRef>,
+    /// Where the block starts in the data section. A block takes a whole 4196-byte slot unless it
+    /// is packed, and the slot of a block is the index of the block unless blocks before it are
+    /// references or packed. A reference takes no space, so it starts where it ends.
+    start: u32,
+    /// Whether the block is packed right after the block before it instead of starting a new
+    /// slot, see `SsTableBuilder::set_pack_threshold`.
+    packed: bool,
+}
+
+/// A data block of another table, by the id of the table and the index of the block in it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BlockRef {
+    pub table_id: usize,
+    pub block_idx: usize,
+}
+
+/// The version of the block meta layout, stored in front of the encoded metas.
+/// Version 2 added `num_entries`, version 3 the block references, version 4 the packed blocks.
+/// The layouts before version 2 carried no version, neither the original one nor the one that
+/// added `last_key`, so both are rejected as version 0, the high byte of the first offset, rather
+/// than decoded as one another.
+const BLOCK_META_VERSION: u8 = 4;
+
+/// The oldest version of the block meta layout still decoded.
+const MIN_BLOCK_META_VERSION: u8 = 2;
+
+/// Set in the flags of the meta of a block reference.
+const FLAG_REFERENCE: u8 = 1;
+
+/// Set in the flags of the meta of a packed block.
+const FLAG_PACKED: u8 = 2;
+
+/// Round `pos` up to the start of a 4196-byte slot.
+pub(crate) fn align_up(pos: u32) -> u32 {
+    (pos + 4195) / 4196 * 4196
+}
+
+/// Get the smallest key greater than every key starting with `prefix`, or `None` if there is
+/// none, i.e. if `prefix` is empty or only made of 0xff bytes.
+fn prefix_successor(prefix: &[u8]) -> Option<Vec<u8>> {
+    let len = prefix.iter().rposition(|&byte| byte != 0xff)? + 1;
+    let mut successor = prefix[..len].to_vec();
+    successor[len - 1] += 1;
+    Some(successor)
+}
+
+/// Get the length of the common prefix of `a` and `b`.
+fn shared_prefix_len(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b.iter()).take_while(|(a, b)| a == b).count()
 }
 
 impl BlockMeta {
     /// Encode block meta to a buffer.
-    /// You may add extra fields to the buffer,
-    /// in order to help keep track of `first_key` when decoding from the same buffer in the future.
-    pub fn encode_block_meta(
+    /// Adjacent `first_key`s usually share a long prefix, so each `first_key` is stored as the length
+    /// of the prefix shared with the previous `first_key` followed by the remaining suffix, and the
+    /// `last_key` likewise relative to the `first_key` of the same block. The metas follow a single
+    /// `BLOCK_META_VERSION` (u8) byte, and each of them is laid out as:
+    /// | offset (u32) | num_entries (u16) | flags (u8) | [ref_table_id (u64) | ref_block_idx (u32)] | shared_len (u16) | suffix_len (u16) | suffix | last_shared_len (u16) | last_suffix_len (u16) | last_suffix |
+    /// where `flags` has `FLAG_REFERENCE` set for a reference, whose ids of the referenced block
+    /// follow, and `FLAG_PACKED` for a packed block. Where each block starts is not stored, as it
+    /// follows from the blocks before it.
+    pub fn encode_block_meta(block_meta: &[BlockMeta], buf: &mut Vec<u8>) {
+        Self::encode_block_meta_with(block_meta, buf, true);
+    }
+
+    /// Encode block meta like `encode_block_meta`, with the prefix compression of the keys only if
+    /// `prefix_compression` is set. Without it, every key is stored in full with a `shared_len`
+    /// of 0, in the same layout, so both are decoded by `decode_block_meta`.
+    pub fn encode_block_meta_with(
         block_meta: &[BlockMeta],
-        #[allow(clippy::ptr_arg)] // remove this allow after you finish
         buf: &mut Vec<u8>,
+        prefix_compression: bool,
     ) {
+        let shared_prefix_len = |a: &[u8], b: &[u8]| match prefix_compression {
+            true => shared_prefix_len(a, b),
+            false => 0,
+        };
+        buf.push(BLOCK_META_VERSION);
+        let mut prev_key: &[u8] = &[];
         for meta in block_meta {
+            let shared_len = shared_prefix_len(prev_key, &meta.first_key);
+            let suffix = &meta.first_key[shared_len..];
             buf.extend_from_slice(&meta.offset.to_be_bytes());
-            buf.extend_from_slice(&meta.key_len.to_be_bytes());
-            buf.extend_from_slice(&meta.first_key);
+            buf.extend_from_slice(&meta.num_entries.to_be_bytes());
+            let packed = if meta.packed { FLAG_PACKED } else { 0 };
+            match meta.reference {
+                Some(reference) => {
+                    buf.push(FLAG_REFERENCE | packed);
+                    buf.extend_from_slice(&(reference.table_id as u64).to_be_bytes());
+                    buf.extend_from_slice(&(reference.block_idx as u32).to_be_bytes());
+                }
+                None => buf.push(packed),
+            }
+            buf.extend_from_slice(&(shared_len as u16).to_be_bytes());
+            buf.extend_from_slice(&(suffix.len() as u16).to_be_bytes());
+            buf.extend_from_slice(suffix);
+            let last_shared_len = shared_prefix_len(&meta.first_key, &meta.last_key);
+            let last_suffix = &meta.last_key[last_shared_len..];
+            buf.extend_from_slice(&(last_shared_len as u16).to_be_bytes());
+            buf.extend_from_slice(&(last_suffix.len() as u16).to_be_bytes());
+            buf.extend_from_slice(last_suffix);
+            prev_key = &meta.first_key;
         }
     }
 
-    /// Decode block meta from a buffer.
-    pub fn decode_block_meta(buf: impl Buf) -> Vec<BlockMeta> {
-        let mut block_metas = Vec::new();
+    /// Decode block meta from a buffer, reconstructing each `first_key` from the previous one.
+    /// Fails with `TableError::UnsupportedVersion` if the metas were encoded with a version other
+    /// than `BLOCK_META_VERSION` or the one before it, and with `TableError::Corruption` if they
+    /// are truncated.
+    pub fn decode_block_meta(buf: impl Buf) -> Result<Vec<BlockMeta>, TableError> {
+        let mut block_metas: Vec<BlockMeta> = Vec::new();
         let mut buf = buf;
+        let version = Self::decode_version(&mut buf)?;
+        let mut data_end = 0;
         while buf.has_remaining() {
-            let offset = buf.get_u32();
-            let key_len = buf.get_u16();
-            let first_key = buf.copy_to_bytes(key_len as usize);
-            block_metas.push(BlockMeta {
-                offset,
-                key_len,
-                first_key,
+            let prev_key = block_metas
+                .last()
+                .map_or(&[][..], |prev| &prev.first_key[..]);
+            let meta = Self::decode_next(&mut buf, prev_key, version, data_end)?;
+            data_end = meta.data_end(data_end);
+            block_metas.push(meta);
+        }
+        Ok(block_metas)
+    }
+
+    /// Decode the version in front of the encoded metas, checking that it is supported.
+    pub(crate) fn decode_version(buf: &mut impl Buf) -> Result<u8, TableError> {
+        if !buf.has_remaining() {
+            return Err(TableError::corruption(
+                None,
+                "block meta is missing its version",
+            ));
+        }
+        let version = buf.get_u8();
+        if !(MIN_BLOCK_META_VERSION..=BLOCK_META_VERSION).contains(&version) {
+            return Err(TableError::UnsupportedVersion {
+                what: "block meta",
+                found: version,
+                expected: BLOCK_META_VERSION,
             });
         }
-        block_metas
+        Ok(version)
+    }
+
+    /// Decode the meta at the front of `buf`, encoded with `version`, whose `first_key` is stored
+    /// relative to `prev_key`, the `first_key` of the previous meta. Any key sharing that prefix
+    /// works as well, such as the `first_key` being decoded itself. `data_end` is where the data
+    /// section continues after the blocks before this one, see `data_end`.
+    pub(crate) fn decode_next(
+        buf: &mut impl Buf,
+        prev_key: &[u8],
+        version: u8,
+        data_end: u32,
+    ) -> Result<BlockMeta, TableError> {
+        let truncated = || TableError::corruption(None, "block meta truncated");
+        if buf.remaining() < 10 {
+            return Err(truncated());
+        }
+        let offset = buf.get_u32();
+        let num_entries = buf.get_u16();
+        let mut reference = None;
+        let mut packed = false;
+        if version >= 3 {
+            if buf.remaining() < 5 {
+                return Err(truncated());
+            }
+            let flags = buf.get_u8();
+            let known_flags = match version {
+                3 => FLAG_REFERENCE,
+                _ => FLAG_REFERENCE | FLAG_PACKED,
+            };
+            if flags & !known_flags != 0 {
+                return Err(TableError::corruption(
+                    None,
+                    format!("invalid block meta flags {:#x}", flags),
+                ));
+            }
+            packed = flags & FLAG_PACKED != 0;
+            if flags & FLAG_REFERENCE != 0 {
+                if buf.remaining() < 16 {
+                    return Err(truncated());
+                }
+                reference = Some(BlockRef {
+                    table_id: buf.get_u64() as usize,
+                    block_idx: buf.get_u32() as usize,
+                });
+            }
+        }
+        let shared_len = buf.get_u16() as usize;
+        let suffix_len = buf.get_u16() as usize;
+        // the first meta has no previous key, so it must store its first key in full
+        if shared_len > prev_key.len() {
+            return Err(TableError::corruption(
+                None,
+                format!(
+                    "first key shares {} bytes with a previous key of {} bytes",
+                    shared_len,
+                    prev_key.len()
+                ),
+            ));
+        }
+        if buf.remaining() < suffix_len + 4 {
+            return Err(truncated());
+        }
+        let mut first_key = Vec::with_capacity(shared_len + suffix_len);
+        first_key.extend_from_slice(&prev_key[..shared_len]);
+        first_key.extend_from_slice(&buf.copy_to_bytes(suffix_len));
+        let last_shared_len = buf.get_u16() as usize;
+        let last_suffix_len = buf.get_u16() as usize;
+        if last_shared_len > first_key.len() {
+            return Err(TableError::corruption(
+                None,
+                format!(
+                    "last key shares {} bytes with a first key of {} bytes",
+                    last_shared_len,
+                    first_key.len()
+                ),
+            ));
+        }
+        if buf.remaining() < last_suffix_len {
+            return Err(truncated());
+        }
+        let mut last_key = Vec::with_capacity(last_shared_len + last_suffix_len);
+        last_key.extend_from_slice(&first_key[..last_shared_len]);
+        last_key.extend_from_slice(&buf.copy_to_bytes(last_suffix_len));
+        let start = match (reference, packed) {
+            (Some(_), _) => offset,
+            (None, true) => data_end,
+            (None, false) => align_up(data_end),
+        };
+        Ok(BlockMeta {
+            offset,
+            num_entries,
+            key_len: first_key.len() as u16,
+            first_key: Bytes::from(first_key),
+            last_key: Bytes::from(last_key),
+            reference,
+            start,
+            packed,
+        })
+    }
+
+    /// Get a copy of this meta for the block moved `delta` bytes further into a file, e.g. when
+    /// the data blocks of several tables are concatenated. `delta` is a whole number of slots.
+    pub fn rebase(&self, delta: u32) -> BlockMeta {
+        BlockMeta {
+            offset: self.offset + delta,
+            start: self.start + delta,
+            ..self.clone()
+        }
+    }
+
+    /// Whether the block is packed right after the block before it, without alignment.
+    pub fn is_packed(&self) -> bool {
+        self.packed
+    }
+
+    /// Get where the block starts in the data section.
+    fn start(&self) -> u64 {
+        self.start as u64
+    }
+
+    /// Get where the data section continues after this block, padding included, given
+    /// `data_end`, where it continued after the blocks before it. A packed block is followed right
+    /// away, a stored block fills its whole slot, and a reference takes no space.
+    fn data_end(&self, data_end: u32) -> u32 {
+        match (self.reference, self.packed) {
+            (Some(_), _) => data_end,
+            (None, true) => self.offset,
+            (None, false) => self.start + 4196,
+        }
     }
 }
 
+/// Merge the block metas of `tables`, as if their data sections were concatenated in order into
+/// a single data section. Each table's data section is a whole number of 4196-byte slots, so the
+/// blocks stay aligned and the metas of each table are rebased by the length of the data
+/// sections before it. The tables must be ordered by key and must not overlap. The metas left
+/// without keys by a sparse index stay without keys.
+pub fn concat_metas(tables: &[Arc<SsTable>]) -> Vec<BlockMeta> {
+    let mut metas = Vec::with_capacity(tables.iter().map(|table| table.num_of_blocks()).sum());
+    let mut delta = 0;
+    for table in tables {
+        metas.extend(table.block_metas().iter().map(|meta| meta.rebase(delta)));
+        delta += table.block_meta_offset;
+    }
+    metas
+}
+
+/// Read the entries with a key in `[lower, upper)` of two tables merged together, e.g. an L0 table
+/// and the L1 table it overlaps during a targeted compaction, without the generic merge stack.
+/// `a` is the newer table: of two entries with the same key, the entry of `a` wins. Tombstones
+/// are kept, so that a deletion in `a` still shadows the entries of older tables.
+pub fn merge_range(
+    a: &Arc<SsTable>,
+    b: &Arc<SsTable>,
+    lower: &[u8],
+    upper: &[u8],
+) -> Result<Vec<(Bytes, Bytes)>> {
+    let mut iter = TwoMergeIterator::create(
+        SsTableIterator::create_and_seek_to_key(a.clone(), lower)?,
+        SsTableIterator::create_and_seek_to_key(b.clone(), lower)?,
+    )?;
+    let mut entries = Vec::new();
+    while iter.is_valid() && iter.key() < upper {
+        entries.push((
+            Bytes::copy_from_slice(iter.key()),
+            Bytes::copy_from_slice(iter.value()),
+        ));
+        iter.next()?;
+    }
+    Ok(entries)
+}
+
+/// The bytes a `FileObject` reads from.
+enum FileData {
+    /// The whole file, read into memory.
+    Memory(Bytes),
+    /// A file left on disk, read through a handle of `cache`, see
+    /// `FileObject::open_with_handle_cache`.
+    Disk {
+        path: PathBuf,
+        size: u64,
+        /// The id of the file in `cache`.
+        id: u64,
+        cache: Arc<FileHandleCache>,
+    },
+}
+
 /// A file object.
-pub struct FileObject(Bytes);
+pub struct FileObject {
+    data: FileData,
+    /// Number of `read` calls served by this file, used to observe I/O patterns.
+    reads: AtomicUsize,
+    /// The number of bytes fetched at once by a sequential read, 0 to read only what is asked.
+    readahead: usize,
+    /// The bytes fetched by the last sequential read, along with their offset.
+    readahead_buf: Mutex<Option<(u64, Bytes)>>,
+}
 
 impl FileObject {
     pub fn read(&self, offset: u64, len: u64) -> Result<Vec<u8>> {
-        Ok(self.0[offset as usize..(offset + len) as usize].to_vec())
+        let mut buf = Vec::with_capacity(len as usize);
+        self.read_into(offset, len, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// Read like `read`, into `buf` instead of a new buffer. `buf` is cleared first.
+    pub fn read_into(&self, offset: u64, len: u64, buf: &mut Vec<u8>) -> Result<()> {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        if offset + len > self.size() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!(
+                    "read of {} bytes at offset {} exceeds file size {}",
+                    len,
+                    offset,
+                    self.size()
+                ),
+            )
+            .into());
+        }
+        match &self.data {
+            FileData::Memory(data) => {
+                buf.clear();
+                buf.extend_from_slice(&data[offset as usize..(offset + len) as usize]);
+            }
+            FileData::Disk {
+                path, id, cache, ..
+            } => cache.read_at(*id, path, offset, len, buf)?,
+        }
+        Ok(())
+    }
+
+    /// Read like `read_into`, as part of a sequential scan. With read-ahead, a read missing the
+    /// read-ahead buffer fetches `readahead` bytes from `offset` at once, so that the reads of the
+    /// scan that follow are served from the buffer without reading the file again.
+    pub fn read_sequential_into(&self, offset: u64, len: u64, buf: &mut Vec<u8>) -> Result<()> {
+        if self.readahead == 0 {
+            return self.read_into(offset, len, buf);
+        }
+        let mut readahead_buf = self.readahead_buf.lock();
+        let buffered = match &*readahead_buf {
+            Some((start, data))
+                if *start <= offset && offset + len <= start + data.len() as u64 =>
+            {
+                data.slice((offset - start) as usize..(offset - start + len) as usize)
+            }
+            _ => {
+                let fetch_len = (self.readahead as u64)
+                    .max(len)
+                    .min(self.size().saturating_sub(offset));
+                let mut data = Vec::new();
+                self.read_into(offset, fetch_len.max(len), &mut data)?;
+                let data = Bytes::from(data);
+                *readahead_buf = Some((offset, data.clone()));
+                data.slice(..len as usize)
+            }
+        };
+        buf.clear();
+        buf.extend_from_slice(&buffered);
+        Ok(())
+    }
+
+    /// Fetch `readahead` bytes at once on sequential reads, see `read_sequential_into`.
+    pub fn with_readahead(mut self, readahead: usize) -> Self {
+        self.readahead = readahead;
+        self
     }
 
     pub fn size(&self) -> u64 {
-        self.0.len() as u64
+        match &self.data {
+            FileData::Memory(data) => data.len() as u64,
+            FileData::Disk { size, .. } => *size,
+        }
+    }
+
+    /// Get the number of bytes of the file held in memory: all of them for a file read into
+    /// memory, none for one left on disk, see `open_with_handle_cache`.
+    pub fn resident_size(&self) -> u64 {
+        match &self.data {
+            FileData::Memory(data) => data.len() as u64,
+            FileData::Disk { .. } => 0,
+        }
+    }
+
+    /// Get the number of reads issued against this file so far.
+    pub fn read_count(&self) -> usize {
+        self.reads.load(Ordering::Relaxed)
+    }
+
+    fn from_bytes(data: Bytes) -> Self {
+        Self {
+            data: FileData::Memory(data),
+            reads: AtomicUsize::new(0),
+            readahead: 0,
+            readahead_buf: Mutex::new(None),
+        }
     }
 
     /// Create a new file object (day 2) and write the file to the disk (day 4).
     pub fn create(path: &Path, data: Vec<u8>) -> Result<Self> {
-        Ok(Self(Bytes::from(data)))
+        Ok(Self::from_bytes(Bytes::from(data)))
     }
 
     pub fn open(path: &Path) -> Result<Self> {
+        Ok(Self::from_bytes(Bytes::from(std::fs::read(path)?)))
+    }
+
+    /// Open the file at `path` without reading it into memory: each read goes to the disk through
+    /// a handle of `cache`, which bounds how many files are open at once across all the files
+    /// sharing it.
+    pub fn open_with_handle_cache(path: &Path, cache: Arc<FileHandleCache>) -> Result<Self> {
+        let size = std::fs::metadata(path)?.len();
         Ok(Self {
-            0: Bytes::from(std::fs::read(path)?),
+            data: FileData::Disk {
+                path: path.to_path_buf(),
+                size,
+                id: cache.register(),
+                cache,
+            },
+            reads: AtomicUsize::new(0),
+            readahead: 0,
+            readahead_buf: Mutex::new(None),
         })
     }
 }
 
-/// -------------------------------------------------------------------------------------------------------
-/// |              Data Block             |             Meta Block              |          Extra          |
-/// -------------------------------------------------------------------------------------------------------
-/// | Data Block #1 | ... | Data Block #N | Meta Block #1 | ... | Meta Block #N | Meta Block Offset (u32) |
-/// -------------------------------------------------------------------------------------------------------
+impl Drop for FileObject {
+    fn drop(&mut self) {
+        if let FileData::Disk { id, cache, .. } = &self.data {
+            cache.forget(*id);
+        }
+    }
+}
+
+/// ---------------------------------------------------------------------------------------------------------------------------------------------------------------
+/// |              Data Block             |             Meta Block              |                                      Extra                                      |
+/// ---------------------------------------------------------------------------------------------------------------------------------------------------------------
+/// | Data Block #1 | ... | Data Block #N | Meta Block #1 | ... | Meta Block #N | Bloom Filter (empty) | Properties | Footer (FOOTER_SIZE bytes) |
+/// ---------------------------------------------------------------------------------------------------------------------------------------------------------------
 pub struct SsTable {
     /// The actual storage unit of SsTable, the format is as above.
     file: FileObject,
-    /// The meta blocks that hold info for data blocks.
-    block_metas: Vec<BlockMeta>,
+    /// The meta blocks that hold info for data blocks, only left unset while the table is held
+    /// by a `LazySsTable`.
+    block_metas: OnceLock<Vec<BlockMeta>>,
     /// The offset that indicates the start point of meta blocks in `file`.
     block_meta_offset: u32,
+    /// The id of the table, which keys its blocks in `block_cache`.
+    id: usize,
+    block_cache: Option<Arc<BlockCache>>,
+    /// The footer of the table, see `Footer`.
+    footer: Footer,
+    /// The user-defined properties of the table, see `SsTableBuilder::set_property`. Decoded
+    /// along with `block_metas`.
+    properties: OnceLock<BTreeMap<String, Bytes>>,
+    /// The offset of the footer in `file`, which ends the properties section.
+    footer_offset: u64,
+    /// The tables the block references of this table point to, by id, see `with_block_sources`.
+    block_sources: BTreeMap<usize, Arc<SsTable>>,
+    /// The block metas left on disk, when the table is opened with `OpenOptions::disk_index`.
+    /// `block_metas` is then only loaded by the operations that go over every meta.
+    disk_index: Option<DiskIndex>,
+}
+
+/// A table opened with `SsTable::open_lazy`, of which only the footer is read. The block metas
+/// and the properties are read the first time the table is asked for, so that a failure of that
+/// read is returned to the caller instead of surfacing from accessors like `find_block_idx`.
+pub struct LazySsTable {
+    table: SsTable,
+}
+
+impl LazySsTable {
+    /// Get the id of the table.
+    pub fn id(&self) -> usize {
+        self.table.id
+    }
+
+    /// Get the footer of the table.
+    pub fn footer(&self) -> &Footer {
+        &self.table.footer
+    }
+
+    /// Get the table, reading and decoding its block metas and properties first if they are not
+    /// loaded yet. Fails if that read fails, in which case the next call tries again.
+    pub fn table(&self) -> Result<&SsTable, TableError> {
+        self.table.load_index()?;
+        Ok(&self.table)
+    }
+
+    /// Get the table like `table`, taking it out of the lazy handle, e.g. to share it in an `Arc`.
+    pub fn into_table(self) -> Result<SsTable, TableError> {
+        self.table.load_index()?;
+        Ok(self.table)
+    }
+}
+
+/// The options of a block read.
+#[derive(Clone, Default)]
+pub struct ReadOptions {
+    /// The pool to decode blocks into, so that their buffers are recycled.
+    pub block_pool: Option<Arc<BlockPool>>,
+}
+
+/// The options of opening a table, see `SsTable::open_with_options`.
+#[derive(Clone, Default)]
+pub struct OpenOptions {
+    /// Leave the block metas on disk, keeping only one key every `chunk_len` metas in memory,
+    /// see `DiskIndex`.
+    pub disk_index: Option<DiskIndexOptions>,
+}
+
+/// Where a cached block read comes from, which decides whether the block is worth caching.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CacheHint {
+    /// A lookup of a few keys, whose blocks are likely to be read again: cache the block.
+    #[default]
+    PointLookup,
+    /// A scan over many blocks, each read once: use the cached block if there is one, but do not
+    /// cache it otherwise, so that the scan does not evict the blocks of point lookups.
+    Scan,
+}
+
+/// Read the footer of a table held in the first `logical_len` bytes of `file`, and check that the
+/// sections it points to are in order.
+fn read_footer(file: &FileObject, logical_len: u64) -> Result<Footer, TableError> {
+    if logical_len < FOOTER_SIZE as u64 || logical_len > file.size() {
+        return Err(TableError::corruption(
+            None,
+            format!(
+                "invalid logical length {} for a file of {} bytes",
+                logical_len,
+                file.size()
+            ),
+        ));
+    }
+    let footer_offset = logical_len - FOOTER_SIZE as u64;
+    let footer = file
+        .read(footer_offset, FOOTER_SIZE as u64)
+        .map_err(TableError::from_read)?;
+    let footer = Footer::decode(&footer)?;
+    if footer.properties_len as u64 > footer_offset {
+        return Err(TableError::corruption(
+            None,
+            format!("properties of {} bytes out of range", footer.properties_len),
+        ));
+    }
+    if footer.bloom_offset as u64 > footer_offset - footer.properties_len as u64 {
+        return Err(TableError::corruption(
+            None,
+            format!("bloom offset {} out of range", footer.bloom_offset),
+        ));
+    }
+    if footer.block_meta_offset > footer.bloom_offset {
+        return Err(TableError::corruption(
+            None,
+            format!(
+                "block meta offset {} out of range",
+                footer.block_meta_offset
+            ),
+        ));
+    }
+    Ok(footer)
 }
 
 impl SsTable {
+    /// Read the footer of the table in `file` without checking it or opening the table, e.g. to
+    /// dump it when a table fails to open. Only fails if the file is too small to hold a footer
+    /// or cannot be read.
+    pub fn read_footer_bytes(file: &FileObject) -> Result<RawFooter, TableError> {
+        if file.size() < FOOTER_SIZE as u64 {
+            return Err(TableError::corruption(
+                None,
+                format!("file of {} bytes is too small for a footer", file.size()),
+            ));
+        }
+        let bytes = file
+            .read(file.size() - FOOTER_SIZE as u64, FOOTER_SIZE as u64)
+            .map_err(TableError::from_read)?;
+        Ok(RawFooter::new(bytes))
+    }
+
     #[cfg(test)]
-    pub(crate) fn open_for_test(file: FileObject) -> Result<Self> {
+    pub(crate) fn open_for_test(file: FileObject) -> Result<Self, TableError> {
         Self::open(0, None, file)
     }
 
-    /// Open SSTable from a file.
-    pub fn open(id: usize, block_cache: Option<Arc<BlockCache>>, file: FileObject) -> Result<Self> {
-        let block_meta_offset = file.read(file.size() - 4, 4)?;
-        let block_meta_offset = u32::from_be_bytes(block_meta_offset[0..4].try_into().unwrap());
-        let buf = file.read(
-            block_meta_offset as u64,
-            file.size() - 4 - block_meta_offset as u64,
-        )?;
-        let metas = BlockMeta::decode_block_meta(Bytes::from(buf));
+    /// Build a table straight from `blocks`, laid out and padded like `SsTableBuilder::build`
+    /// lays them out, with the metas and the footer describing them. Unlike going through the
+    /// builder, the blocks are kept exactly as given, e.g. empty or unusually split.
+    /// An empty block takes the last key of the block before it as its first and last key, so
+    /// the metas stay sorted.
+    #[cfg(test)]
+    pub(crate) fn from_blocks(id: usize, blocks: Vec<Block>) -> Result<Self, TableError> {
+        let mut data = Vec::new();
+        let mut metas = Vec::with_capacity(blocks.len());
+        let mut prev_key = Bytes::new();
+        let mut uncompressed_bytes = 0;
+        for block in &blocks {
+            let encoded = block.encode();
+            assert!(encoded.len() <= 4196, "encoded block too large");
+            data.extend_from_slice(&encoded);
+            let (first_key, last_key) = match block.num_of_entries() {
+                0 => (prev_key.clone(), prev_key.clone()),
+                n => (
+                    Bytes::copy_from_slice(block.key_at(0)),
+                    Bytes::copy_from_slice(block.key_at(n - 1)),
+                ),
+            };
+            metas.push(BlockMeta {
+                offset: data.len() as u32,
+                num_entries: block.num_of_entries() as u16,
+                key_len: first_key.len() as u16,
+                first_key,
+                last_key: last_key.clone(),
+                reference: None,
+                start: metas.len() as u32 * 4196,
+                packed: false,
+            });
+            uncompressed_bytes += block.uncompressed_len() as u64;
+            prev_key = last_key;
+            data.resize(metas.len() * 4196, 0);
+        }
+        let block_meta_offset = data.len() as u32;
+        BlockMeta::encode_block_meta(&metas, &mut data);
+        let footer = Footer {
+            block_meta_offset,
+            bloom_offset: data.len() as u32,
+            entry_count: metas.iter().map(|meta| meta.num_entries as u64).sum(),
+            max_ts: 0,
+            uncompressed_bytes,
+            compressed_bytes: blocks.iter().map(|block| block.encoded_len() as u64).sum(),
+            table_id: id as u64,
+            index_sparsity: 1,
+            properties_len: 0,
+        };
+        footer.encode(&mut data);
+        Self::open(id, None, FileObject::from_bytes(Bytes::from(data)))
+    }
+
+    /// Open SSTable from a file, as table `id`. The id is taken as given, see
+    /// `open_with_expected_id` to check it against the id stored in the footer.
+    pub fn open(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        file: FileObject,
+    ) -> Result<Self, TableError> {
+        let len = file.size();
+        Self::open_with_len(id, block_cache, file, len)
+    }
+
+    /// Open SSTable from a file like `open`, as the id stored in its footer. If `expected_id` is
+    /// given and differs from the stored id, this fails with `TableError::IdMismatch`, which
+    /// catches a file opened in place of another one.
+    pub fn open_with_expected_id(
+        expected_id: Option<usize>,
+        block_cache: Option<Arc<BlockCache>>,
+        file: FileObject,
+    ) -> Result<Self, TableError> {
+        let mut sst = Self::open(0, block_cache, file)?;
+        let found = sst.footer.table_id;
+        match expected_id {
+            Some(expected) if expected as u64 != found => {
+                Err(TableError::IdMismatch { expected, found })
+            }
+            _ => {
+                sst.id = found as usize;
+                Ok(sst)
+            }
+        }
+    }
+
+    /// Open SSTable from a file like `open`. If `prefetch_boundary_blocks` is set and there is a
+    /// block cache, the first and the last data block are read into the cache right away, so that
+    /// neither the first scan nor a lookup of the largest keys pays for a cold read.
+    pub fn open_with_prefetch(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        file: FileObject,
+        prefetch_boundary_blocks: bool,
+    ) -> Result<Self, TableError> {
+        let sst = Self::open(id, block_cache, file)?;
+        if let Some(block_cache) = sst
+            .block_cache
+            .as_ref()
+            .filter(|_| prefetch_boundary_blocks)
+        {
+            if sst.num_of_blocks() > 0 {
+                for block_idx in [0, sst.num_of_blocks() - 1] {
+                    block_cache.insert((sst.id, block_idx), sst.read_block(block_idx)?);
+                }
+            }
+        }
+        Ok(sst)
+    }
+
+    /// Open SSTable from a file like `open`. If `verify_index` is set, every data block is read
+    /// to check that its first entry matches the `first_key` of its meta, as a wrong `first_key`
+    /// silently sends `find_block_idx` to the wrong block. Fails with `TableError::Corruption` on
+    /// the first mismatch. Blocks left out of a sparse index have no `first_key` to check.
+    pub fn open_with_verify_index(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        file: FileObject,
+        verify_index: bool,
+    ) -> Result<Self, TableError> {
+        let sst = Self::open(id, block_cache, file)?;
+        if verify_index {
+            for (block_idx, meta) in sst.block_metas().iter().enumerate() {
+                if !sst.is_indexed(block_idx) {
+                    continue;
+                }
+                let block = sst.read_block(block_idx)?;
+                let first_key = match block.num_of_entries() {
+                    0 => &[][..],
+                    _ => block.key_at(0),
+                };
+                if first_key != meta.first_key {
+                    return Err(TableError::corruption(
+                        Some(block_idx),
+                        format!(
+                            "starts with {:?} but its meta has first_key {:?}",
+                            Bytes::copy_from_slice(first_key),
+                            meta.first_key
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(sst)
+    }
+
+    /// Open SSTable from the first `logical_len` bytes of a file.
+    /// The footer is read relative to `logical_len` instead of the physical file size, so any bytes
+    /// after the table (padding, or other tables packed into the same file) are ignored.
+    pub fn open_with_len(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        file: FileObject,
+        logical_len: u64,
+    ) -> Result<Self, TableError> {
+        let sst = Self::open_lazy_with_len(id, block_cache, file, logical_len)?;
+        sst.load_index()?;
+        Ok(sst)
+    }
+
+    /// Open SSTable from a file like `open`, keeping only the data blocks that end within the first
+    /// `valid_data_len` bytes, e.g. when the metas are intact but only a prefix of the data can be
+    /// trusted, as it was only replicated up to that point. The table then behaves as if it held
+    /// only those blocks, `num_of_blocks` and `last_key` included, while the footer is kept as it
+    /// is, so its totals still describe the whole table.
+    pub fn open_truncated_at(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        file: FileObject,
+        valid_data_len: u64,
+    ) -> Result<Self, TableError> {
+        let mut sst = Self::open(id, block_cache, file)?;
+        let num_of_blocks = sst
+            .block_metas()
+            .iter()
+            .take_while(|meta| meta.offset as u64 <= valid_data_len)
+            .count();
+        if num_of_blocks == sst.num_of_blocks() {
+            return Ok(sst);
+        }
+        // the new last block may be left out of a sparse index, which always keeps the last block
+        let last_keys = match num_of_blocks.checked_sub(1) {
+            Some(last_idx) if !sst.is_indexed(last_idx) => {
+                let block = sst.read_block(last_idx)?;
+                let num_of_entries = block.num_of_entries();
+                (num_of_entries > 0).then(|| {
+                    (
+                        Bytes::copy_from_slice(block.key_at(0)),
+                        Bytes::copy_from_slice(block.key_at(num_of_entries - 1)),
+                    )
+                })
+            }
+            _ => None,
+        };
+        let metas = sst.block_metas.get_mut().unwrap();
+        metas.truncate(num_of_blocks);
+        if let (Some(meta), Some((first_key, last_key))) = (metas.last_mut(), last_keys) {
+            meta.key_len = first_key.len() as u16;
+            meta.first_key = first_key;
+            meta.last_key = last_key;
+        }
+        Ok(sst)
+    }
+
+    /// Open SSTable from a file like `open`, with `options`.
+    /// With a `disk_index`, the meta section is scanned to build the index but the metas are not
+    /// kept, see `DiskIndex`. Point lookups, iterators and block reads then read the chunk of
+    /// metas they need from the file. The operations that go over every meta, such as
+    /// `block_metas` itself, compaction or verification, load all of them on first use. Fails
+    /// with `TableError::Unsupported` if the table cannot be opened with a disk index.
+    pub fn open_with_options(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        file: FileObject,
+        options: &OpenOptions,
+    ) -> Result<Self, TableError> {
+        let Some(disk_index) = &options.disk_index else {
+            return Self::open(id, block_cache, file);
+        };
+        let len = file.size();
+        let mut sst = Self::open_lazy_with_len(id, block_cache, file, len)?;
+        sst.disk_index = Some(DiskIndex::open(&sst.file, &sst.footer, disk_index)?);
+        let start = sst.footer_offset - sst.footer.properties_len as u64;
+        let buf = sst
+            .file
+            .read(start, sst.footer.properties_len as u64)
+            .map_err(TableError::from_read)?;
+        let _ = sst.properties.set(decode_properties(&buf)?);
+        Ok(sst)
+    }
+
+    /// Open SSTable from a file like `open`, but only read the footer. The block metas and the
+    /// properties are read and decoded when the table is first asked for with
+    /// `LazySsTable::table`, so opening many tables that are mostly left unread stays cheap, and
+    /// a failure of that read is returned there rather than from a later read of the table.
+    pub fn open_lazy(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        file: FileObject,
+    ) -> Result<LazySsTable, TableError> {
+        let len = file.size();
+        let table = Self::open_lazy_with_len(id, block_cache, file, len)?;
+        Ok(LazySsTable { table })
+    }
+
+    fn open_lazy_with_len(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        file: FileObject,
+        logical_len: u64,
+    ) -> Result<Self, TableError> {
+        let footer = read_footer(&file, logical_len)?;
         Ok(Self {
             file,
-            block_metas: metas,
-            block_meta_offset,
+            block_metas: OnceLock::new(),
+            block_meta_offset: footer.block_meta_offset,
+            id,
+            block_cache,
+            footer,
+            properties: OnceLock::new(),
+            footer_offset: logical_len - FOOTER_SIZE as u64,
+            block_sources: BTreeMap::new(),
+            disk_index: None,
         })
     }
 
-    /// Read a block from the disk.
-    pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>> {
-        let block_offset = self.block_metas[block_idx].offset;
-        let start = block_offset / 4196 * 4196;
-        let block_data = self
+    /// Read and decode the block metas and the properties of the table, with a single read
+    /// spanning both. Does nothing if they are already loaded. Every `SsTable` handed out has
+    /// them loaded, see `LazySsTable`.
+    fn load_index(&self) -> Result<(), TableError> {
+        if self.block_metas.get().is_some() && self.properties.get().is_some() {
+            return Ok(());
+        }
+        let start = self.footer.block_meta_offset as u64;
+        let buf = self
             .file
-            .read(start as u64, (block_offset - start) as u64)?;
-        let block = Block::decode(&block_data);
-        Ok(Arc::new(block))
+            .read(start, self.footer_offset - start)
+            .map_err(TableError::from_read)?;
+        let metas_len = (self.footer.bloom_offset - self.footer.block_meta_offset) as usize;
+        let metas = BlockMeta::decode_block_meta(&buf[..metas_len])?;
+        let properties =
+            decode_properties(&buf[buf.len() - self.footer.properties_len as usize..])?;
+        // a concurrent load may have won the race, with the same content
+        let _ = self.block_metas.set(metas);
+        let _ = self.properties.set(properties);
+        Ok(())
+    }
+
+    /// Open SSTable from a data file and an index file written by `SsTableBuilder::build_split`.
+    /// Fails with `TableError::Corruption` if the two files do not match.
+    pub fn open_split(
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        data_path: impl AsRef<Path>,
+        index_path: impl AsRef<Path>,
+    ) -> Result<Self, TableError> {
+        let index = std::fs::read(index_path)?;
+        if index.len() < FOOTER_SIZE {
+            return Err(TableError::corruption(None, "index file too small"));
+        }
+        let (index, footer) = index.split_at(index.len() - FOOTER_SIZE);
+        let footer = Footer::decode(footer)?;
+        let Some(properties_offset) = index.len().checked_sub(footer.properties_len as usize)
+        else {
+            return Err(TableError::corruption(
+                None,
+                format!(
+                    "properties of {} bytes beyond the index file",
+                    footer.properties_len
+                ),
+            ));
+        };
+        if footer.bloom_offset as usize > properties_offset {
+            return Err(TableError::corruption(
+                None,
+                format!("bloom offset {} beyond the index file", footer.bloom_offset),
+            ));
+        }
+        let properties = decode_properties(&index[properties_offset..])?;
+        let data_len = footer.block_meta_offset;
+        let file = FileObject::open(data_path.as_ref()).map_err(TableError::from_read)?;
+        if file.size() != data_len as u64 {
+            return Err(TableError::corruption(
+                None,
+                format!(
+                    "data file has {} bytes but the index expects {}",
+                    file.size(),
+                    data_len
+                ),
+            ));
+        }
+        let metas = BlockMeta::decode_block_meta(&index[..footer.bloom_offset as usize])?;
+        if let Some(meta) = metas.iter().find(|meta| meta.offset > data_len) {
+            return Err(TableError::corruption(
+                None,
+                format!("block offset {} beyond the data file", meta.offset),
+            ));
+        }
+        Ok(Self {
+            file,
+            block_metas: metas.into(),
+            block_meta_offset: data_len,
+            id,
+            block_cache,
+            footer,
+            properties: properties.into(),
+            footer_offset: data_len as u64,
+            block_sources: BTreeMap::new(),
+            disk_index: None,
+        })
+    }
+
+    /// Register the tables the block references of this table point to, so that reading a
+    /// referenced block transparently reads it from its table, see
+    /// `SsTableBuilder::add_block_reference`. Until the table of a reference is registered,
+    /// reading the block fails with `TableError::MissingBlockSource`. The tables are kept open as
+    /// long as this table is.
+    pub fn with_block_sources(mut self, tables: impl IntoIterator<Item = Arc<SsTable>>) -> Self {
+        self.block_sources
+            .extend(tables.into_iter().map(|table| (table.id, table)));
+        self
+    }
+
+    /// Get the table a block reference of block `block_idx` points to.
+    fn block_source(
+        &self,
+        block_idx: usize,
+        reference: &BlockRef,
+    ) -> Result<&Arc<SsTable>, TableError> {
+        self.block_sources
+            .get(&reference.table_id)
+            .ok_or(TableError::MissingBlockSource {
+                block_idx,
+                table_id: reference.table_id,
+            })
+    }
+
+    /// Reopen the table with another block cache, e.g. to hot-swap the cache configuration.
+    /// The file, the metas and the footer are kept as they are, so this does no I/O at all.
+    /// Blocks cached under the old cache are not carried over.
+    pub fn reopen(self, block_cache: Option<Arc<BlockCache>>) -> SsTable {
+        Self {
+            block_cache,
+            ..self
+        }
+    }
+
+    /// Read a block from the disk.
+    pub fn read_block(&self, block_idx: usize) -> Result<Arc<Block>, TableError> {
+        let block_data = self.read_encoded_block(block_idx)?;
+        Self::decode_block(block_idx, &block_data)
+    }
+
+    /// Read a block like `read_block`, as the next one of a sequential scan, so that it may be
+    /// served from the read-ahead buffer of the file, see `FileObject::with_readahead`.
+    pub fn read_block_sequential(&self, block_idx: usize) -> Result<Arc<Block>, TableError> {
+        if matches!(self.try_block_meta(block_idx), Ok(meta) if meta.reference.is_some()) {
+            return self.read_block(block_idx);
+        }
+        let len = self.encoded_block_len(block_idx)?;
+        let start = self.encoded_block_start(block_idx, len)?;
+        let mut block_data = Vec::with_capacity(len);
+        self.file
+            .read_sequential_into(start, len as u64, &mut block_data)
+            .map_err(TableError::from_read)?;
+        Self::decode_block(block_idx, &block_data)
+    }
+
+    /// Read a block from the disk when the caller already knows its encoded length.
+    /// Data blocks are aligned to 4196 bytes, so the block starts at `block_idx * 4196` unless
+    /// blocks before it are references or packed.
+    pub fn read_block_with_len(
+        &self,
+        block_idx: usize,
+        len: usize,
+    ) -> Result<Arc<Block>, TableError> {
+        let block_data = self.read_encoded_block_with_len(block_idx, len)?;
+        Self::decode_block(block_idx, &block_data)
+    }
+
+    /// Read a block from the disk with `options`.
+    /// With a `block_pool`, the block is decoded into a buffer taken from the pool, which goes back
+    /// to the pool once the last reference to the block is dropped.
+    pub fn read_block_with_options(
+        &self,
+        block_idx: usize,
+        options: &ReadOptions,
+    ) -> Result<Arc<Block>, TableError> {
+        let Some(pool) = &options.block_pool else {
+            return self.read_block(block_idx);
+        };
+        let mut block_data = pool.get();
+        let result = self
+            .encoded_block_len(block_idx)
+            .and_then(|len| self.read_encoded_block_into(block_idx, len, &mut block_data));
+        if let Err(err) = result {
+            pool.put(block_data);
+            return Err(err);
+        }
+        Block::try_decode_pooled(block_data, pool.clone())
+            .map(Arc::new)
+            .map_err(|err| TableError::corruption(Some(block_idx), format!("{:#}", err)))
+    }
+
+    /// Decode block `block_idx` from `bytes` fetched by the caller, e.g. with a ranged read of
+    /// `block_byte_range` from remote storage, so that the I/O layer owns the fetch. Fails with
+    /// `TableError::Corruption` if `bytes` is not as long as the block. The block is inserted into
+    /// the block cache of the table, if any, so that later reads of it are served from there.
+    pub fn decode_block_from(
+        &self,
+        block_idx: usize,
+        bytes: &[u8],
+    ) -> Result<Arc<Block>, TableError> {
+        let len = self.encoded_block_len(block_idx)?;
+        if bytes.len() != len {
+            return Err(TableError::corruption(
+                Some(block_idx),
+                format!("expected {} bytes, got {}", len, bytes.len()),
+            ));
+        }
+        let block = Self::decode_block(block_idx, bytes)?;
+        if let Some(block_cache) = &self.block_cache {
+            block_cache.insert((self.id, block_idx), block.clone());
+        }
+        Ok(block)
+    }
+
+    /// Decode a block read from the disk, checking that every entry lies within it, so that a
+    /// corrupted block fails with `TableError::Corruption` instead of panicking once read.
+    fn decode_block(block_idx: usize, block_data: &[u8]) -> Result<Arc<Block>, TableError> {
+        Block::try_decode(block_data)
+            .map(Arc::new)
+            .map_err(|err| TableError::corruption(Some(block_idx), format!("{:#}", err)))
+    }
+
+    /// Read the encoded bytes of a block from the disk, without decoding it.
+    fn read_encoded_block(&self, block_idx: usize) -> Result<Vec<u8>, TableError> {
+        let len = self.encoded_block_len(block_idx)?;
+        self.read_encoded_block_with_len(block_idx, len)
+    }
+
+    /// Get the encoded length of a block from its meta, or from the meta of the referenced block.
+    fn encoded_block_len(&self, block_idx: usize) -> Result<usize, TableError> {
+        let meta = self.try_block_meta(block_idx)?;
+        if let Some(reference) = &meta.reference {
+            let source = self.block_source(block_idx, reference)?;
+            return source.encoded_block_len(reference.block_idx);
+        }
+        Ok((meta.offset as u64).saturating_sub(meta.start()) as usize)
+    }
+
+    fn read_encoded_block_with_len(
+        &self,
+        block_idx: usize,
+        len: usize,
+    ) -> Result<Vec<u8>, TableError> {
+        let mut buf = Vec::with_capacity(len);
+        self.read_encoded_block_into(block_idx, len, &mut buf)?;
+        Ok(buf)
+    }
+
+    fn read_encoded_block_into(
+        &self,
+        block_idx: usize,
+        len: usize,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), TableError> {
+        if let Some(reference) = self.try_block_meta(block_idx)?.reference.as_ref() {
+            let source = self.block_source(block_idx, reference)?;
+            return source.read_encoded_block_into(reference.block_idx, len, buf);
+        }
+        let start = self.encoded_block_start(block_idx, len)?;
+        self.file
+            .read_into(start, len as u64, buf)
+            .map_err(TableError::from_read)
+    }
+
+    /// Get where a block of `len` encoded bytes starts in the file, checking that it lies within
+    /// the data section.
+    fn encoded_block_start(&self, block_idx: usize, len: usize) -> Result<u64, TableError> {
+        let meta = self.try_block_meta(block_idx)?;
+        let start = meta.start();
+        if !(2..=4196).contains(&len) || start + len as u64 > self.block_meta_offset as u64 {
+            return Err(TableError::corruption(
+                Some(block_idx),
+                format!("invalid length {}", len),
+            ));
+        }
+        Ok(start)
     }
 
     /// Read a block from disk, with block cache. (Day 4)
     pub fn read_block_cached(&self, block_idx: usize) -> Result<Arc<Block>> {
-        unimplemented!()
+        match &self.block_cache {
+            Some(block_cache) => block_cache
+                .try_get_with((self.id, block_idx), || self.read_block(block_idx))
+                .map_err(|err| anyhow!("{}", err)),
+            None => Ok(self.read_block(block_idx)?),
+        }
+    }
+
+    /// Read a block like `read_block_cached`, with `hint` deciding whether a block missing from
+    /// the cache is admitted to it, see `CacheHint`.
+    pub fn read_block_cached_with_hint(
+        &self,
+        block_idx: usize,
+        hint: CacheHint,
+    ) -> Result<Arc<Block>> {
+        match (&self.block_cache, hint) {
+            (Some(block_cache), CacheHint::Scan) => match block_cache.get(&(self.id, block_idx)) {
+                Some(block) => Ok(block),
+                None => Ok(self.read_block(block_idx)?),
+            },
+            _ => self.read_block_cached(block_idx),
+        }
+    }
+
+    /// Get the value of `key` from the table, `None` if the key is not in the table.
+    /// A deleted key is returned with its empty value, it is up to the caller to interpret it.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        match (self.first_key(), self.last_key()) {
+            (Some(first_key), Some(last_key)) if first_key <= key && key <= last_key => {}
+            _ => return Ok(None),
+        }
+        let block = self.read_block(self.find_block_idx(key))?;
+        let iter = BlockIterator::create_and_seek_to_key(block, key);
+        if iter.is_valid() && iter.key() == key {
+            return Ok(Some(Bytes::copy_from_slice(iter.value())));
+        }
+        Ok(None)
+    }
+
+    /// Get the value of `key` like `get`, but without reading the whole block. Only the offset
+    /// section of the block is read, then the entries probed by a binary search, one read each.
+    /// This issues more reads but transfers far fewer bytes, which pays off on remote storage.
+    /// A block with delta-encoded keys, or stored in another table, is read whole.
+    pub fn get_with_partial_read(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        match (self.first_key(), self.last_key()) {
+            (Some(first_key), Some(last_key)) if first_key <= key && key <= last_key => {}
+            _ => return Ok(None),
+        }
+        let block_idx = self.find_block_idx(key);
+        let meta = self.try_block_meta(block_idx)?;
+        let start = meta.start();
+        let len = (meta.offset as u64).saturating_sub(start);
+        if meta.reference.is_none() && !(2..=4196).contains(&len) {
+            bail!("invalid length {} for block {}", len, block_idx);
+        }
+        let num_of_elements = match meta.reference {
+            Some(_) => Vec::new(),
+            None => self.file.read(start + len - 2, 2)?,
+        };
+        if meta.reference.is_some()
+            || block::is_delta_encoded(&num_of_elements)
+            || block::has_seqs(&num_of_elements)
+        {
+            // the keys cannot be compared without rebuilding them from a restart, and the entries
+            // with sequence numbers are left to the block to parse
+            let block = self.read_block(block_idx)?;
+            let iter = BlockIterator::create_and_seek_to_key(block, key);
+            if iter.is_valid() && iter.key() == key {
+                return Ok(Some(Bytes::copy_from_slice(iter.value())));
+            }
+            return Ok(None);
+        }
+        let num_of_elements = u16::from_be_bytes([num_of_elements[0], num_of_elements[1]]) as u64;
+        if len < 2 + num_of_elements * 2 {
+            bail!(
+                "block {} too small for {} entries",
+                block_idx,
+                num_of_elements
+            );
+        }
+        let data_len = len - 2 - num_of_elements * 2;
+        // the offsets are stored in reverse order
+        let offsets = self.file.read(start + data_len, num_of_elements * 2)?;
+        let entry_range = |idx: usize| {
+            let offset_at = |idx: usize| {
+                let pos = offsets.len() - 2 - idx * 2;
+                u16::from_be_bytes([offsets[pos], offsets[pos + 1]]) as u64
+            };
+            let end = if idx + 1 < num_of_elements as usize {
+                offset_at(idx + 1)
+            } else {
+                data_len
+            };
+            (offset_at(idx), end)
+        };
+        let mut low = 0;
+        let mut high = num_of_elements as usize;
+        while low < high {
+            let mid = (low + high) / 2;
+            let (entry_start, entry_end) = entry_range(mid);
+            if entry_start >= entry_end || entry_end > data_len {
+                bail!("entry {} of block {} out of range", mid, block_idx);
+            }
+            let entry = self
+                .file
+                .read(start + entry_start, entry_end - entry_start)?;
+            // only the entry holding `key` has its value decoded
+            match block::decode_raw_key(&entry)?.cmp(key) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return Ok(Some(block::decode_raw_entry(&entry)?.1)),
+            }
+        }
+        Ok(None)
+    }
+
+    /// Get the smallest key of the table, `None` if the table is empty.
+    pub fn first_key(&self) -> Option<&Bytes> {
+        if let Some(disk_index) = &self.disk_index {
+            return disk_index.first_key();
+        }
+        self.block_metas().first().map(|meta| &meta.first_key)
+    }
+
+    /// Get the largest key of the table, `None` if the table is empty.
+    pub fn last_key(&self) -> Option<&Bytes> {
+        if let Some(disk_index) = &self.disk_index {
+            return disk_index.last_key();
+        }
+        self.block_metas().last().map(|meta| &meta.last_key)
     }
 
     /// Find the block that may contain `key`.
     /// Note: You may want to make use of the `first_key` stored in `BlockMeta`.
     /// You may also assume the key-value pairs stored in each consecutive block are sorted.
+    ///
+    /// With a sparse index, the indexed blocks are binary searched first, then the blocks of the
+    /// group after the indexed one are checked in turn, reading each one to get its first key. If
+    /// such a read fails, that block is returned, so the error shows up once the caller reads it.
     pub fn find_block_idx(&self, key: &[u8]) -> usize {
-        unimplemented!()
+        if let Some(disk_index) = &self.disk_index {
+            return disk_index.find_block_idx(&self.file, key);
+        }
+        let index_sparsity = self.index_sparsity();
+        if index_sparsity == 1 {
+            return self
+                .block_metas()
+                .partition_point(|meta| meta.first_key <= key)
+                .saturating_sub(1);
+        }
+        let num_of_blocks = self.block_metas().len();
+        let mut low = 0;
+        let mut high = (num_of_blocks + index_sparsity - 1) / index_sparsity;
+        while low < high {
+            let mid = (low + high) / 2;
+            if self.block_metas()[mid * index_sparsity].first_key <= key {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        if low == 0 {
+            return 0;
+        }
+        let start = (low - 1) * index_sparsity;
+        let end = num_of_blocks.min(start + index_sparsity);
+        for block_idx in start + 1..end {
+            match self.block_first_key(block_idx) {
+                Ok(first_key) if first_key <= key => {}
+                Ok(_) => return block_idx - 1,
+                Err(_) => return block_idx,
+            }
+        }
+        end - 1
+    }
+
+    /// Get every how many blocks the metas keep the keys of their block, 1 for a dense index, see
+    /// `SsTableBuilder::set_index_sparsity`.
+    pub fn index_sparsity(&self) -> usize {
+        (self.footer.index_sparsity as usize).max(1)
+    }
+
+    /// Check whether the meta of a block keeps its first and last key. A sparse index keeps them
+    /// for every `index_sparsity`-th block, and for the last block so that the range of the table
+    /// is known without a read.
+    pub fn is_indexed(&self, block_idx: usize) -> bool {
+        block_idx % self.index_sparsity() == 0 || block_idx + 1 == self.num_of_blocks()
+    }
+
+    /// Get the first key of a block, from its meta if it is indexed, else from the block itself.
+    fn block_first_key(&self, block_idx: usize) -> Result<Bytes> {
+        if self.is_indexed(block_idx) {
+            return Ok(self.block_metas()[block_idx].first_key.clone());
+        }
+        let block = self.read_block_cached(block_idx)?;
+        if block.num_of_entries() == 0 {
+            return Err(TableError::corruption(
+                Some(block_idx),
+                "empty block left out of the index",
+            )
+            .into());
+        }
+        Ok(Bytes::copy_from_slice(block.key_at(0)))
+    }
+
+    /// Estimate the number of entries with a key in `[lower, upper]` without scanning the range.
+    /// The blocks fully inside the range are counted from `BlockMeta::num_entries`, and only the
+    /// two blocks holding the bounds are read, to binary search the bounds in them, so the result
+    /// is exact at the cost of at most two block reads. Tombstones are counted as entries.
+    pub fn estimate_range_count(&self, lower: &[u8], upper: &[u8]) -> Result<usize> {
+        match (self.first_key(), self.last_key()) {
+            (Some(first_key), Some(last_key))
+                if lower <= upper && lower <= last_key && first_key <= upper => {}
+            _ => return Ok(0),
+        }
+        let lower_block = self.find_block_idx(lower);
+        let upper_block = self.find_block_idx(upper);
+        // the number of entries before `lower` in its block, and up to `upper` in its block
+        let (Ok(skipped) | Err(skipped)) = self.read_block(lower_block)?.search(lower);
+        let taken = match self.read_block(upper_block)?.search(upper) {
+            Ok(idx) => idx + 1,
+            Err(idx) => idx,
+        };
+        let whole: usize = self.block_metas()[lower_block..upper_block]
+            .iter()
+            .map(|meta| meta.num_entries as usize)
+            .sum();
+        Ok(whole + taken - skipped)
+    }
+
+    /// Check whether the table holds any key in `[lower, upper)`, reading at most one block.
+    /// The first key at or after `lower` is either in the block that may hold `lower`, which is
+    /// only read when its meta cannot settle the answer, or is the first key of the next block.
+    /// With a sparse index, the metas settle less, so a second block may be read on top of the
+    /// ones read by `find_block_idx`.
+    pub fn contains_range(&self, lower: &[u8], upper: &[u8]) -> Result<bool> {
+        match (self.first_key(), self.last_key()) {
+            (Some(_), Some(last_key)) if lower < upper && lower <= last_key => {}
+            _ => return Ok(false),
+        }
+        let block_idx = self.find_block_idx(lower);
+        let meta = &self.block_metas()[block_idx];
+        if self.is_indexed(block_idx) {
+            if meta.last_key < lower {
+                // `lower` falls between this block and the next one
+                return Ok(self.block_first_key(block_idx + 1)? < upper);
+            }
+            if meta.first_key >= lower || meta.last_key < upper {
+                return Ok(meta.first_key < upper);
+            }
+        }
+        let block = self.read_block(block_idx)?;
+        let (Ok(idx) | Err(idx)) = block.search(lower);
+        if idx == block.num_of_entries() {
+            // only for a block left out of the index, which is never the last one
+            return Ok(self.block_first_key(block_idx + 1)? < upper);
+        }
+        Ok(block.key_at(idx) < upper)
+    }
+
+    /// Look up a batch of keys, returning the values in the same order as `keys`.
+    /// The keys are probed in sorted order so that keys landing in the same block share a single
+    /// block read. The tables have no Bloom filter, their filter section being left empty, so a
+    /// key is only ruled out without a read when it falls outside the key range of its block.
+    pub fn multi_get(&self, keys: &[&[u8]]) -> Result<Vec<Option<Bytes>>> {
+        let mut results = vec![None; keys.len()];
+        let Some(first_key) = self.first_key() else {
+            return Ok(results);
+        };
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_by_key(|&idx| keys[idx]);
+        let mut cur_block: Option<(usize, Arc<Block>)> = None;
+        for idx in order {
+            let key = keys[idx];
+            if key < first_key.as_ref() {
+                continue;
+            }
+            let block_idx = self.find_block_idx(key);
+            if self.is_indexed(block_idx) && key > self.block_metas()[block_idx].last_key.as_ref() {
+                continue;
+            }
+            let block = match &cur_block {
+                Some((cur_idx, block)) if *cur_idx == block_idx => block.clone(),
+                _ => {
+                    let block = self.read_block(block_idx)?;
+                    cur_block = Some((block_idx, block.clone()));
+                    block
+                }
+            };
+            let iter = BlockIterator::create_and_seek_to_key(block, key);
+            if iter.is_valid() && iter.key() == key {
+                results[idx] = Some(Bytes::copy_from_slice(iter.value()));
+            }
+        }
+        Ok(results)
+    }
+
+    /// Call `f` with every key of the table in order, e.g. to build an in-memory index. Each
+    /// block is read once, without admitting it to the block cache, and the keys are passed in
+    /// place in the block, so no value is ever copied or decompressed.
+    pub fn for_each_key(&self, mut f: impl FnMut(&[u8])) -> Result<()> {
+        for block_idx in 0..self.num_of_blocks() {
+            let block = self.read_block_cached_with_hint(block_idx, CacheHint::Scan)?;
+            for idx in 0..block.num_of_entries() {
+                f(block.key_at(idx));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get every key of the table in order, see `for_each_key` to go over them without
+    /// collecting them.
+    pub fn all_keys(&self) -> Result<Vec<Bytes>> {
+        let mut keys = Vec::with_capacity(self.footer.entry_count as usize);
+        self.for_each_key(|key| keys.push(Bytes::copy_from_slice(key)))?;
+        Ok(keys)
+    }
+
+    /// Sample roughly `n` evenly-spaced keys from the table, e.g. to build a key histogram for
+    /// compaction planning. The samples are the `first_key`s of evenly chosen blocks, so no block
+    /// is read, and at most one key is returned per block. With a sparse index, only indexed
+    /// blocks are chosen, so fewer keys may be returned.
+    pub fn sample_keys(&self, n: usize) -> Vec<Bytes> {
+        self.evenly_indexed_blocks(0..n, n)
+            .into_iter()
+            .map(|block_idx| self.block_metas()[block_idx].first_key.clone())
+            .collect()
+    }
+
+    /// Get the indexed blocks at or before the `i * num_of_blocks / n`-th blocks for `i` in
+    /// `range`, without duplicates.
+    fn evenly_indexed_blocks(&self, range: std::ops::Range<usize>, n: usize) -> Vec<usize> {
+        let num_of_blocks = self.block_metas().len();
+        let n = n.min(num_of_blocks);
+        let index_sparsity = self.index_sparsity();
+        let mut blocks: Vec<usize> = range
+            .take_while(|&i| i < n)
+            .map(|i| {
+                let block_idx = i * num_of_blocks / n;
+                block_idx - block_idx % index_sparsity
+            })
+            .collect();
+        blocks.dedup();
+        blocks
+    }
+
+    /// Get `n - 1` keys that split the table into `n` ranges of roughly equal size, e.g. to scan
+    /// the table in parallel. The split points are the `first_key`s of evenly chosen blocks, so the
+    /// ranges differ by at most one block and no block is read. Fewer points are returned if the
+    /// table has fewer than `n` blocks, as a block is never split. With a sparse index, only
+    /// indexed blocks are chosen, so the ranges are less even and there may be fewer points.
+    pub fn split_points(&self, n: usize) -> Vec<Bytes> {
+        self.evenly_indexed_blocks(1..n, n)
+            .into_iter()
+            .filter(|&block_idx| block_idx > 0)
+            .map(|block_idx| self.block_metas()[block_idx].first_key.clone())
+            .collect()
+    }
+
+    /// Get the meta of a data block, or `None` if `idx` is out of range.
+    pub fn block_meta(&self, idx: usize) -> Option<&BlockMeta> {
+        self.block_metas().get(idx)
+    }
+
+    /// Get the index of the data block that holds the byte at `offset` in the file, e.g. to locate
+    /// an offset from a corruption report. The padding after a block counts as part of it, as each
+    /// block takes a 4196-byte slot unless packed. Returns `None` for offsets in the meta blocks,
+    /// the footer, or the padding before an aligned block. Block references take no space, so no
+    /// offset maps to them.
+    pub fn block_for_offset(&self, offset: u64) -> Option<usize> {
+        if offset >= self.block_meta_offset as u64 {
+            return None;
+        }
+        let metas = self.block_metas();
+        // the blocks start in order, a reference where the next stored block could start
+        let end = metas.partition_point(|meta| meta.start() <= offset);
+        let block_idx = metas[..end]
+            .iter()
+            .rposition(|meta| meta.reference.is_none())?;
+        let meta = &metas[block_idx];
+        (offset < meta.data_end(meta.start) as u64).then_some(block_idx)
+    }
+
+    /// Get the range of bytes of the file holding the encoded block `block_idx`, without the
+    /// alignment padding after it, e.g. for a remote fetcher to issue a single ranged read and pass
+    /// the bytes to `Block::decode`. Returns `None` if there is no such block, if the block is a
+    /// reference to another table, whose bytes are not in this file, or if its meta is corrupted.
+    pub fn block_byte_range(&self, block_idx: usize) -> Option<std::ops::Range<u64>> {
+        let meta = self.block_metas().get(block_idx)?;
+        if meta.reference.is_some() {
+            return None;
+        }
+        let start = meta.start();
+        let end = meta.offset as u64;
+        (start < end && end - start <= 4196 && end <= self.block_meta_offset as u64)
+            .then_some(start..end)
+    }
+
+    /// Check every data block of the table, spreading the blocks over one thread per core: each
+    /// block must decode cleanly, hold as many entries as its meta says, with strictly increasing
+    /// keys matching the keys of its meta, all after the last key of the block before it.
+    /// Returns the error of the first corrupted block, as a `TableError::Corruption` naming it.
+    pub fn verify_parallel(&self) -> Result<(), TableError> {
+        let num_of_blocks = self.num_of_blocks();
+        let threads = std::thread::available_parallelism()
+            .map_or(1, |threads| threads.get())
+            .min(num_of_blocks);
+        let next_block = AtomicUsize::new(0);
+        // the failed block with the smallest index so far, blocks past it need no check
+        let first_error: Mutex<Option<(usize, TableError)>> = Mutex::new(None);
+        std::thread::scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|| loop {
+                    let block_idx = next_block.fetch_add(1, Ordering::Relaxed);
+                    if block_idx >= num_of_blocks
+                        || matches!(&*first_error.lock(), Some((idx, _)) if *idx < block_idx)
+                    {
+                        break;
+                    }
+                    if let Err(err) = self.verify_block(block_idx) {
+                        let mut first_error = first_error.lock();
+                        if first_error
+                            .as_ref()
+                            .map_or(true, |(idx, _)| block_idx < *idx)
+                        {
+                            *first_error = Some((block_idx, err));
+                        }
+                    }
+                });
+            }
+        });
+        match first_error.into_inner() {
+            Some((_, err)) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    /// Check the CRC32 of every stored data block against the checksums recorded with
+    /// `SsTableBuilder::set_block_checksums`, for integrity scrubbing. The blocks are read in
+    /// order, with the boundaries taken from the metas, and served from the read-ahead buffer of
+    /// the file if it has one, see `FileObject::with_readahead`. Unlike `verify_parallel`, no
+    /// block is decoded. Referenced blocks are left to the table they are stored in.
+    /// Fails with `TableError::Corruption` naming the first block whose checksum does not match,
+    /// and with `TableError::Unsupported` if the table was built without checksums.
+    pub fn verify_checksums_only(&self) -> Result<(), TableError> {
+        let Some(mut checksums) = self.property(BLOCK_CHECKSUMS_PROPERTY) else {
+            return Err(TableError::Unsupported("table built without block checksums"));
+        };
+        if checksums.len() != self.num_of_blocks() * 4 {
+            return Err(TableError::corruption(
+                None,
+                format!(
+                    "{} bytes of block checksums for {} blocks",
+                    checksums.len(),
+                    self.num_of_blocks()
+                ),
+            ));
+        }
+        let mut block_data = Vec::new();
+        for (block_idx, meta) in self.block_metas().iter().enumerate() {
+            let expected = checksums.get_u32();
+            if meta.reference.is_some() {
+                continue;
+            }
+            let len = (meta.offset as u64).saturating_sub(meta.start());
+            let start = self.encoded_block_start(block_idx, len as usize)?;
+            self.file
+                .read_sequential_into(start, len, &mut block_data)
+                .map_err(TableError::from_read)?;
+            let actual = compat::crc32(&block_data);
+            if actual != expected {
+                return Err(TableError::corruption(
+                    Some(block_idx),
+                    format!("checksum {:#010x}, expected {:#010x}", actual, expected),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check a single data block, see `verify_parallel`.
+    fn verify_block(&self, block_idx: usize) -> Result<(), TableError> {
+        let corruption = |detail: String| TableError::corruption(Some(block_idx), detail);
+        let block_data = self.read_encoded_block(block_idx)?;
+        let block =
+            Block::try_decode(&block_data).map_err(|err| corruption(format!("{:#}", err)))?;
+        let meta = &self.block_metas()[block_idx];
+        let num_of_entries = block.num_of_entries();
+        if num_of_entries != meta.num_entries as usize {
+            return Err(corruption(format!(
+                "{} entries but its meta has {}",
+                num_of_entries, meta.num_entries
+            )));
+        }
+        if num_of_entries == 0 {
+            return Ok(());
+        }
+        for idx in 1..num_of_entries {
+            if block.key_at(idx - 1) >= block.key_at(idx) {
+                return Err(corruption(format!("entry {} out of order", idx)));
+            }
+        }
+        let (first_key, last_key) = (block.key_at(0), block.key_at(num_of_entries - 1));
+        if self.is_indexed(block_idx) && (first_key != meta.first_key || last_key != meta.last_key)
+        {
+            return Err(corruption("keys do not match its meta".to_string()));
+        }
+        if block_idx > 0 && self.is_indexed(block_idx - 1) {
+            let prev_last_key = &self.block_metas()[block_idx - 1].last_key;
+            if first_key <= prev_last_key {
+                return Err(corruption(format!(
+                    "starts with {:?}, not after the last key {:?} of the block before",
+                    Bytes::copy_from_slice(first_key),
+                    prev_last_key
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the metas of all data blocks.
+    /// A table opened with a disk index loads them all on the first call, and panics if the meta
+    /// section, which was fully decoded when the table was opened, can no longer be read.
+    pub fn block_metas(&self) -> &[BlockMeta] {
+        if self.disk_index.is_some() {
+            return self.block_metas.get_or_init(|| {
+                let start = self.footer.block_meta_offset as u64;
+                self.file
+                    .read(start, self.footer.bloom_offset as u64 - start)
+                    .map_err(TableError::from_read)
+                    .and_then(|buf| BlockMeta::decode_block_meta(&buf[..]))
+                    .expect("the meta section of a disk-indexed table can be read")
+            });
+        }
+        self.block_metas
+            .get()
+            .expect("the metas are loaded before the table is handed out")
+    }
+
+    /// Get the meta of a block, from the disk index of the table if it has one.
+    pub(crate) fn try_block_meta(
+        &self,
+        block_idx: usize,
+    ) -> Result<Cow<'_, BlockMeta>, TableError> {
+        if let Some(disk_index) = &self.disk_index {
+            return disk_index.block_meta(&self.file, block_idx).map(Cow::Owned);
+        }
+        match self.block_metas().get(block_idx) {
+            Some(meta) => Ok(Cow::Borrowed(meta)),
+            None => Err(TableError::BlockIndexOutOfRange {
+                block_idx,
+                num_of_blocks: self.num_of_blocks(),
+            }),
+        }
+    }
+
+    /// Get the disk index of the table, if it was opened with one.
+    pub fn disk_index(&self) -> Option<&DiskIndex> {
+        self.disk_index.as_ref()
+    }
+
+    /// Re-pack the table into a new SSTable with a different target block size.
+    /// Every entry is copied as-is, including tombstones, so the new table holds the same data.
+    /// As data blocks are aligned to 4196 bytes, `new_block_size` cannot exceed 4196: a larger
+    /// one, e.g. 16 KB, fails without writing anything.
+    pub fn rebuild(
+        &self,
+        new_block_size: usize,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        path: impl AsRef<Path>,
+    ) -> Result<SsTable> {
+        if new_block_size > 4196 {
+            bail!(
+                "block size {} exceeds the 4196-byte block slots",
+                new_block_size
+            );
+        }
+        let mut builder = SsTableBuilder::new(new_block_size);
+        for block_idx in 0..self.num_of_blocks() {
+            let mut iter = BlockIterator::create_and_seek_to_first(self.read_block(block_idx)?);
+            while iter.is_valid() {
+                builder.try_add(iter.key(), iter.value())?;
+                iter.next();
+            }
+        }
+        builder.build(id, block_cache, path)
+    }
+
+    /// Write a new SSTable holding the blocks of this table followed by new blocks with a target
+    /// size of `block_size` built from `new_entries`, e.g. for append-heavy workloads. The blocks
+    /// of this table are copied verbatim, with a reference resolved to the block it points to, so
+    /// only the new entries are encoded. Every key of `new_entries` must sort after `last_key`.
+    pub fn append_blocks(
+        &self,
+        mut new_entries: impl StorageIterator,
+        block_size: usize,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        path: impl AsRef<Path>,
+    ) -> Result<SsTable> {
+        let mut builder = SsTableBuilder::new(block_size);
+        for (block_idx, meta) in self.block_metas().iter().enumerate() {
+            let encoded = Bytes::from(self.read_encoded_block(block_idx)?);
+            if self.is_indexed(block_idx) {
+                builder.add_encoded_block(encoded, &meta.first_key, &meta.last_key)?;
+            } else {
+                // the meta of a block left out of a sparse index lacks its keys, which the new
+                // table indexes
+                let block = self.read_block(block_idx)?;
+                let last_idx = block.num_of_entries() - 1;
+                builder.add_encoded_block(encoded, block.key_at(0), block.key_at(last_idx))?;
+            }
+        }
+        let mut last_key = self.last_key().cloned();
+        while new_entries.is_valid() {
+            let key = new_entries.key();
+            if last_key
+                .as_ref()
+                .map_or(false, |last_key| key <= &last_key[..])
+            {
+                bail!(
+                    "appended key {:?} does not sort after {:?}",
+                    Bytes::copy_from_slice(key),
+                    last_key.unwrap()
+                );
+            }
+            builder.try_add(key, new_entries.value())?;
+            last_key = Some(Bytes::copy_from_slice(key));
+            new_entries.next()?;
+        }
+        builder.build(id, block_cache, path)
+    }
+
+    /// Create a cursor over the table, which combines seeking and scanning in one object.
+    pub fn cursor(self: &Arc<Self>) -> SsTableCursor {
+        SsTableCursor::new(self.clone())
+    }
+
+    /// Create an iterator over the whole table that reads every block through `read_block_cached`,
+    /// for scans likely to run concurrently over the same table. The cache loads a missing block
+    /// once even when several scans miss it at the same time, the others waiting for that read,
+    /// so concurrent scans of a cold table read each block from the disk only once. Without a
+    /// block cache, this is a plain `SsTableIterator`.
+    pub fn scan_cached(self: &Arc<Self>) -> Result<SsTableIterator> {
+        SsTableIterator::create_cached_and_seek_to_first(self.clone())
+    }
+
+    /// Scan the whole table, yielding each entry along with the index of the block holding it.
+    /// A failed block read is yielded as an error and ends the scan.
+    pub fn scan_with_block_idx(
+        self: &Arc<Self>,
+    ) -> impl Iterator<Item = Result<(usize, Bytes, Bytes)>> {
+        let table = self.clone();
+        let mut block_idx = 0;
+        let mut iter: Option<BlockIterator> = None;
+        std::iter::from_fn(move || loop {
+            if let Some(block_iter) = &mut iter {
+                if block_iter.is_valid() {
+                    let entry = (
+                        block_idx,
+                        Bytes::copy_from_slice(block_iter.key()),
+                        Bytes::copy_from_slice(block_iter.value()),
+                    );
+                    block_iter.next();
+                    return Some(Ok(entry));
+                }
+                block_idx += 1;
+            }
+            if block_idx >= table.num_of_blocks() {
+                return None;
+            }
+            match table.read_block(block_idx) {
+                Ok(block) => iter = Some(BlockIterator::create_and_seek_to_first(block)),
+                Err(err) => {
+                    block_idx = table.num_of_blocks();
+                    iter = None;
+                    return Some(Err(err.into()));
+                }
+            }
+        })
+    }
+
+    /// Scan the whole table merged with `overlay`, e.g. writes not yet flushed to a table, for
+    /// reads that must see them. An entry of `overlay` shadows the entry of the table with the same
+    /// key: a value replaces it, and `None` deletes it, so that the key is skipped. The overlay is
+    /// copied, which is cheap for the small sets of pending writes it is meant for. A failed block
+    /// read is yielded as an error and ends the scan.
+    pub fn scan_with_overlay(
+        self: &Arc<Self>,
+        overlay: &BTreeMap<Bytes, Option<Bytes>>,
+    ) -> impl Iterator<Item = Result<(Bytes, Bytes)>> {
+        let mut table = self
+            .scan_with_block_idx()
+            .map(|entry| entry.map(|(_, key, value)| (key, value)))
+            .peekable();
+        let mut overlay = overlay.clone().into_iter().peekable();
+        std::iter::from_fn(move || loop {
+            let order = match (table.peek(), overlay.peek()) {
+                (None, None) => return None,
+                (Some(Err(_)), _) | (Some(Ok(_)), None) => return table.next(),
+                (None, Some(_)) => std::cmp::Ordering::Greater,
+                (Some(Ok((key, _))), Some((overlay_key, _))) => key.cmp(overlay_key),
+            };
+            match order {
+                std::cmp::Ordering::Less => return table.next(),
+                std::cmp::Ordering::Equal => {
+                    table.next();
+                }
+                std::cmp::Ordering::Greater => {}
+            }
+            if let Some((key, Some(value))) = overlay.next() {
+                return Some(Ok((key, value)));
+            }
+        })
+    }
+
+    /// Scan the entries with a key in `[lower, upper]`, yielding only those for which
+    /// `pred(key, value)` holds. The predicate sees the entries in place in the block, so rejected
+    /// entries are never copied. A failed block read is yielded as an error and ends the scan.
+    pub fn scan_filtered(
+        self: &Arc<Self>,
+        lower: &[u8],
+        upper: &[u8],
+        pred: impl Fn(&[u8], &[u8]) -> bool,
+    ) -> impl Iterator<Item = Result<(Bytes, Bytes)>> {
+        let table = self.clone();
+        let upper = Bytes::copy_from_slice(upper);
+        let mut block_idx = self.find_block_idx(lower);
+        let mut lower = Some(Bytes::copy_from_slice(lower));
+        let mut iter: Option<BlockIterator> = None;
+        std::iter::from_fn(move || loop {
+            if let Some(block_iter) = &mut iter {
+                while block_iter.is_valid() {
+                    let (key, value) = (block_iter.key(), block_iter.value());
+                    if key > &upper[..] {
+                        block_idx = table.num_of_blocks();
+                        break;
+                    }
+                    let entry = pred(key, value)
+                        .then(|| (Bytes::copy_from_slice(key), Bytes::copy_from_slice(value)));
+                    block_iter.next();
+                    if let Some(entry) = entry {
+                        return Some(Ok(entry));
+                    }
+                }
+                block_idx += 1;
+                iter = None;
+            }
+            if block_idx >= table.num_of_blocks() {
+                return None;
+            }
+            match table.read_block(block_idx) {
+                Ok(block) => {
+                    iter = Some(match lower.take() {
+                        Some(lower) => BlockIterator::create_and_seek_to_key(block, &lower),
+                        None => BlockIterator::create_and_seek_to_first(block),
+                    })
+                }
+                Err(err) => {
+                    block_idx = table.num_of_blocks();
+                    return Some(Err(err.into()));
+                }
+            }
+        })
+    }
+
+    /// Scan the entries whose key starts with `prefix`, from the largest key down to the smallest.
+    /// The scan starts right before the smallest key past all the keys with `prefix`, found with a
+    /// single block search, then walks the entries and the blocks backwards until a key no longer
+    /// starts with `prefix`. A failed block read is yielded as an error and ends the scan.
+    pub fn scan_prefix_rev(
+        self: &Arc<Self>,
+        prefix: &[u8],
+    ) -> impl Iterator<Item = Result<(Bytes, Bytes)>> {
+        let table = self.clone();
+        let prefix = Bytes::copy_from_slice(prefix);
+        // the current block and the number of its entries left to yield, from the last one
+        let mut cur: Option<(usize, Arc<Block>, usize)> = None;
+        let mut done = self.block_metas().is_empty();
+        std::iter::from_fn(move || loop {
+            if done {
+                return None;
+            }
+            let block_idx = match &mut cur {
+                Some((_, block, end)) if *end > 0 => {
+                    *end -= 1;
+                    let (key, value) = match block::decode_raw_entry(block.raw_entry(*end)) {
+                        Ok(entry) => entry,
+                        Err(err) => {
+                            done = true;
+                            return Some(Err(err.into()));
+                        }
+                    };
+                    if !key.starts_with(&prefix) {
+                        done = true;
+                        return None;
+                    }
+                    return Some(Ok((Bytes::copy_from_slice(key), value)));
+                }
+                Some((0, _, _)) => {
+                    done = true;
+                    return None;
+                }
+                Some((block_idx, _, _)) => *block_idx - 1,
+                // the block holding the smallest key past the prefix, or the last block if no key
+                // can be past it
+                None => match prefix_successor(&prefix) {
+                    Some(successor) => table.find_block_idx(&successor),
+                    None => table.num_of_blocks() - 1,
+                },
+            };
+            let block = match table.read_block(block_idx) {
+                Ok(block) => block,
+                Err(err) => {
+                    done = true;
+                    return Some(Err(err.into()));
+                }
+            };
+            let end = match (&cur, prefix_successor(&prefix)) {
+                (None, Some(successor)) => {
+                    let (Ok(idx) | Err(idx)) = block.search(&successor);
+                    idx
+                }
+                _ => block.num_of_entries(),
+            };
+            cur = Some((block_idx, block, end));
+        })
+    }
+
+    /// Get the ratio of the stored size of the data blocks to their size with every value stored
+    /// plain, from the totals recorded when the table was built: below 1.0 when compressing the
+    /// values paid off, 1.0 when nothing was compressed or the table is empty.
+    pub fn compression_ratio(&self) -> f64 {
+        if self.footer.uncompressed_bytes == 0 {
+            return 1.0;
+        }
+        self.footer.compressed_bytes as f64 / self.footer.uncompressed_bytes as f64
+    }
+
+    /// Get the total encoded length of the data blocks as stored, without the alignment padding,
+    /// from the footer.
+    pub fn data_size(&self) -> u64 {
+        self.footer.compressed_bytes
+    }
+
+    /// Get the total encoded length of the data blocks with every value stored plain, from the
+    /// footer, e.g. to estimate the memory the decoded blocks take. It equals `data_size` when
+    /// no value is compressed.
+    pub fn uncompressed_data_size(&self) -> u64 {
+        self.footer.uncompressed_bytes
+    }
+
+    /// Get the encoded length of each data block as stored, without its alignment padding, e.g.
+    /// to spot tables with tiny or oversized blocks. The lengths add up to the `compressed_bytes`
+    /// of the footer. A block reference takes no space in the table, so its length is 0.
+    pub fn block_size_histogram(&self) -> Vec<u32> {
+        self.block_metas()
+            .iter()
+            .map(|meta| match meta.reference {
+                Some(_) => 0,
+                None => (meta.offset as u64).saturating_sub(meta.start()) as u32,
+            })
+            .collect()
+    }
+
+    /// Get the size of the file holding the table.
+    pub fn table_size(&self) -> u64 {
+        self.file.size()
+    }
+
+    /// Get the id of the table.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Get the value of a user-defined property of the table, `None` if it was not set, see
+    /// `SsTableBuilder::set_property`.
+    pub fn property(&self, key: &str) -> Option<&[u8]> {
+        self.properties
+            .get()
+            .expect("the properties are loaded before the table is handed out")
+            .get(key)
+            .map(|value| value.as_ref())
+    }
+
+    /// Get the level of the table set with `SsTableBuilder::set_level`, `None` if it was not set
+    /// or the `level` property is not a level.
+    pub fn level(&self) -> Option<u32> {
+        let level = self.property(LEVEL_PROPERTY)?;
+        Some(u32::from_be_bytes(level.try_into().ok()?))
+    }
+
+    /// Get the footer of the table.
+    pub fn footer(&self) -> &Footer {
+        &self.footer
+    }
+
+    /// Get the approximate number of heap bytes held by the table: the block metas with their keys,
+    /// or the chunk heads of its disk index, plus the file content if it is resident in memory,
+    /// see `FileObject::resident_size`.
+    pub fn memory_footprint(&self) -> usize {
+        let metas_size = self.block_metas.get().map_or(0, |metas| {
+            metas.capacity() * std::mem::size_of::<BlockMeta>()
+                + metas
+                    .iter()
+                    .map(|meta| meta.first_key.len() + meta.last_key.len())
+                    .sum::<usize>()
+        });
+        let disk_index_size = self
+            .disk_index
+            .as_ref()
+            .map_or(0, |disk_index| disk_index.memory_footprint());
+        metas_size + disk_index_size + self.file.resident_size() as usize
     }
 
     /// Get number of data blocks.
     pub fn num_of_blocks(&self) -> usize {
-        unimplemented!()
+        if let Some(disk_index) = &self.disk_index {
+            return disk_index.num_of_blocks();
+        }
+        self.block_metas().len()
     }
 }
 
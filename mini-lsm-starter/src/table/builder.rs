@@ -1,27 +1,100 @@
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 
 use super::FileObject;
-use crate::block::Block;
 use crate::block::BlockBuilder;
+use crate::block::BlockIterator;
+use crate::block::{self, Block};
+use crate::iterators::StorageIterator;
 use bytes::Bytes;
 
-use super::{BlockMeta, SsTable};
+use super::compat::crc32;
+use super::properties::{encode_properties, BLOCK_CHECKSUMS_PROPERTY, LEVEL_PROPERTY};
+use super::{align_up, BlockMeta, BlockRef, Footer, SsTable, TableError, FOOTER_SIZE};
 use crate::lsm_storage::BlockCache;
 
+/// Write the stored block `encoded` whose meta is `meta` to `writer`, which is at `data_end` of
+/// the data section, padding it before the block up to its start and, unless the block is packed,
+/// after it up to the end of its slot.
+fn write_block(
+    writer: &mut impl Write,
+    pad_byte: u8,
+    data_end: u32,
+    encoded: &[u8],
+    meta: &BlockMeta,
+) -> std::io::Result<()> {
+    writer.write_all(&vec![pad_byte; (meta.start - data_end) as usize])?;
+    writer.write_all(encoded)?;
+    writer.write_all(&vec![
+        pad_byte;
+        (meta.data_end(data_end) - meta.offset) as usize
+    ])
+}
+
 /// Builds an SSTable from key-value pairs.
 /// The SSTable format uses 4KB alignment and the offset records the end byte of each data block
 /// --------------------------------------------------------------------------------------------------------------------
 /// | data block 1(0-2500B) | data block 2(4196-6696B) | ... | meta block1 (offset 2500) | meta block2 (offset 6696)|...
 pub struct SsTableBuilder {
     pub(super) meta: Vec<BlockMeta>,
-    data_blocks: Vec<Block>,
+    /// The encoded data blocks that are already cut.
+    pub(super) data_blocks: Vec<Bytes>,
     cur_block: BlockBuilder,
     cur_start: u32,
     block_size: usize,
     first_key: Vec<u8>,
+    last_key: Vec<u8>,
+    /// The byte used to pad each data block to the 4196-byte alignment.
+    pad_byte: u8,
+    /// The encoded length below which a data block is packed instead of aligned.
+    pack_threshold: usize,
+    /// The number of bytes buffered before issuing a write to the file in `build`.
+    write_buffer_size: usize,
+    /// The entries added so far, only recorded by builders created with `new_validated`.
+    validated: Option<Vec<(Bytes, Bytes)>>,
+    /// Whether the data blocks store their values compressed.
+    compress_values: bool,
+    /// Whether the data blocks delta-encode their keys when the keys allow it.
+    delta_keys: bool,
+    /// Whether `add` accepts empty keys.
+    allow_empty_keys: bool,
+    /// The total encoded length of the data blocks cut so far, with every value stored plain.
+    uncompressed_bytes: u64,
+    /// The total encoded length of the data blocks cut so far, as written to the file.
+    compressed_bytes: u64,
+    /// The largest timestamp of the keys, recorded in the footer.
+    max_ts: u64,
+    /// Every how many blocks the metas keep the keys of their block.
+    index_sparsity: usize,
+    /// The file the data blocks are written to as soon as they are cut, and its path, only for
+    /// builders created with `new_spilling`.
+    spill: Option<(BufWriter<File>, PathBuf)>,
+    /// The error of the first failed write to `spill`, reported by `build_spilled`.
+    spill_error: Option<std::io::Error>,
+    /// The user-defined properties of the table.
+    properties: BTreeMap<String, Bytes>,
+    /// Where `add_with_rollover` writes the tables it finishes, see `set_rollover`.
+    rollover: Option<Rollover>,
+    /// Whether the keys of the metas are stored relative to the key before them.
+    prefix_compress_metas: bool,
+    /// The big-endian CRC32 of each data block cut so far, only recorded once enabled with
+    /// `set_block_checksums`.
+    block_checksums: Option<Vec<u8>>,
+}
+
+/// The settings of `SsTableBuilder::add_with_rollover`.
+struct Rollover {
+    /// The estimated size at which a table is finished.
+    max_table_size: usize,
+    /// The directory the tables are written to.
+    dir: PathBuf,
+    /// The id of the table being built.
+    id: usize,
 }
 
 impl SsTableBuilder {
@@ -35,81 +108,890 @@ impl SsTableBuilder {
             cur_start: 0,
             block_size,
             first_key: Vec::new(),
+            last_key: Vec::new(),
+            pad_byte: 0,
+            pack_threshold: 0,
+            write_buffer_size: 1 << 20,
+            validated: None,
+            compress_values: false,
+            delta_keys: false,
+            allow_empty_keys: false,
+            uncompressed_bytes: 0,
+            compressed_bytes: 0,
+            max_ts: 0,
+            index_sparsity: 1,
+            spill: None,
+            spill_error: None,
+            properties: BTreeMap::new(),
+            rollover: None,
+            prefix_compress_metas: true,
+            block_checksums: None,
+        }
+    }
+
+    /// Create a builder that writes each data block to the file at `path` as soon as it is cut,
+    /// instead of keeping all of them in memory until the table is built, so that building a huge
+    /// table only holds the block being built and the metas. The table is finished with
+    /// `build_spilled`. The blocks are written through a buffer of the default
+    /// `write_buffer_size`.
+    pub fn new_spilling(block_size: usize, path: impl AsRef<Path>) -> Result<Self> {
+        let builder = Self::new(block_size);
+        let writer = builder.buffered(File::create(&path)?);
+        Ok(Self {
+            spill: Some((writer, path.as_ref().to_path_buf())),
+            ..builder
+        })
+    }
+
+    /// Create a builder that checks its output in debug builds: after `build`, the table is
+    /// re-opened from the file and scanned, and `build` panics if the entries or the block metas
+    /// do not match what was added. This keeps a copy of every entry, so release builds, built
+    /// without `debug_assertions`, skip it and behave like `new`.
+    pub fn new_validated(block_size: usize) -> Self {
+        Self {
+            validated: cfg!(debug_assertions).then(Vec::new),
+            ..Self::new(block_size)
+        }
+    }
+
+    /// Set the byte used to pad data blocks to the alignment, 0 by default.
+    /// The padding is never read back, so this only changes what the file looks like on disk,
+    /// e.g. a recognizable pattern when debugging.
+    pub fn set_pad_byte(&mut self, pad_byte: u8) {
+        self.pad_byte = pad_byte;
+    }
+
+    /// Pack the data blocks whose encoded length is below `pack_threshold` bytes right after the
+    /// block before them, instead of padding them to a 4196-byte slot of their own, 0 by default,
+    /// which aligns every block. Tiny blocks then take no more space than their bytes, while the
+    /// larger ones stay aligned for the page cache. The data section still ends on a whole slot.
+    /// Takes effect for the blocks cut after this call.
+    pub fn set_pack_threshold(&mut self, pack_threshold: usize) {
+        self.pack_threshold = pack_threshold;
+    }
+
+    /// Set how many bytes `build` accumulates before writing them to the file, 1MB by default.
+    /// A larger buffer coalesces the writes of many small blocks into fewer syscalls.
+    pub fn set_write_buffer_size(&mut self, write_buffer_size: usize) {
+        self.write_buffer_size = write_buffer_size;
+    }
+
+    /// Store the values of the data blocks compressed while keeping the keys plain, off by default.
+    /// Seeks still compare plain keys, and a value is only decompressed when it is read.
+    /// Takes effect for the entries added after this call.
+    pub fn set_compress_values(&mut self, compress_values: bool) {
+        self.compress_values = compress_values;
+        self.cur_block.set_compress_values(compress_values);
+    }
+
+    /// Delta-encode the keys of the data blocks whose keys are all fixed-width big-endian integers,
+    /// e.g. counters, off by default, see `BlockBuilder::set_delta_keys`. Opt-in because many
+    /// tables have short fixed-width keys that are not counters, and delta-encoded blocks are
+    /// read whole and rebuilt on every read, so they give up `get_with_partial_read` and pooled
+    /// block buffers. Takes effect from the
+    /// current block if it is still empty, or else from the next one.
+    pub fn set_delta_keys(&mut self, delta_keys: bool) {
+        self.delta_keys = delta_keys;
+        if self.cur_block.is_empty() {
+            self.cur_block.set_delta_keys(delta_keys);
         }
     }
 
+    /// Let `add` accept empty keys, off by default.
+    /// Iterators treat an empty key as the end of the data, so an entry with an empty key is stored
+    /// but cannot be iterated over: only opt in when the table is read some other way.
+    pub fn set_allow_empty_keys(&mut self, allow_empty_keys: bool) {
+        self.allow_empty_keys = allow_empty_keys;
+    }
+
+    /// Store the first key of each meta as the prefix it shares with the first key of the meta
+    /// before it plus the rest, and the last key likewise relative to the first key, on by
+    /// default. Turning it off stores every key in full, which only makes the metas larger, see
+    /// `BlockMeta::encode_block_meta_with`.
+    pub fn set_meta_prefix_compression(&mut self, prefix_compress_metas: bool) {
+        self.prefix_compress_metas = prefix_compress_metas;
+    }
+
+    /// Record the CRC32 of every stored data block in the `block_checksums` property, off by
+    /// default, so that `SsTable::verify_checksums_only` can check the blocks without decoding
+    /// them. The property is only written if this is enabled before the first block is cut.
+    pub fn set_block_checksums(&mut self, block_checksums: bool) {
+        match (block_checksums, &self.block_checksums) {
+            (true, None) if self.meta.is_empty() => self.block_checksums = Some(Vec::new()),
+            (true, _) => {}
+            (false, _) => self.block_checksums = None,
+        }
+    }
+
+    /// Set the largest timestamp of the MVCC keys of the table, recorded in the footer, 0 by
+    /// default. The builder cannot tell MVCC keys from plain ones, so it is up to the caller.
+    pub fn set_max_ts(&mut self, max_ts: u64) {
+        self.max_ts = max_ts;
+    }
+
+    /// Keep the first and last key in the metas of only every `index_sparsity`-th block, and of
+    /// the last block, 1 by default, which keeps the keys of every block.
+    /// With long keys, the keys make up most of the metas, so a sparsity of K shrinks the metas,
+    /// on disk and in memory, almost K times. In exchange, `SsTable::find_block_idx` reads up to
+    /// K - 1 blocks to find out which block of a group of K may hold a key.
+    pub fn set_index_sparsity(&mut self, index_sparsity: usize) {
+        assert!(index_sparsity > 0, "the first block is always indexed");
+        self.index_sparsity = index_sparsity;
+    }
+
+    /// Store the checksums of the data blocks in their property, if they were recorded.
+    fn store_block_checksums(&mut self) {
+        if let Some(block_checksums) = self.block_checksums.take() {
+            self.properties.insert(
+                BLOCK_CHECKSUMS_PROPERTY.to_string(),
+                Bytes::from(block_checksums),
+            );
+        }
+    }
+
+    /// Drop the keys from the metas of the blocks left out of the sparse index.
+    fn drop_unindexed_keys(&mut self) {
+        let num_of_blocks = self.meta.len();
+        for (block_idx, meta) in self.meta.iter_mut().enumerate() {
+            if block_idx % self.index_sparsity != 0 && block_idx + 1 != num_of_blocks {
+                meta.key_len = 0;
+                meta.first_key = Bytes::new();
+                meta.last_key = Bytes::new();
+            }
+        }
+    }
+
+    /// Set a user-defined property of the table, e.g. its creator or its level, replacing the
+    /// value set before for `key` if any. The properties are stored in a section of their own
+    /// before the footer, away from the data and the metas, and read with `SsTable::property`.
+    pub fn set_property(&mut self, key: &str, value: &[u8]) {
+        assert!(key.len() <= u16::MAX as usize, "property key too long");
+        self.properties
+            .insert(key.to_string(), Bytes::copy_from_slice(value));
+    }
+
+    /// Set the level of the LSM tree the table belongs to, read with `SsTable::level`. Stored as
+    /// the big-endian `level` property, so it is lost if replaced with `set_property`.
+    pub fn set_level(&mut self, level: u32) {
+        self.set_property(LEVEL_PROPERTY, &level.to_be_bytes());
+    }
+
+    /// Append the metas, the properties and the footer of the table to `buf`, which starts at
+    /// offset `base` of its file, and return the footer.
+    fn encode_index(
+        &self,
+        id: usize,
+        block_meta_offset: u32,
+        base: u32,
+        buf: &mut Vec<u8>,
+    ) -> Footer {
+        BlockMeta::encode_block_meta_with(&self.meta, buf, self.prefix_compress_metas);
+        let bloom_offset = base + buf.len() as u32;
+        let properties_start = buf.len();
+        encode_properties(&self.properties, buf);
+        let footer = Footer {
+            block_meta_offset,
+            bloom_offset,
+            entry_count: self.meta.iter().map(|meta| meta.num_entries as u64).sum(),
+            max_ts: self.max_ts,
+            uncompressed_bytes: self.uncompressed_bytes,
+            compressed_bytes: self.compressed_bytes,
+            table_id: id as u64,
+            index_sparsity: self.index_sparsity as u32,
+            properties_len: (buf.len() - properties_start) as u32,
+        };
+        footer.encode(buf);
+        footer
+    }
+
+    /// Create an empty block with room for `expected_entries` entries.
+    fn new_block(&self, expected_entries: usize) -> BlockBuilder {
+        let mut block = BlockBuilder::with_capacity(self.block_size, expected_entries);
+        block.set_compress_values(self.compress_values);
+        block.set_delta_keys(self.delta_keys);
+        block
+    }
+
     /// Adds a key-value pair to SSTable.
     /// Note: You should split a new block when the current block is full.(`std::mem::replace` may be of help here)
-    pub fn add(&mut self, key: &[u8], value: &[u8]) {
-        if self.cur_block.add(key, value) {
-            if self.first_key.is_empty() {
-                self.first_key = key.to_vec();
+    /// Returns `false` without adding anything if the key is empty and empty keys are not allowed,
+    /// or if the entry does not fit in a block of its own, see `BlockBuilder::add`.
+    #[must_use]
+    pub fn add(&mut self, key: &[u8], value: &[u8]) -> bool {
+        if key.is_empty() && !self.allow_empty_keys {
+            return false;
+        }
+        let first_in_block = self.cur_block.is_empty();
+        if !self.cur_block.add(key, value) {
+            // an entry that does not fit in an empty block is rejected before the current block
+            // is cut for it
+            if first_in_block || !self.new_block(0).add(key, value) {
+                return false;
             }
-            return;
+            self.finish_block();
+            if !self.cur_block.add(key, value) {
+                return false;
+            }
+        }
+        if self.cur_block.num_of_entries() == 1 {
+            self.first_key = key.to_vec();
         }
+        self.last_key = key.to_vec();
+        if let Some(added) = &mut self.validated {
+            added.push((Bytes::copy_from_slice(key), Bytes::copy_from_slice(value)));
+        }
+        true
+    }
+
+    /// Get the last key added to the table so far, `None` if nothing was added yet.
+    fn last_added_key(&self) -> Option<&[u8]> {
+        if !self.cur_block.is_empty() {
+            return Some(&self.last_key);
+        }
+        self.meta.last().map(|meta| meta.last_key.as_ref())
+    }
+
+    /// Adds a key-value pair like `add`, failing instead of returning `false` when the entry is
+    /// rejected.
+    pub(crate) fn try_add(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        if !self.add(key, value) {
+            bail!(
+                "cannot add key {:?}: empty, or the entry does not fit in a block",
+                Bytes::copy_from_slice(key)
+            );
+        }
+        Ok(())
+    }
+
+    /// Cut the current block and start a new one.
+    fn finish_block(&mut self) {
         let block_size = self.cur_block.size() as u32;
+        // expect as many entries as fit in a block at the average entry size of the block just cut
+        let expected_entries =
+            self.block_size * self.cur_block.num_of_entries() / block_size as usize;
         // BlockBuider::new assign to self.cur_block, cur_block holds the old self.cur_block so neither is dropped
-        let cur_block = std::mem::replace(&mut self.cur_block, BlockBuilder::new(self.block_size));
-        self.data_blocks.push(BlockBuilder::build(cur_block));
-        let first_key = std::mem::replace(&mut self.first_key, key.to_vec());
-        self.meta.push(BlockMeta {
-            offset: self.cur_start + block_size,
+        let new_block = self.new_block(expected_entries);
+        let cur_block = std::mem::replace(&mut self.cur_block, new_block);
+        let num_entries = cur_block.num_of_entries() as u16;
+        self.uncompressed_bytes += cur_block.uncompressed_size() as u64;
+        let mut encoded = Vec::with_capacity(block_size as usize);
+        cur_block.encode_into(&mut encoded);
+        self.compressed_bytes += encoded.len() as u64;
+        let (start, packed) = self.place_block(encoded.len());
+        let first_key = std::mem::take(&mut self.first_key);
+        let last_key = std::mem::take(&mut self.last_key);
+        let meta = BlockMeta {
+            offset: start + block_size,
+            num_entries,
             key_len: first_key.len() as u16,
             first_key: Bytes::from(first_key),
+            last_key: Bytes::from(last_key),
+            reference: None,
+            start,
+            packed,
+        };
+        self.push_block(Bytes::from(encoded), &meta);
+        self.push_meta(meta);
+    }
+
+    /// Get where the next stored block, of `len` encoded bytes, starts, and whether it is packed.
+    fn place_block(&self, len: usize) -> (u32, bool) {
+        if len < self.pack_threshold {
+            (self.cur_start, true)
+        } else {
+            (align_up(self.cur_start), false)
+        }
+    }
+
+    /// Append a whole data block that is already encoded, e.g. copied verbatim from another table
+    /// during compaction, after cutting the current block. `first_key` and `last_key` must be the
+    /// first and last key of the block, and the block must sort after every entry added so far.
+    /// Fails with `TableError::Corruption` if `encoded` is not a valid block, e.g. when it was read
+    /// from a corrupted table, leaving the builder as it was.
+    pub fn add_encoded_block(
+        &mut self,
+        encoded: Bytes,
+        first_key: &[u8],
+        last_key: &[u8],
+    ) -> Result<(), TableError> {
+        assert!(encoded.len() <= 4196, "encoded block too large");
+        let block = Block::try_decode(&encoded)
+            .map_err(|err| TableError::corruption(None, format!("{:#}", err)))?;
+        if !self.cur_block.is_empty() {
+            self.finish_block();
+        }
+        let block = Arc::new(block);
+        if let Some(added) = &mut self.validated {
+            let mut iter = BlockIterator::create_and_seek_to_first(block.clone());
+            while iter.is_valid() {
+                added.push((
+                    Bytes::copy_from_slice(iter.key()),
+                    Bytes::copy_from_slice(iter.value()),
+                ));
+                iter.next();
+            }
+        }
+        self.uncompressed_bytes += block.uncompressed_len() as u64;
+        self.compressed_bytes += encoded.len() as u64;
+        let (start, packed) = self.place_block(encoded.len());
+        let meta = BlockMeta {
+            offset: start + encoded.len() as u32,
+            // `num_of_elements` ends the encoded block
+            num_entries: block::num_of_elements(&encoded),
+            key_len: first_key.len() as u16,
+            first_key: Bytes::copy_from_slice(first_key),
+            last_key: Bytes::copy_from_slice(last_key),
+            reference: None,
+            start,
+            packed,
+        };
+        self.push_block(encoded, &meta);
+        self.push_meta(meta);
+        Ok(())
+    }
+
+    /// Record the meta of a block just cut, and move past its slot. In debug builds, this checks
+    /// that a stored block ends strictly after the stored block before it, as `find_block_idx` and
+    /// every block read rely on the blocks advancing.
+    fn push_meta(&mut self, meta: BlockMeta) {
+        if meta.reference.is_none() {
+            if let Some(prev) = self.meta.iter().rev().find(|prev| prev.reference.is_none()) {
+                debug_assert!(
+                    meta.offset > prev.offset && meta.start > prev.start,
+                    "block {} ends at offset {}, not after the block before it at offset {}",
+                    self.meta.len(),
+                    meta.offset,
+                    prev.offset
+                );
+            }
+        }
+        self.cur_start = meta.data_end(self.cur_start);
+        self.meta.push(meta);
+    }
+
+    /// Append a data block that is a copy of the block `reference` of another table, after
+    /// cutting the current block, without storing its bytes: the meta records the reference, and
+    /// the block is read from the other table, see `SsTable::with_block_sources`. `num_entries`,
+    /// `first_key` and `last_key` must be those of the referenced block, which must sort after
+    /// every entry added so far. Not supported by builders created with `new_validated` in debug
+    /// builds, as the entries of the block are not at hand.
+    pub fn add_block_reference(
+        &mut self,
+        reference: BlockRef,
+        num_entries: u16,
+        first_key: &[u8],
+        last_key: &[u8],
+    ) {
+        assert!(
+            self.validated.is_none(),
+            "a validated builder cannot reference blocks"
+        );
+        if !self.cur_block.is_empty() {
+            self.finish_block();
+        }
+        if let Some(block_checksums) = &mut self.block_checksums {
+            // the block is checked in the table it is stored in
+            block_checksums.extend_from_slice(&0u32.to_be_bytes());
+        }
+        self.push_meta(BlockMeta {
+            // the reference takes no space, so it ends where it starts
+            offset: self.cur_start,
+            num_entries,
+            key_len: first_key.len() as u16,
+            first_key: Bytes::copy_from_slice(first_key),
+            last_key: Bytes::copy_from_slice(last_key),
+            reference: Some(reference),
+            start: self.cur_start,
+            packed: false,
         });
-        self.cur_block = BlockBuilder::new(self.block_size);
-        assert!(self.cur_block.add(key, value));
-        self.cur_start += 4196;
+    }
+
+    /// Adds a key-value pair to SSTable like `add`, and returns the index of the data block the
+    /// entry was placed in, e.g. to build a key to block map alongside the table, or `None` if
+    /// `add` rejected the entry. The returned indices never decrease and match the block layout
+    /// of the built table.
+    pub fn add_tracked(&mut self, key: &[u8], value: &[u8]) -> Option<usize> {
+        if !self.add(key, value) {
+            return None;
+        }
+        // the entry is always in the current block, which follows all the finished ones
+        Some(self.meta.len())
     }
 
     /// Get the estimated size of the SSTable.
     /// Since the data blocks contain much more data than meta blocks, just return the size of data blocks here.
+    /// Block references take no space.
     pub fn estimated_size(&self) -> usize {
-        self.data_blocks.len() * 4196 + self.cur_block.is_empty() as usize * 4196
+        self.cur_start as usize + !self.cur_block.is_empty() as usize * 4196
     }
 
-    /// Builds the SSTable and writes it to the given path. No need to actually write to disk until
-    /// chapter 4 block cache.
+    /// Builds the SSTable and writes it to the given path.
+    /// The blocks are encoded and written one by one through a buffer of `write_buffer_size`
+    /// bytes, so the whole file is never assembled in memory.
+    ///
+    /// The output is deterministic for a given id: building the same entries with the same block
+    /// size, pad byte and `id` always produces a byte-identical file. Blocks are padded with the
+    /// fixed `pad_byte`, the metas are kept in a `Vec` in the order the blocks were cut, and
+    /// nothing derived from the clock or the `path` is written to the file. The `id` is, as the
+    /// `table_id` of the footer.
     pub fn build(
-        self,
+        mut self,
         id: usize,
         block_cache: Option<Arc<BlockCache>>,
         path: impl AsRef<Path>,
     ) -> Result<SsTable> {
-        let mut data = Vec::new();
-        for data_block in self.data_blocks {
-            let data_bytes = data_block.encode();
-            let padding_bytes = vec![0; 4196 - data_bytes.len()];
-            data.extend_from_slice(&data_bytes);
-            data.extend_from_slice(&padding_bytes);
-        }
-        let mut block_meta_offset = self.cur_start;
-        let mut meta = self.meta;
+        assert!(
+            self.spill.is_none(),
+            "a spilling builder is finished with build_spilled"
+        );
         if !self.cur_block.is_empty() {
-            let block_size = self.cur_block.size() as u32;
-            let data_bytes = self.cur_block.build().encode();
-            let padding_bytes = vec![0; 4196 - data_bytes.len()];
-            data.extend_from_slice(&data_bytes);
-            data.extend_from_slice(&padding_bytes);
-            block_meta_offset += 4196;
-            meta.push(BlockMeta {
-                offset: self.cur_start + block_size,
-                key_len: self.first_key.len() as u16,
-                first_key: Bytes::from(self.first_key),
-            });
+            self.finish_block();
         }
+        self.align_data_end();
+        self.drop_unindexed_keys();
+        self.store_block_checksums();
+        let mut writer = self.buffered(File::create(path.as_ref())?);
+        self.write_data_blocks(&mut writer)?;
+        self.finish_file(id, block_cache, writer, path.as_ref())
+    }
 
-        BlockMeta::encode_block_meta(&meta, &mut data);
+    /// Builds the SSTable of a builder created with `new_spilling`, whose data blocks are already
+    /// in the file: only the last block, the metas and the footer are left to write.
+    pub fn build_spilled(
+        mut self,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+    ) -> Result<SsTable> {
+        if !self.cur_block.is_empty() {
+            self.finish_block();
+        }
+        self.align_data_end();
+        self.drop_unindexed_keys();
+        self.store_block_checksums();
+        let Some((writer, path)) = self.spill.take() else {
+            panic!("only a builder created with new_spilling can be finished with build_spilled");
+        };
+        if let Some(err) = self.spill_error.take() {
+            return Err(err.into());
+        }
+        self.finish_file(id, block_cache, writer, &path)
+    }
+
+    /// Write the metas and the footer after the data blocks already written to `writer`, then
+    /// open the table at `path`.
+    fn finish_file(
+        self,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        mut writer: BufWriter<File>,
+        path: &Path,
+    ) -> Result<SsTable> {
+        let block_meta_offset = self.cur_start;
+        let mut meta_data = Vec::new();
+        let footer = self.encode_index(id, block_meta_offset, block_meta_offset, &mut meta_data);
+        writer.write_all(&meta_data)?;
+        writer.flush()?;
 
-        data.extend_from_slice(block_meta_offset.to_be_bytes().as_ref());
+        self.check_built(|| Ok(SsTable::open(id, None, FileObject::open(path)?)?));
 
         Ok(SsTable {
-            file: FileObject::create(path.as_ref(), data)?,
-            block_metas: meta,
-            block_meta_offset: block_meta_offset,
+            file: FileObject::open(path)?,
+            block_metas: self.meta.into(),
+            block_meta_offset,
+            id,
+            block_cache,
+            footer,
+            properties: self.properties.into(),
+            footer_offset: (block_meta_offset as usize + meta_data.len() - FOOTER_SIZE) as u64,
+            block_sources: BTreeMap::new(),
+            disk_index: None,
         })
     }
 
+    /// Builds the SSTable like `build`, but writes the data blocks to `data_path` and the meta
+    /// blocks to `index_path`, e.g. to keep the index on faster storage than the data:
+    /// ----------------------------------------------------------------------------
+    /// | Meta Block #1 | ... | Meta Block #N | Bloom Filter (empty) | Footer |
+    /// ----------------------------------------------------------------------------
+    /// The footer's `block_meta_offset` records the length of the data file, so that
+    /// `SsTable::open_split` can check it, and its `bloom_offset` is relative to the index file.
+    /// The data file holds the data blocks laid out exactly as in a single-file SSTable.
+    pub fn build_split(
+        mut self,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        data_path: impl AsRef<Path>,
+        index_path: impl AsRef<Path>,
+    ) -> Result<SsTable> {
+        assert!(
+            self.spill.is_none(),
+            "a spilling builder is finished with build_spilled"
+        );
+        if !self.cur_block.is_empty() {
+            self.finish_block();
+        }
+        self.align_data_end();
+        self.drop_unindexed_keys();
+        self.store_block_checksums();
+        let mut writer = self.buffered(File::create(data_path.as_ref())?);
+        self.write_data_blocks(&mut writer)?;
+        writer.flush()?;
+        let data_len = self.cur_start;
+        let mut index_data = Vec::new();
+        let footer = self.encode_index(id, data_len, 0, &mut index_data);
+        std::fs::write(index_path.as_ref(), index_data)?;
+
+        self.check_built(|| {
+            Ok(SsTable::open_split(
+                id,
+                None,
+                data_path.as_ref(),
+                index_path.as_ref(),
+            )?)
+        });
+
+        Ok(SsTable {
+            file: FileObject::open(data_path.as_ref())?,
+            block_metas: self.meta.into(),
+            block_meta_offset: data_len,
+            id,
+            block_cache,
+            footer,
+            properties: self.properties.into(),
+            footer_offset: data_len as u64,
+            block_sources: BTreeMap::new(),
+            disk_index: None,
+        })
+    }
+
+    /// Keep a cut data block until the table is built, or write it right away when spilling.
+    /// `meta` is the meta of the block, not pushed yet.
+    fn push_block(&mut self, encoded: Bytes, meta: &BlockMeta) {
+        if let Some(block_checksums) = &mut self.block_checksums {
+            block_checksums.extend_from_slice(&crc32(&encoded).to_be_bytes());
+        }
+        let Some((writer, _)) = &mut self.spill else {
+            self.data_blocks.push(encoded);
+            return;
+        };
+        if self.spill_error.is_none() {
+            if let Err(err) = write_block(writer, self.pad_byte, self.cur_start, &encoded, meta) {
+                self.spill_error = Some(err);
+            }
+        }
+    }
+
+    /// Pad the data section up to a whole number of slots if it ends with packed blocks, so that
+    /// the data sections of several tables can still be concatenated, see `concat_metas`.
+    fn align_data_end(&mut self) {
+        let data_end = align_up(self.cur_start);
+        if let Some((writer, _)) = &mut self.spill {
+            let padding = vec![self.pad_byte; (data_end - self.cur_start) as usize];
+            if let Err(err) = writer.write_all(&padding) {
+                self.spill_error.get_or_insert(err);
+            }
+        }
+        self.cur_start = data_end;
+    }
+
+    /// Wrap the file the table is written to in a buffer of `write_buffer_size` bytes.
+    pub(super) fn buffered<W: Write>(&self, file: W) -> BufWriter<W> {
+        BufWriter::with_capacity(self.write_buffer_size, file)
+    }
+
+    /// Write the data blocks, each padded to the 4196-byte alignment unless packed.
+    pub(super) fn write_data_blocks(&self, writer: &mut impl Write) -> Result<()> {
+        let stored = self.meta.iter().filter(|meta| meta.reference.is_none());
+        let mut data_end = 0;
+        for (data_bytes, meta) in self.data_blocks.iter().zip(stored) {
+            write_block(writer, self.pad_byte, data_end, data_bytes, meta)?;
+            data_end = meta.data_end(data_end);
+        }
+        writer.write_all(&vec![self.pad_byte; (self.cur_start - data_end) as usize])?;
+        Ok(())
+    }
+
+    /// Check the table written by `build`, opened by `open`, against the entries added to a
+    /// builder created with `new_validated`, panicking with the mismatch if it does not hold them.
+    fn check_built(&self, open: impl FnOnce() -> Result<SsTable>) {
+        if let Some(expected) = &self.validated {
+            if let Err(err) = open().and_then(|sst| Self::validate(&sst, expected)) {
+                panic!(
+                    "validated build wrote a table not matching its entries: {:#}",
+                    err
+                );
+            }
+        }
+    }
+
+    /// Check that `sst` holds exactly the `expected` entries, and that the first and last key of
+    /// each indexed block match its meta.
+    fn validate(sst: &SsTable, expected: &[(Bytes, Bytes)]) -> Result<()> {
+        let mut expected = expected.iter();
+        for (block_idx, meta) in sst.block_metas().iter().enumerate() {
+            let mut iter = BlockIterator::create_and_seek_to_first(sst.read_block(block_idx)?);
+            let indexed = sst.is_indexed(block_idx);
+            if indexed && iter.key() != meta.first_key {
+                bail!(
+                    "block {} starts with {:?} but its meta has first_key {:?}",
+                    block_idx,
+                    Bytes::copy_from_slice(iter.key()),
+                    meta.first_key
+                );
+            }
+            let mut last_key = Vec::new();
+            while iter.is_valid() {
+                match expected.next() {
+                    Some((key, value)) if key == iter.key() && value == iter.value() => {}
+                    Some((key, value)) => bail!(
+                        "block {} holds {:?} => {:?}, expected {:?} => {:?}",
+                        block_idx,
+                        Bytes::copy_from_slice(iter.key()),
+                        Bytes::copy_from_slice(iter.value()),
+                        key,
+                        value
+                    ),
+                    None => bail!(
+                        "block {} holds unexpected key {:?}",
+                        block_idx,
+                        Bytes::copy_from_slice(iter.key())
+                    ),
+                }
+                last_key = iter.key().to_vec();
+                iter.next();
+            }
+            if indexed && last_key != meta.last_key {
+                bail!(
+                    "block {} ends with {:?} but its meta has last_key {:?}",
+                    block_idx,
+                    Bytes::from(last_key),
+                    meta.last_key
+                );
+            }
+        }
+        if let Some((key, _)) = expected.next() {
+            bail!("key {:?} is missing from the table", key);
+        }
+        Ok(())
+    }
+
+    /// Finish tables with `add_with_rollover` once their `estimated_size` reaches
+    /// `max_table_size`, writing them to `dir` as `<id>.sst`, with ids counting up from `first_id`.
+    pub fn set_rollover(&mut self, max_table_size: usize, dir: impl AsRef<Path>, first_id: usize) {
+        self.rollover = Some(Rollover {
+            max_table_size,
+            dir: dir.as_ref().to_path_buf(),
+            id: first_id,
+        });
+    }
+
+    /// Adds a key-value pair like `add`, then if the table has reached the size set with
+    /// `set_rollover`, builds it and passes it to `on_table_complete`, e.g. to upload it while the
+    /// next table is being built. The builder then goes on with an empty table with the same
+    /// settings and the next id. Call `finish_rollover` after the last entry to build the last
+    /// table. Returns whether the pair was added, like `add`. Fails without adding anything if
+    /// `set_rollover` was not called, or if the builder was created with `new_spilling`, which
+    /// writes to a single file.
+    pub fn add_with_rollover(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        mut on_table_complete: impl FnMut(SsTable),
+    ) -> Result<bool> {
+        let Some(max_table_size) = self.rollover.as_ref().map(|rollover| rollover.max_table_size)
+        else {
+            bail!("add_with_rollover without set_rollover");
+        };
+        if self.spill.is_some() {
+            bail!("a spilling builder cannot roll over");
+        }
+        if !self.add(key, value) {
+            return Ok(false);
+        }
+        if self.estimated_size() >= max_table_size {
+            let next = Self {
+                rollover: self.rollover.as_ref().map(|rollover| Rollover {
+                    max_table_size,
+                    dir: rollover.dir.clone(),
+                    id: rollover.id + 1,
+                }),
+                ..self.empty_with_settings()
+            };
+            on_table_complete(std::mem::replace(self, next).build_rollover()?);
+        }
+        Ok(true)
+    }
+
+    /// Build the table being filled by `add_with_rollover`, if it holds any entry, and pass it
+    /// to `on_table_complete`. Fails like `add_with_rollover` without the rollover settings.
+    pub fn finish_rollover(self, mut on_table_complete: impl FnMut(SsTable)) -> Result<()> {
+        if self.rollover.is_none() {
+            bail!("finish_rollover without set_rollover");
+        }
+        if self.meta.is_empty() && self.cur_block.is_empty() {
+            return Ok(());
+        }
+        on_table_complete(self.build_rollover()?);
+        Ok(())
+    }
+
+    /// Build the table at the path given by the rollover settings.
+    fn build_rollover(self) -> Result<SsTable> {
+        let Some(rollover) = &self.rollover else {
+            bail!("add_with_rollover without set_rollover");
+        };
+        let (id, path) = (
+            rollover.id,
+            rollover.dir.join(format!("{}.sst", rollover.id)),
+        );
+        self.build(id, None, path)
+    }
+
+    /// Create an empty builder with the same settings, the rollover settings aside.
+    fn empty_with_settings(&self) -> Self {
+        let mut builder = Self::new(self.block_size);
+        builder.pad_byte = self.pad_byte;
+        builder.pack_threshold = self.pack_threshold;
+        builder.write_buffer_size = self.write_buffer_size;
+        builder.validated = self.validated.as_ref().map(|_| Vec::new());
+        builder.set_compress_values(self.compress_values);
+        builder.set_delta_keys(self.delta_keys);
+        builder.allow_empty_keys = self.allow_empty_keys;
+        builder.max_ts = self.max_ts;
+        builder.index_sparsity = self.index_sparsity;
+        builder.properties = self.properties.clone();
+        builder.prefix_compress_metas = self.prefix_compress_metas;
+        builder.block_checksums = self.block_checksums.as_ref().map(|_| Vec::new());
+        builder
+    }
+
+    /// Builds the SSTable from all the entries of `iter`, e.g. the output of a compaction.
+    /// Entries dropped by `iter` never reach the builder, so no block is cut for them, and an
+    /// `iter` that yields nothing produces a valid table without any data block.
+    pub fn build_from_iter(
+        mut self,
+        mut iter: impl StorageIterator,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        path: impl AsRef<Path>,
+    ) -> Result<SsTable> {
+        while iter.is_valid() {
+            self.try_add(iter.key(), iter.value())?;
+            iter.next()?;
+        }
+        self.build(id, block_cache, path)
+    }
+
+    /// Builds an SSTable with a target block size of `block_size` from `entries` in any order,
+    /// e.g. for ad-hoc tables. The entries are sorted by key first, and of several entries with
+    /// the same key, only the one that comes last in `entries` is kept. Fails on an empty key.
+    pub fn from_unsorted(
+        mut entries: Vec<(Bytes, Bytes)>,
+        block_size: usize,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        path: impl AsRef<Path>,
+    ) -> Result<SsTable> {
+        // a stable sort keeps the entries with the same key in their order
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+        let mut builder = Self::new(block_size);
+        for (idx, (key, value)) in entries.iter().enumerate() {
+            if entries.get(idx + 1).map_or(false, |(next, _)| next == key) {
+                continue;
+            }
+            builder.try_add(key, value)?;
+        }
+        builder.build(id, block_cache, path)
+    }
+
+    /// Adds every row of a sorted CSV or TSV stream, read from `r` and split on `delimiter`
+    /// (`b','` or `b'\t'`), taking the key from column `key_col` and the value from column
+    /// `val_col`, both 0-based. Fields are taken as raw bytes: there is no quoting, so a field
+    /// cannot hold the delimiter or a newline. Empty lines are skipped, and a trailing `\r` is
+    /// dropped. The keys must be strictly increasing, starting after the last key added to the
+    /// builder before, if any; an out-of-order row, a row missing a column
+    /// or an empty key fails the import with its 1-based line number, leaving the rows before it
+    /// added. Returns the number of rows added.
+    pub fn import_csv<R: Read>(
+        &mut self,
+        r: R,
+        delimiter: u8,
+        key_col: usize,
+        val_col: usize,
+    ) -> Result<usize> {
+        let mut prev_key = self.last_added_key().map(<[u8]>::to_vec);
+        let mut rows = 0;
+        for (line_idx, line) in BufReader::new(r).split(b'\n').enumerate() {
+            let mut line = line?;
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            if line.is_empty() {
+                continue;
+            }
+            let line_no = line_idx + 1;
+            let fields: Vec<&[u8]> = line.split(|byte| *byte == delimiter).collect();
+            let (Some(key), Some(value)) = (fields.get(key_col), fields.get(val_col)) else {
+                bail!(
+                    "line {}: expected at least {} columns, found {}",
+                    line_no,
+                    key_col.max(val_col) + 1,
+                    fields.len()
+                );
+            };
+            if let Some(prev_key) = &prev_key {
+                if key <= &prev_key.as_slice() {
+                    bail!(
+                        "line {}: key {:?} is not after the previous key {:?}",
+                        line_no,
+                        Bytes::copy_from_slice(key),
+                        Bytes::copy_from_slice(prev_key)
+                    );
+                }
+            }
+            if let Err(err) = self.try_add(key, value) {
+                bail!("line {}: {:#}", line_no, err);
+            }
+            prev_key = Some(key.to_vec());
+            rows += 1;
+        }
+        Ok(rows)
+    }
+
+    /// Builds the SSTable from a sorted memtable snapshot merged with an existing SSTable.
+    /// Both inputs are walked in lockstep and the memtable entry is preferred when the keys tie,
+    /// so the flush path does not need to go through generic `StorageIterator`s.
+    pub fn build_merged(
+        mut self,
+        memtable: &[(Bytes, Bytes)],
+        sstable: &SsTable,
+        id: usize,
+        block_cache: Option<Arc<BlockCache>>,
+        path: impl AsRef<Path>,
+    ) -> Result<SsTable> {
+        let mut mem_idx = 0;
+        for block_idx in 0..sstable.num_of_blocks() {
+            let mut iter = BlockIterator::create_and_seek_to_first(sstable.read_block(block_idx)?);
+            while iter.is_valid() {
+                while mem_idx < memtable.len() && memtable[mem_idx].0.as_ref() < iter.key() {
+                    self.try_add(&memtable[mem_idx].0, &memtable[mem_idx].1)?;
+                    mem_idx += 1;
+                }
+                if mem_idx < memtable.len() && memtable[mem_idx].0.as_ref() == iter.key() {
+                    self.try_add(&memtable[mem_idx].0, &memtable[mem_idx].1)?;
+                    mem_idx += 1;
+                } else {
+                    self.try_add(iter.key(), iter.value())?;
+                }
+                iter.next();
+            }
+        }
+        for (key, value) in &memtable[mem_idx..] {
+            self.try_add(key, value)?;
+        }
+        self.build(id, block_cache, path)
+    }
+
     #[cfg(test)]
     pub(crate) fn build_for_test(self, path: impl AsRef<Path>) -> Result<SsTable> {
         self.build(0, None, path)
@@ -0,0 +1,295 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use super::{DedupStore, SsTable, SsTableBuilder};
+use crate::block::BlockIterator;
+use crate::lsm_storage::BlockCache;
+
+/// What a compaction did with its input blocks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// The number of input blocks copied verbatim into the output.
+    pub copied_blocks: usize,
+    /// The number of copied blocks recorded as references to identical blocks of a
+    /// `DedupStore`, included in `copied_blocks`.
+    pub deduped_blocks: usize,
+    /// The number of input blocks decoded to merge their entries one by one.
+    pub decoded_blocks: usize,
+    /// The number of entries decoded from the input blocks, including the shadowed versions.
+    pub decoded_entries: usize,
+    /// The largest number of decoded input blocks held at once, at most one per input.
+    pub peak_loaded_blocks: usize,
+}
+
+/// The estimated output of a compaction, see `estimate_compaction_output`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CompactionEstimate {
+    /// The number of entries of the output, tombstones included.
+    pub output_entries: usize,
+    /// The total encoded length of the data blocks of the output, without the alignment padding.
+    pub output_bytes: u64,
+    /// The number of tombstones of the inputs shadowed by a newer version of their key, which
+    /// the compaction drops. The tombstones that are not shadowed are kept, see `compact`.
+    pub dropped_tombstones: usize,
+}
+
+/// The number of blocks `estimate_compaction_output` reads at most to sample the overlaps.
+const ESTIMATE_SAMPLE_BLOCKS: usize = 32;
+
+/// Estimate the output of compacting `tables`, ordered from the newest to the oldest like for
+/// `compact`, without merging them. Only the entries of blocks whose key range overlaps a block
+/// of another table can be shadowed; the overlaps are found and the other entries counted from
+/// the metas, without reading any block. The share of shadowed
+/// entries and tombstones in the overlapping blocks is measured on up to
+/// `ESTIMATE_SAMPLE_BLOCKS` of them, evenly spread, and extrapolated, so the estimate is exact
+/// when there are no more overlapping blocks than that. The output is assumed to encode its
+/// entries as compactly as the inputs.
+pub fn estimate_compaction_output(tables: &[Arc<SsTable>]) -> Result<CompactionEstimate> {
+    let mut total_entries = 0;
+    let mut total_bytes = 0;
+    // the overlapping blocks, by table and block index, along with their number of entries
+    let mut overlapping = Vec::new();
+    for (table_idx, table) in tables.iter().enumerate() {
+        total_bytes += table.data_size();
+        for (block_idx, meta) in table.block_metas().iter().enumerate() {
+            total_entries += meta.num_entries as usize;
+            // the keys of a block left out of a sparse index are unknown
+            let overlaps = !table.is_indexed(block_idx)
+                || tables
+                    .iter()
+                    .enumerate()
+                    .filter(|&(other_idx, _)| other_idx != table_idx)
+                    .any(|(_, other)| overlaps_blocks(other, &meta.first_key, &meta.last_key));
+            if overlaps {
+                overlapping.push((table_idx, block_idx, meta.num_entries as usize));
+            }
+        }
+    }
+    let overlapping_entries: usize = overlapping.iter().map(|&(_, _, entries)| entries).sum();
+    let num_samples = overlapping.len().min(ESTIMATE_SAMPLE_BLOCKS);
+    let (mut sampled, mut shadowed, mut shadowed_tombstones) = (0, 0, 0);
+    for sample in 0..num_samples {
+        let (table_idx, block_idx, _) = overlapping[sample * overlapping.len() / num_samples];
+        let mut iter =
+            BlockIterator::create_and_seek_to_first(tables[table_idx].read_block(block_idx)?);
+        while iter.is_valid() {
+            sampled += 1;
+            let mut newer = tables[..table_idx].iter();
+            if newer.try_fold(false, |found, table| {
+                Ok::<_, anyhow::Error>(found || table.get(iter.key())?.is_some())
+            })? {
+                shadowed += 1;
+                shadowed_tombstones += iter.value().is_empty() as usize;
+            }
+            iter.next();
+        }
+    }
+    let extrapolate = |count: usize| match sampled {
+        0 => 0,
+        _ => (count as f64 * overlapping_entries as f64 / sampled as f64).round() as usize,
+    };
+    let output_entries = total_entries - extrapolate(shadowed).min(total_entries);
+    let output_bytes = match total_entries {
+        0 => 0,
+        _ => (total_bytes as f64 * output_entries as f64 / total_entries as f64).round() as u64,
+    };
+    Ok(CompactionEstimate {
+        output_entries,
+        output_bytes,
+        dropped_tombstones: extrapolate(shadowed_tombstones),
+    })
+}
+
+/// Check from the metas alone whether a block of `table` may hold keys in `first_key..=last_key`.
+/// With a sparse index, the keys of most blocks are unknown, so any overlap with the range of the
+/// table counts.
+fn overlaps_blocks(table: &SsTable, first_key: &[u8], last_key: &[u8]) -> bool {
+    match (table.first_key(), table.last_key()) {
+        (Some(table_first), Some(table_last))
+            if first_key <= table_last && table_first <= last_key => {}
+        _ => return false,
+    }
+    if table.index_sparsity() > 1 {
+        return true;
+    }
+    // the blocks are ordered and disjoint, so only the last one starting at most at `last_key`
+    // may reach `first_key`
+    let metas = table.block_metas();
+    match metas.partition_point(|meta| meta.first_key <= last_key) {
+        0 => false,
+        idx => metas[idx - 1].last_key >= first_key,
+    }
+}
+
+/// The position of a compaction input.
+struct Input {
+    table: Arc<SsTable>,
+    block_idx: usize,
+    /// The iterator over the current block, `None` when positioned at the start of `block_idx`
+    /// without having read it yet.
+    iter: Option<BlockIterator>,
+}
+
+impl Input {
+    fn is_valid(&self) -> bool {
+        self.block_idx < self.table.num_of_blocks()
+    }
+
+    fn key(&self) -> &[u8] {
+        match &self.iter {
+            Some(iter) => iter.key(),
+            None => &self.table.block_metas()[self.block_idx].first_key,
+        }
+    }
+
+    /// Read the current block if its meta lacks the keys, being left out of a sparse index, as
+    /// `key` and the block copy rely on them.
+    fn load_unindexed(&mut self, stats: &mut CompactionStats) -> Result<()> {
+        while self.iter.is_none() && self.is_valid() && !self.table.is_indexed(self.block_idx) {
+            self.next(stats)?;
+        }
+        Ok(())
+    }
+
+    fn next(&mut self, stats: &mut CompactionStats) -> Result<()> {
+        match &mut self.iter {
+            Some(iter) => {
+                iter.next();
+                if !iter.is_valid() {
+                    self.block_idx += 1;
+                    self.iter = None;
+                }
+            }
+            None => {
+                let iter =
+                    BlockIterator::create_and_seek_to_first(self.table.read_block(self.block_idx)?);
+                stats.decoded_blocks += 1;
+                if iter.is_valid() {
+                    self.iter = Some(iter);
+                } else {
+                    self.block_idx += 1;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Merge `tables`, ordered from the newest to the oldest, into a new SSTable. For a key present in
+/// several tables, only the version of the newest table is kept; tombstones are kept as well.
+///
+/// When the whole key range of an input block lies strictly between the last key written and the
+/// next key of every other input, nothing can shadow or interleave with its entries, so the block
+/// is copied verbatim into the output instead of being decoded and re-added entry by entry. This
+/// makes compacting mostly disjoint tables close to a plain copy.
+///
+/// The memory used on top of the input tables stays bounded however large they are: an input
+/// only holds a decoded block once its next key is needed, one block at a time, and the output is
+/// built with `SsTableBuilder::new_spilling`, which writes each block out as soon as it is cut.
+pub fn compact(
+    tables: &[Arc<SsTable>],
+    block_size: usize,
+    id: usize,
+    block_cache: Option<Arc<BlockCache>>,
+    path: impl AsRef<Path>,
+) -> Result<(SsTable, CompactionStats)> {
+    compact_with_dedup(tables, block_size, id, block_cache, path, None)
+}
+
+/// Merge `tables` like `compact`, deduplicating the copied blocks against `dedup`: a block copied
+/// verbatim whose content is already stored in a table of the store is recorded as a reference
+/// to that block instead of being written again, which makes the output of compacting mostly
+/// unchanged data a fraction of its size. The output reads the referenced blocks from the tables
+/// of the store, which must stay around as long as the output, and must be registered again with
+/// `SsTable::with_block_sources` when the output is reopened.
+pub fn compact_with_dedup(
+    tables: &[Arc<SsTable>],
+    block_size: usize,
+    id: usize,
+    block_cache: Option<Arc<BlockCache>>,
+    path: impl AsRef<Path>,
+    dedup: Option<&DedupStore>,
+) -> Result<(SsTable, CompactionStats)> {
+    let mut inputs: Vec<Input> = tables
+        .iter()
+        .map(|table| Input {
+            table: table.clone(),
+            block_idx: 0,
+            iter: None,
+        })
+        .collect();
+    let mut builder = SsTableBuilder::new_spilling(block_size, path)?;
+    let mut stats = CompactionStats::default();
+    let mut last_key: Option<Bytes> = None;
+    loop {
+        for input in &mut inputs {
+            input.load_unindexed(&mut stats)?;
+        }
+        let loaded_blocks = inputs.iter().filter(|input| input.iter.is_some()).count();
+        stats.peak_loaded_blocks = stats.peak_loaded_blocks.max(loaded_blocks);
+        // the input with the smallest key, the newest one on ties
+        let Some(cur) = (0..inputs.len())
+            .filter(|&idx| inputs[idx].is_valid())
+            .min_by(|&a, &b| inputs[a].key().cmp(inputs[b].key()).then(a.cmp(&b)))
+        else {
+            break;
+        };
+        let input = &inputs[cur];
+        if input.iter.is_none() {
+            let meta = &input.table.block_metas()[input.block_idx];
+            let after_last = last_key.as_ref().map_or(true, |key| meta.first_key > key);
+            let before_others = inputs
+                .iter()
+                .enumerate()
+                .all(|(idx, other)| idx == cur || !other.is_valid() || meta.last_key < other.key());
+            if after_last && before_others {
+                let encoded = input.table.read_encoded_block(input.block_idx)?;
+                let reference = match dedup {
+                    Some(dedup) => {
+                        let content_hash =
+                            SsTable::decode_block(input.block_idx, &encoded)?.content_hash();
+                        dedup.find(content_hash, &encoded)?
+                    }
+                    None => None,
+                };
+                match reference {
+                    Some(reference) => {
+                        builder.add_block_reference(
+                            reference,
+                            meta.num_entries,
+                            &meta.first_key,
+                            &meta.last_key,
+                        );
+                        stats.deduped_blocks += 1;
+                    }
+                    None => builder.add_encoded_block(
+                        Bytes::from(encoded),
+                        &meta.first_key,
+                        &meta.last_key,
+                    )?,
+                }
+                last_key = Some(meta.last_key.clone());
+                stats.copied_blocks += 1;
+                inputs[cur].block_idx += 1;
+                continue;
+            }
+            inputs[cur].next(&mut stats)?;
+            continue;
+        }
+        let iter = input.iter.as_ref().unwrap();
+        stats.decoded_entries += 1;
+        if last_key.as_ref().map_or(true, |key| key != iter.key()) {
+            builder.try_add(iter.key(), iter.value())?;
+            last_key = Some(Bytes::copy_from_slice(iter.key()));
+        }
+        inputs[cur].next(&mut stats)?;
+    }
+    let mut sst = builder.build_spilled(id, block_cache)?;
+    if let Some(dedup) = dedup {
+        sst = sst.with_block_sources(dedup.tables().cloned());
+    }
+    Ok((sst, stats))
+}
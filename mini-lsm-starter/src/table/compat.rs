@@ -0,0 +1,244 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use bytes::{Buf, Bytes};
+
+use super::{BlockMeta, FileObject, Footer, SsTable, TableError, FOOTER_SIZE};
+use crate::block::BlockBuilder;
+
+/// The target block size used when re-packing a reference table, the default of upstream mini-lsm.
+const REPACK_BLOCK_SIZE: usize = 4096;
+
+/// Compute the CRC32 (IEEE) checksum of `data`, as `crc32fast::hash` does in upstream mini-lsm.
+pub(super) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Split the trailing u32 checksum off `buf` and check it against the rest.
+fn verify_checksum<'a>(buf: &'a [u8], what: &'static str) -> Result<&'a [u8]> {
+    if buf.len() < 4 {
+        bail!("{} too small for a checksum", what);
+    }
+    let (data, checksum) = buf.split_at(buf.len() - 4);
+    let expected = (&checksum[..]).get_u32();
+    let actual = crc32(data);
+    if actual != expected {
+        return Err(TableError::ChecksumMismatch {
+            what,
+            expected,
+            actual,
+        }
+        .into());
+    }
+    Ok(data)
+}
+
+/// Decode the entries of a reference data block, whose keys are stored relative to the first key.
+fn decode_reference_block(data: &[u8]) -> Result<Vec<(Bytes, Bytes)>> {
+    if data.len() < 2 {
+        bail!("block too small");
+    }
+    let num_of_elements = (&data[data.len() - 2..]).get_u16() as usize;
+    if data.len() < 2 + num_of_elements * 2 {
+        bail!("block too small for {} entries", num_of_elements);
+    }
+    let mut buf = &data[..data.len() - 2 - num_of_elements * 2];
+    let mut first_key: Vec<u8> = Vec::new();
+    let mut result = Vec::with_capacity(num_of_elements);
+    for idx in 0..num_of_elements {
+        if buf.remaining() < 4 {
+            bail!("entry {} truncated", idx);
+        }
+        let overlap_len = buf.get_u16() as usize;
+        let rest_len = buf.get_u16() as usize;
+        if overlap_len > first_key.len() || buf.remaining() < rest_len + 2 {
+            bail!("entry {} truncated", idx);
+        }
+        let mut key = first_key[..overlap_len].to_vec();
+        key.extend_from_slice(&buf[..rest_len]);
+        buf.advance(rest_len);
+        let value_len = buf.get_u16() as usize;
+        if buf.remaining() < value_len {
+            bail!("entry {} truncated", idx);
+        }
+        let value = Bytes::copy_from_slice(&buf[..value_len]);
+        buf.advance(value_len);
+        if idx == 0 {
+            first_key = key.clone();
+        }
+        result.push((Bytes::from(key), value));
+    }
+    Ok(result)
+}
+
+/// Lay out a single re-packed block at the end of `data`, padded to the 4196-byte alignment.
+fn finish_block(
+    builder: BlockBuilder,
+    first_key: Bytes,
+    last_key: Bytes,
+    data: &mut Vec<u8>,
+    metas: &mut Vec<BlockMeta>,
+) {
+    let block = builder.build();
+    let encoded = block.encode();
+    let start = data.len() as u32;
+    data.extend_from_slice(&encoded);
+    data.resize(start as usize + 4196, 0);
+    metas.push(BlockMeta {
+        offset: start + encoded.len() as u32,
+        num_entries: block.num_of_entries() as u16,
+        key_len: first_key.len() as u16,
+        first_key,
+        last_key,
+        reference: None,
+        start,
+        packed: false,
+    });
+}
+
+impl SsTable {
+    /// Open an SSTable written by upstream mini-lsm (the week 1 layout, without MVCC timestamps).
+    ///
+    /// The reference layout is
+    /// ```text
+    /// | Block #1 | crc32 | ... | Block #N | crc32 | Meta Section | Meta Offset (u32) | Bloom Section | Bloom Offset (u32) |
+    /// Meta Section:  | num_of_metas (u32) | (offset, first_key_len, first_key, last_key_len, last_key) * N | crc32 |
+    /// Bloom Section: | filter | k (u8) | crc32 |
+    /// ```
+    /// and differs from the layout of this crate in the following ways, all handled here:
+    /// * Data blocks are packed back to back, each followed by its own crc32, instead of being
+    ///   padded to 4196 bytes. `BlockMeta::offset` is the start of the block, not its end.
+    /// * Inside a block, each key is stored as the length of the prefix shared with the first key
+    ///   of the block followed by the rest of the key, instead of in full.
+    /// * The meta section is prefixed with the number of metas and followed by a crc32, and
+    ///   `first_key`/`last_key` are stored in full instead of prefix-shared with the previous meta.
+    /// * A bloom filter section with its own crc32 follows the meta offset, and the footer is the
+    ///   bloom offset. This crate has no bloom filter, so the filter is checked and dropped.
+    ///
+    /// Both layouts store every integer in big-endian and encode the offset section of a block
+    /// the same way, so no byte swapping is needed. All checksums are verified, and the entries
+    /// are re-packed into the layout of this crate in memory; the file itself is not modified.
+    pub fn open_reference(path: impl AsRef<Path>) -> Result<Self> {
+        let file = std::fs::read(path)?;
+        let len = file.len();
+        if len < 4 {
+            bail!("reference sst too small");
+        }
+        let bloom_offset = (&file[len - 4..]).get_u32() as usize;
+        if bloom_offset < 4 || bloom_offset > len - 4 {
+            bail!("bloom offset {} out of range", bloom_offset);
+        }
+        verify_checksum(&file[bloom_offset..len - 4], "bloom filter")?;
+        let meta_offset = (&file[bloom_offset - 4..bloom_offset]).get_u32() as usize;
+        if meta_offset > bloom_offset - 4 {
+            bail!("block meta offset {} out of range", meta_offset);
+        }
+        let mut meta_buf = &file[meta_offset..bloom_offset - 4];
+        if meta_buf.len() < 4 {
+            bail!("block meta too small");
+        }
+        let num_of_metas = meta_buf.get_u32() as usize;
+        let mut meta_buf = verify_checksum(meta_buf, "block meta")?;
+        let mut block_offsets = Vec::with_capacity(num_of_metas);
+        for _ in 0..num_of_metas {
+            if meta_buf.remaining() < 6 {
+                bail!("block meta truncated");
+            }
+            block_offsets.push(meta_buf.get_u32() as usize);
+            let first_key_len = meta_buf.get_u16() as usize;
+            if meta_buf.remaining() < first_key_len + 2 {
+                bail!("block meta truncated");
+            }
+            meta_buf.advance(first_key_len);
+            let last_key_len = meta_buf.get_u16() as usize;
+            if meta_buf.remaining() < last_key_len {
+                bail!("block meta truncated");
+            }
+            meta_buf.advance(last_key_len);
+        }
+        if meta_buf.has_remaining() {
+            bail!("{} trailing bytes in block meta", meta_buf.remaining());
+        }
+
+        let mut data = Vec::new();
+        let mut metas = Vec::new();
+        let mut builder = BlockBuilder::new(REPACK_BLOCK_SIZE);
+        let mut first_key = Bytes::new();
+        let mut last_key = Bytes::new();
+        for (block_idx, &start) in block_offsets.iter().enumerate() {
+            let end = block_offsets
+                .get(block_idx + 1)
+                .copied()
+                .unwrap_or(meta_offset);
+            if start > end || end > meta_offset {
+                bail!("block {} out of range", block_idx);
+            }
+            let block = verify_checksum(&file[start..end], "data block")?;
+            for (key, value) in decode_reference_block(block)? {
+                if !builder.add(&key, &value) {
+                    let full =
+                        std::mem::replace(&mut builder, BlockBuilder::new(REPACK_BLOCK_SIZE));
+                    finish_block(full, first_key, last_key.clone(), &mut data, &mut metas);
+                    first_key = Bytes::new();
+                    if !builder.add(&key, &value) {
+                        bail!("entry {:?} too large to re-pack", key);
+                    }
+                }
+                if first_key.is_empty() {
+                    first_key = key.clone();
+                }
+                last_key = key;
+            }
+        }
+        if !builder.is_empty() {
+            finish_block(builder, first_key, last_key, &mut data, &mut metas);
+        }
+
+        // the blocks are re-packed without compression
+        let block_bytes: u64 = metas
+            .iter()
+            .enumerate()
+            .map(|(idx, meta)| (meta.offset - idx as u32 * 4196) as u64)
+            .sum();
+        let block_meta_offset = data.len() as u32;
+        BlockMeta::encode_block_meta(&metas, &mut data);
+        // the bloom filter of the reference table is dropped
+        let footer = Footer {
+            block_meta_offset,
+            bloom_offset: data.len() as u32,
+            entry_count: metas.iter().map(|meta| meta.num_entries as u64).sum(),
+            max_ts: 0,
+            uncompressed_bytes: block_bytes,
+            compressed_bytes: block_bytes,
+            table_id: 0,
+            index_sparsity: 1,
+            properties_len: 0,
+        };
+        footer.encode(&mut data);
+        let footer_offset = (data.len() - FOOTER_SIZE) as u64;
+        Ok(Self {
+            file: FileObject::from_bytes(Bytes::from(data)),
+            block_metas: metas.into(),
+            block_meta_offset,
+            id: 0,
+            block_cache: None,
+            footer,
+            properties: BTreeMap::new().into(),
+            footer_offset,
+            block_sources: BTreeMap::new(),
+            disk_index: None,
+        })
+    }
+}
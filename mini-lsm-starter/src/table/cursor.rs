@@ -0,0 +1,76 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use super::{SsTable, SsTableIterator};
+use crate::iterators::StorageIterator;
+
+/// A cursor over an SSTable for range queries. After positioning the cursor with `seek` or
+/// `seek_for_prev`, each call to `next` yields one entry, starting from the one the cursor landed
+/// on. A cursor that was never positioned starts from the first entry of the table.
+pub struct SsTableCursor {
+    table: Arc<SsTable>,
+    /// The underlying iterator, created on the first positioning.
+    iter: Option<SsTableIterator>,
+    /// Whether the current entry has already been yielded by `next`.
+    yielded: bool,
+}
+
+impl SsTableCursor {
+    pub(super) fn new(table: Arc<SsTable>) -> Self {
+        Self {
+            table,
+            iter: None,
+            yielded: false,
+        }
+    }
+
+    /// Position the cursor on the first entry whose key >= `key`.
+    pub fn seek(&mut self, key: &[u8]) -> Result<()> {
+        match &mut self.iter {
+            Some(iter) => iter.seek_to_key(key)?,
+            None => {
+                self.iter = Some(SsTableIterator::create_and_seek_to_key(
+                    self.table.clone(),
+                    key,
+                )?)
+            }
+        }
+        self.yielded = false;
+        Ok(())
+    }
+
+    /// Position the cursor on the last entry whose key <= `key`.
+    pub fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        if self.iter.is_none() {
+            self.iter = Some(SsTableIterator::create_and_seek_to_first(
+                self.table.clone(),
+            )?);
+        }
+        self.iter.as_mut().unwrap().seek_for_prev(key)?;
+        self.yielded = false;
+        Ok(())
+    }
+
+    /// Yield the next entry, or `None` once the cursor moves past the end of the table.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<Result<(&[u8], &[u8])>> {
+        if self.iter.is_none() {
+            match SsTableIterator::create_and_seek_to_first(self.table.clone()) {
+                Ok(iter) => self.iter = Some(iter),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        let iter = self.iter.as_mut().unwrap();
+        if self.yielded && iter.is_valid() {
+            if let Err(err) = iter.next() {
+                return Some(Err(err));
+            }
+        }
+        self.yielded = true;
+        if !iter.is_valid() {
+            return None;
+        }
+        Some(Ok((iter.key(), iter.value())))
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use super::{BlockRef, SsTable};
+
+/// The data blocks of a set of tables by their content hash, so that a compaction writing a block
+/// identical to one of them records a reference to it instead of storing the block again, see
+/// `compact_with_dedup`. The tables are kept open, as the tables referencing their blocks read
+/// them from there.
+#[derive(Default)]
+pub struct DedupStore {
+    /// The stored block holding each content, by `Block::content_hash`.
+    blocks: HashMap<u64, BlockRef>,
+    tables: BTreeMap<usize, Arc<SsTable>>,
+}
+
+impl DedupStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add every data block of `table` to the store, reading each of them once. A block that is
+    /// itself a reference is recorded as the block it points to, so references never chain.
+    pub fn register(&mut self, table: &Arc<SsTable>) -> Result<()> {
+        for block_idx in 0..table.num_of_blocks() {
+            let block = table.read_block(block_idx)?;
+            let location = table.block_metas()[block_idx]
+                .reference
+                .unwrap_or(BlockRef {
+                    table_id: table.id(),
+                    block_idx,
+                });
+            self.blocks.entry(block.content_hash()).or_insert(location);
+        }
+        for source in table.block_sources.values() {
+            self.tables.insert(source.id(), source.clone());
+        }
+        self.tables.insert(table.id(), table.clone());
+        Ok(())
+    }
+
+    /// Find a stored block with the same encoded bytes as `encoded`, whose content hash is
+    /// `content_hash`. The bytes are compared, so a hash collision never yields a wrong block.
+    pub(super) fn find(&self, content_hash: u64, encoded: &[u8]) -> Result<Option<BlockRef>> {
+        let Some(location) = self.blocks.get(&content_hash) else {
+            return Ok(None);
+        };
+        let table = &self.tables[&location.table_id];
+        let stored = table.read_encoded_block(location.block_idx)?;
+        Ok((stored == encoded).then_some(*location))
+    }
+
+    /// Get the tables whose blocks are in the store.
+    pub fn tables(&self) -> impl Iterator<Item = &Arc<SsTable>> {
+        self.tables.values()
+    }
+
+    /// Get the number of distinct blocks in the store.
+    pub fn num_of_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+}
@@ -0,0 +1,249 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use bytes::{Buf, Bytes};
+
+use super::{BlockMeta, FileObject, Footer, TableError};
+
+/// The options of a table opened with a disk index, see `OpenOptions::disk_index`.
+#[derive(Clone, Copy, Debug)]
+pub struct DiskIndexOptions {
+    /// The number of consecutive block metas loaded together by a lookup, at least 1.
+    pub chunk_len: usize,
+    /// The maximum number of decoded chunks kept in memory.
+    pub cached_chunks: u64,
+}
+
+impl Default for DiskIndexOptions {
+    fn default() -> Self {
+        Self {
+            chunk_len: 64,
+            cached_chunks: 16,
+        }
+    }
+}
+
+/// The bytes of the meta section read at a time while opening a table with a disk index.
+const OPEN_READ_LEN: u64 = 64 * 1024;
+
+/// The largest encoded block meta: the fixed fields, a block reference, and two keys of up to
+/// `u16::MAX` bytes each.
+const MAX_META_LEN: usize = 4 + 2 + 1 + 12 + 4 + u16::MAX as usize + 4 + u16::MAX as usize;
+
+/// The block metas of a table left on disk, for tables with so many blocks that
+/// `SsTable::block_metas` alone takes too much memory.
+///
+/// The metas form a two-level index: the metas are cut into chunks of `chunk_len`, and only the
+/// first key and the position of each chunk stay in memory. A lookup binary searches the chunks,
+/// then reads and decodes the single chunk that may hold the key, keeping the most recently used
+/// chunks in a small cache.
+pub struct DiskIndex {
+    /// The end of the meta section in the file.
+    block_meta_end: u64,
+    /// The first key of each chunk and the position of its first meta in the file.
+    chunks: Vec<(Bytes, u64)>,
+    chunk_len: usize,
+    num_of_blocks: usize,
+    /// The last key of the table, `None` if the table is empty.
+    last_key: Option<Bytes>,
+    /// The version of the block meta layout, to decode the chunks with.
+    meta_version: u8,
+    cached_chunks: moka::sync::Cache<usize, Arc<Vec<BlockMeta>>>,
+    /// The number of chunks read from the file.
+    meta_reads: AtomicUsize,
+}
+
+impl DiskIndex {
+    /// Scan the meta section of the table in `file` to find where the chunks start, keeping only
+    /// their first keys. The section is read `OPEN_READ_LEN` bytes at a time, so opening takes no
+    /// more memory than the chunk heads, however large the section is.
+    /// Tables built with a sparse index are not supported, as the chunks would not all start with
+    /// an indexed block, nor tables with block references or packed blocks, which fail with
+    /// `TableError::Unsupported`, as does a `chunk_len` of 0.
+    pub(crate) fn open(
+        file: &FileObject,
+        footer: &Footer,
+        options: &DiskIndexOptions,
+    ) -> Result<Self, TableError> {
+        if options.chunk_len == 0 {
+            return Err(TableError::Unsupported("disk index chunks of 0 metas"));
+        }
+        if footer.index_sparsity > 1 {
+            return Err(TableError::Unsupported("sparse block index"));
+        }
+        let block_meta_end = footer.bloom_offset as u64;
+        // the position in the file of `buf[0]`, and of the end of what was read so far
+        let mut buf_pos = footer.block_meta_offset as u64;
+        let mut read_end = buf_pos;
+        let mut buf = Vec::new();
+        let mut consumed = 0;
+        let mut meta_version = None;
+        let mut chunks = Vec::new();
+        let mut num_of_blocks = 0;
+        let mut prev_key = Bytes::new();
+        let mut last_key = None;
+        loop {
+            let mut cur = &buf[consumed..];
+            let decoded = match meta_version {
+                None => BlockMeta::decode_version(&mut cur).map(|version| {
+                    meta_version = Some(version);
+                    None
+                }),
+                // without references or packed blocks, each block takes the slot of its index
+                Some(version) => {
+                    let data_end = num_of_blocks as u32 * 4196;
+                    BlockMeta::decode_next(&mut cur, &prev_key, version, data_end).map(Some)
+                }
+            };
+            match decoded {
+                Ok(meta) => {
+                    let pos = buf_pos + consumed as u64;
+                    consumed = buf.len() - cur.len();
+                    let Some(meta) = meta else { continue };
+                    if meta.reference.is_some() {
+                        return Err(TableError::Unsupported("block references"));
+                    }
+                    if meta.is_packed() {
+                        return Err(TableError::Unsupported("packed blocks"));
+                    }
+                    if num_of_blocks % options.chunk_len == 0 {
+                        chunks.push((meta.first_key.clone(), pos));
+                    }
+                    prev_key = meta.first_key;
+                    last_key = Some(meta.last_key);
+                    num_of_blocks += 1;
+                }
+                // the meta may only be cut by the end of the bytes read so far
+                Err(err) if read_end == block_meta_end || buf.len() - consumed > MAX_META_LEN => {
+                    if consumed == buf.len() && meta_version.is_some() {
+                        break;
+                    }
+                    return Err(err);
+                }
+                Err(_) => {
+                    buf.drain(..consumed);
+                    buf_pos += consumed as u64;
+                    consumed = 0;
+                    let len = OPEN_READ_LEN.min(block_meta_end - read_end);
+                    let data = file.read(read_end, len).map_err(TableError::from_read)?;
+                    buf.extend_from_slice(&data);
+                    read_end += len;
+                }
+            }
+        }
+        Ok(Self {
+            block_meta_end,
+            chunks,
+            chunk_len: options.chunk_len,
+            num_of_blocks,
+            last_key,
+            meta_version: meta_version.unwrap_or_default(),
+            cached_chunks: moka::sync::Cache::new(options.cached_chunks),
+            meta_reads: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn num_of_blocks(&self) -> usize {
+        self.num_of_blocks
+    }
+
+    /// Get the number of chunks whose first key is kept in memory.
+    pub fn num_of_chunks(&self) -> usize {
+        self.chunks.len()
+    }
+
+    /// Get the number of times a chunk of metas was read from the file.
+    pub fn meta_reads(&self) -> usize {
+        self.meta_reads.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn first_key(&self) -> Option<&Bytes> {
+        self.chunks.first().map(|(first_key, _)| first_key)
+    }
+
+    pub(crate) fn last_key(&self) -> Option<&Bytes> {
+        self.last_key.as_ref()
+    }
+
+    /// Get the approximate number of heap bytes held by the chunk heads, leaving out the cache.
+    pub(crate) fn memory_footprint(&self) -> usize {
+        self.chunks.capacity() * std::mem::size_of::<(Bytes, u64)>()
+            + self
+                .chunks
+                .iter()
+                .map(|(first_key, _)| first_key.len())
+                .sum::<usize>()
+    }
+
+    /// Get the metas of a chunk, from the cache or else from `file`.
+    fn chunk(
+        &self,
+        file: &FileObject,
+        chunk_idx: usize,
+    ) -> Result<Arc<Vec<BlockMeta>>, TableError> {
+        if let Some(chunk) = self.cached_chunks.get(&chunk_idx) {
+            return Ok(chunk);
+        }
+        let (first_key, start) = &self.chunks[chunk_idx];
+        let end = self
+            .chunks
+            .get(chunk_idx + 1)
+            .map_or(self.block_meta_end, |(_, pos)| *pos);
+        self.meta_reads.fetch_add(1, Ordering::Relaxed);
+        let data = file
+            .read(*start, end - start)
+            .map_err(TableError::from_read)?;
+        let mut buf = &data[..];
+        let mut metas = Vec::with_capacity(self.chunk_len);
+        // the first key is known, and shares its prefix with itself
+        let mut prev_key = first_key.clone();
+        while buf.has_remaining() {
+            let data_end = (chunk_idx * self.chunk_len + metas.len()) as u32 * 4196;
+            let meta = BlockMeta::decode_next(&mut buf, &prev_key, self.meta_version, data_end)?;
+            prev_key = meta.first_key.clone();
+            metas.push(meta);
+        }
+        let metas = Arc::new(metas);
+        self.cached_chunks.insert(chunk_idx, metas.clone());
+        Ok(metas)
+    }
+
+    /// Get the meta of a block, reading its chunk from `file` unless it is cached.
+    pub(crate) fn block_meta(
+        &self,
+        file: &FileObject,
+        block_idx: usize,
+    ) -> Result<BlockMeta, TableError> {
+        if block_idx >= self.num_of_blocks {
+            return Err(TableError::BlockIndexOutOfRange {
+                block_idx,
+                num_of_blocks: self.num_of_blocks,
+            });
+        }
+        let chunk = self.chunk(file, block_idx / self.chunk_len)?;
+        chunk
+            .get(block_idx % self.chunk_len)
+            .cloned()
+            .ok_or_else(|| TableError::corruption(None, "block meta chunk truncated"))
+    }
+
+    /// Find the block that may contain `key`, like `SsTable::find_block_idx`, reading at most one
+    /// chunk of metas. If that read fails, the first block of the chunk is returned, so the error
+    /// shows up once the caller reads the block, which needs the same chunk.
+    pub(crate) fn find_block_idx(&self, file: &FileObject, key: &[u8]) -> usize {
+        if self.chunks.is_empty() {
+            return 0;
+        }
+        let chunk_idx = self
+            .chunks
+            .partition_point(|(first_key, _)| first_key <= key)
+            .saturating_sub(1);
+        let idx = match self.chunk(file, chunk_idx) {
+            Ok(chunk) => chunk
+                .partition_point(|meta| meta.first_key <= key)
+                .saturating_sub(1),
+            Err(_) => 0,
+        };
+        chunk_idx * self.chunk_len + idx
+    }
+}
@@ -0,0 +1,129 @@
+use std::fmt;
+
+/// The errors of opening and reading an SSTable, so that callers can tell a corrupted table from a
+/// failed read. It converts into `anyhow::Error`, and can be recovered with `downcast_ref`.
+#[derive(Debug)]
+pub enum TableError {
+    /// Reading the underlying file failed.
+    Io(std::io::Error),
+    /// The table content is inconsistent. `block_idx` is the data block at fault, `None` when the
+    /// corruption is outside of the data blocks, e.g. in the footer or the block metas.
+    Corruption {
+        block_idx: Option<usize>,
+        detail: String,
+    },
+    /// A section of the table was written with a layout version this build does not read.
+    UnsupportedVersion {
+        what: &'static str,
+        found: u8,
+        expected: u8,
+    },
+    /// A section of the table does not match its stored checksum.
+    ChecksumMismatch {
+        what: &'static str,
+        expected: u32,
+        actual: u32,
+    },
+    /// The table was opened as another table than the one it was built as.
+    IdMismatch { expected: usize, found: u64 },
+    /// The table uses a feature the reader does not support.
+    Unsupported(&'static str),
+    /// A block past the end of the table was requested.
+    BlockIndexOutOfRange {
+        block_idx: usize,
+        num_of_blocks: usize,
+    },
+    /// A block is a reference to a block of a table that was not registered with
+    /// `SsTable::with_block_sources`.
+    MissingBlockSource { block_idx: usize, table_id: usize },
+    /// The read was cancelled by its caller, see `SsTableIterator::set_cancellation`.
+    Cancelled,
+}
+
+impl TableError {
+    pub(crate) fn corruption(block_idx: Option<usize>, detail: impl Into<String>) -> Self {
+        Self::Corruption {
+            block_idx,
+            detail: detail.into(),
+        }
+    }
+
+    /// Convert an error of `FileObject::read` into `Io`, keeping the underlying `io::Error` when
+    /// there is one.
+    pub(crate) fn from_read(err: anyhow::Error) -> Self {
+        match err.downcast::<std::io::Error>() {
+            Ok(err) => Self::Io(err),
+            Err(err) => Self::Io(std::io::Error::new(std::io::ErrorKind::Other, err)),
+        }
+    }
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "I/O error: {}", err),
+            Self::Corruption {
+                block_idx: Some(block_idx),
+                detail,
+            } => write!(f, "corrupted block {}: {}", block_idx, detail),
+            Self::Corruption {
+                block_idx: None,
+                detail,
+            } => write!(f, "corrupted table: {}", detail),
+            Self::UnsupportedVersion {
+                what,
+                found,
+                expected,
+            } => write!(
+                f,
+                "unsupported {} version {}, expected {}",
+                what, found, expected
+            ),
+            Self::ChecksumMismatch {
+                what,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} checksum mismatch: expected {:#010x}, got {:#010x}",
+                what, expected, actual
+            ),
+            Self::IdMismatch { expected, found } => {
+                write!(f, "expected table {}, found table {}", expected, found)
+            }
+            Self::Unsupported(what) => write!(f, "unsupported table: {}", what),
+            Self::BlockIndexOutOfRange {
+                block_idx,
+                num_of_blocks,
+            } => write!(
+                f,
+                "block index {} out of range for {} blocks",
+                block_idx, num_of_blocks
+            ),
+            Self::MissingBlockSource {
+                block_idx,
+                table_id,
+            } => write!(
+                f,
+                "block {} refers to table {}, which is not a block source",
+                block_idx, table_id
+            ),
+            Self::Cancelled => write!(f, "read cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for TableError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for TableError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
@@ -0,0 +1,156 @@
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+/// The open handles of the files of many tables, at most `max_open` of them at once, so that a
+/// process reading from more tables than it may open files does not run out of file descriptors.
+/// The least recently used handle is closed to make room for another one, and a file whose handle
+/// was closed is opened again on its next read. Shared by the tables opened with
+/// `FileObject::open_with_handle_cache`.
+///
+/// The bound is soft: a handle is only closed once the reads using it are done, so while more
+/// than `max_open` reads run at once, as many files may be open, see `live_handles`. Handles not
+/// in use by a read are closed first.
+pub struct FileHandleCache {
+    max_open: usize,
+    /// The open handles by file id, the least recently used first.
+    handles: Mutex<Vec<(u64, Arc<Handle>)>>,
+    /// The id given to the next file.
+    next_id: AtomicU64,
+    /// The number of times a file was opened.
+    opens: AtomicUsize,
+    /// The number of handles not closed yet, in the cache or in use by a read.
+    live: Arc<AtomicUsize>,
+}
+
+/// An open file, counted in `FileHandleCache::live` until it is closed.
+pub(super) struct Handle {
+    file: File,
+    live: Arc<AtomicUsize>,
+}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.live.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl FileHandleCache {
+    /// Create a cache keeping at most `max_open` files open.
+    pub fn new(max_open: usize) -> Self {
+        assert!(max_open > 0, "at least one file must be open to read it");
+        Self {
+            max_open,
+            handles: Mutex::new(Vec::new()),
+            next_id: AtomicU64::new(0),
+            opens: AtomicUsize::new(0),
+            live: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Get an id for a new file, which keys its handle.
+    pub(super) fn register(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Close the handle of the file `id`, if open, e.g. when the file is dropped.
+    pub(super) fn forget(&self, id: u64) {
+        self.handles
+            .lock()
+            .retain(|(handle_id, _)| *handle_id != id);
+    }
+
+    /// Read `len` bytes at `offset` of the file `id` at `path` into `buf`, opening the file first
+    /// if its handle is not open. A handle closed by another read while in use stays valid until
+    /// the read is done.
+    pub(super) fn read_at(
+        &self,
+        id: u64,
+        path: &Path,
+        offset: u64,
+        len: u64,
+        buf: &mut Vec<u8>,
+    ) -> std::io::Result<()> {
+        let handle = self.handle(id, path)?;
+        buf.clear();
+        buf.resize(len as usize, 0);
+        read_exact_at(&handle.file, buf, offset)
+    }
+
+    /// Get the handle of the file `id`, marking it as the most recently used one. The file is
+    /// opened without holding the lock, so that reads of other files do not wait for it.
+    pub(super) fn handle(&self, id: u64, path: &Path) -> std::io::Result<Arc<Handle>> {
+        if let Some(handle) = Self::touch(&mut self.handles.lock(), id) {
+            return Ok(handle);
+        }
+        let file = File::open(path)?;
+        self.opens.fetch_add(1, Ordering::Relaxed);
+        self.live.fetch_add(1, Ordering::Relaxed);
+        let handle = Arc::new(Handle {
+            file,
+            live: self.live.clone(),
+        });
+        let mut handles = self.handles.lock();
+        // another read of the file may have opened it in the meantime, whose handle is kept
+        if let Some(handle) = Self::touch(&mut handles, id) {
+            return Ok(handle);
+        }
+        if handles.len() >= self.max_open {
+            // the least recently used handle that no read is using, else the least recently used
+            let pos = handles
+                .iter()
+                .position(|(_, handle)| Arc::strong_count(handle) == 1)
+                .unwrap_or(0);
+            handles.remove(pos);
+        }
+        handles.push((id, handle.clone()));
+        Ok(handle)
+    }
+
+    /// Get the handle of the file `id` if it is open, marking it as the most recently used one.
+    fn touch(handles: &mut Vec<(u64, Arc<Handle>)>, id: u64) -> Option<Arc<Handle>> {
+        let pos = handles.iter().position(|(handle_id, _)| *handle_id == id)?;
+        let entry = handles.remove(pos);
+        let handle = entry.1.clone();
+        handles.push(entry);
+        Some(handle)
+    }
+
+    /// Get the number of handles kept open by the cache, at most `max_open`.
+    pub fn open_handles(&self) -> usize {
+        self.handles.lock().len()
+    }
+
+    /// Get the number of files currently open, the handles kept by the cache along with the
+    /// ones it closed that reads still use.
+    pub fn live_handles(&self) -> usize {
+        self.live.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of times a file was opened, reopening a closed one included.
+    pub fn opens(&self) -> usize {
+        self.opens.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> std::io::Result<()> {
+    while !buf.is_empty() {
+        match std::os::windows::fs::FileExt::seek_read(file, buf, offset)? {
+            0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,164 @@
+use bytes::Buf;
+
+use super::compat::crc32;
+use super::TableError;
+
+/// Identifies the footer of an SSTable written by this crate, "MLSM".
+const FOOTER_MAGIC: u32 = 0x4d4c_534d;
+
+/// The version of the footer layout. Version 2 added `table_id`, version 3 `index_sparsity`,
+/// version 4 `properties_len`.
+const FOOTER_VERSION: u8 = 4;
+
+/// The length of an encoded footer.
+pub(super) const FOOTER_SIZE: usize = 65;
+
+/// The footer ending every SSTable file. It has a fixed length, so opening a table starts by
+/// reading the last `FOOTER_SIZE` bytes, which locate every other section. It is laid out as:
+/// ------------------------------------------------------------------------------------------------------------------------
+/// | block_meta_offset (u32) | bloom_offset (u32) | entry_count (u64) | max_ts (u64) | uncompressed_bytes (u64) | ...
+/// ------------------------------------------------------------------------------------------------------------------------
+/// ... | compressed_bytes (u64) | table_id (u64) | index_sparsity (u32) | properties_len (u32) | ...
+/// ---------------------------------------------------------------------------------------------
+/// ... | version (u8) | checksum (u32) | magic (u32) |
+/// ---------------------------------------------------
+/// where the checksum is the CRC32 of all the bytes before it. New fields go in front of the
+/// version, along with a version bump.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Footer {
+    /// The offset of the meta section, which is also the length of the data section.
+    pub block_meta_offset: u32,
+    /// The offset of the bloom filter section, which runs up to the properties section and ends
+    /// the meta section. Tables are written without a filter for now, so the section is empty.
+    pub bloom_offset: u32,
+    /// The number of entries in the table, tombstones included.
+    pub entry_count: u64,
+    /// The largest timestamp of the MVCC keys of the table, 0 if unknown.
+    pub max_ts: u64,
+    /// The total encoded length of the data blocks with every value stored plain.
+    pub uncompressed_bytes: u64,
+    /// The total encoded length of the data blocks as stored, without the alignment padding.
+    pub compressed_bytes: u64,
+    /// The id the table was built with.
+    pub table_id: u64,
+    /// Every how many blocks the metas store the keys of their block, see
+    /// `SsTableBuilder::set_index_sparsity`. 1 for a dense index, and 0 is read as 1.
+    pub index_sparsity: u32,
+    /// The length of the properties section, which sits right before the footer and ends the
+    /// bloom filter section, 0 if the table has no property.
+    pub properties_len: u32,
+}
+
+impl Footer {
+    /// Append the encoded footer to `buf`.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        let start = buf.len();
+        buf.extend_from_slice(&self.block_meta_offset.to_be_bytes());
+        buf.extend_from_slice(&self.bloom_offset.to_be_bytes());
+        buf.extend_from_slice(&self.entry_count.to_be_bytes());
+        buf.extend_from_slice(&self.max_ts.to_be_bytes());
+        buf.extend_from_slice(&self.uncompressed_bytes.to_be_bytes());
+        buf.extend_from_slice(&self.compressed_bytes.to_be_bytes());
+        buf.extend_from_slice(&self.table_id.to_be_bytes());
+        buf.extend_from_slice(&self.index_sparsity.to_be_bytes());
+        buf.extend_from_slice(&self.properties_len.to_be_bytes());
+        buf.push(FOOTER_VERSION);
+        let checksum = crc32(&buf[start..]);
+        buf.extend_from_slice(&checksum.to_be_bytes());
+        buf.extend_from_slice(&FOOTER_MAGIC.to_be_bytes());
+    }
+
+    /// Decode a footer from exactly `FOOTER_SIZE` bytes, checking its magic, its checksum and its
+    /// version, in that order.
+    pub fn decode(data: &[u8]) -> Result<Self, TableError> {
+        if data.len() != FOOTER_SIZE {
+            return Err(TableError::corruption(
+                None,
+                format!("footer of {} bytes, expected {}", data.len(), FOOTER_SIZE),
+            ));
+        }
+        let (body, mut trailer) = data.split_at(FOOTER_SIZE - 8);
+        let expected = trailer.get_u32();
+        if trailer.get_u32() != FOOTER_MAGIC {
+            return Err(TableError::corruption(None, "bad footer magic"));
+        }
+        let actual = crc32(body);
+        if actual != expected {
+            return Err(TableError::ChecksumMismatch {
+                what: "footer",
+                expected,
+                actual,
+            });
+        }
+        let version = body[body.len() - 1];
+        if version != FOOTER_VERSION {
+            return Err(TableError::UnsupportedVersion {
+                what: "footer",
+                found: version,
+                expected: FOOTER_VERSION,
+            });
+        }
+        let mut buf = body;
+        Ok(Self {
+            block_meta_offset: buf.get_u32(),
+            bloom_offset: buf.get_u32(),
+            entry_count: buf.get_u64(),
+            max_ts: buf.get_u64(),
+            uncompressed_bytes: buf.get_u64(),
+            compressed_bytes: buf.get_u64(),
+            table_id: buf.get_u64(),
+            index_sparsity: buf.get_u32(),
+            properties_len: buf.get_u32(),
+        })
+    }
+}
+
+/// The footer bytes of a table along with the fields read from them without any check, for
+/// diagnosing a table that fails to open, see `SsTable::read_footer_bytes`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RawFooter {
+    /// The last `FOOTER_SIZE` bytes of the file.
+    pub bytes: Vec<u8>,
+    /// The offset of the meta section, as the footer records it.
+    pub block_meta_offset: u32,
+    /// The version of the footer layout, as the footer records it.
+    pub version: u8,
+    /// The magic ending the footer, which a table written by this crate sets to "MLSM".
+    pub magic: u32,
+    /// The checksum recorded by the footer.
+    pub checksum: u32,
+}
+
+impl RawFooter {
+    /// Read the fields of the footer from `bytes`, which must be `FOOTER_SIZE` bytes long.
+    pub(super) fn new(bytes: Vec<u8>) -> Self {
+        let mut buf = &bytes[..];
+        let block_meta_offset = buf.get_u32();
+        let mut trailer = &bytes[FOOTER_SIZE - 9..];
+        let version = trailer.get_u8();
+        let checksum = trailer.get_u32();
+        let magic = trailer.get_u32();
+        Self {
+            block_meta_offset,
+            version,
+            magic,
+            checksum,
+            bytes,
+        }
+    }
+
+    /// Whether the magic is the one of a table written by this crate.
+    pub fn has_valid_magic(&self) -> bool {
+        self.magic == FOOTER_MAGIC
+    }
+
+    /// Whether the checksum matches the bytes it covers.
+    pub fn has_valid_checksum(&self) -> bool {
+        crc32(&self.bytes[..FOOTER_SIZE - 8]) == self.checksum
+    }
+
+    /// Decode the footer with every check `open` does on it, see `Footer::decode`.
+    pub fn decode(&self) -> Result<Footer, TableError> {
+        Footer::decode(&self.bytes)
+    }
+}
@@ -0,0 +1,102 @@
+use super::{shared_prefix_len, BlockMeta};
+
+/// Every `RESTART_INTERVAL`-th key of a `BlockIndex` is stored in full.
+const RESTART_INTERVAL: usize = 16;
+
+/// A compact in-memory index over the `first_key`s of the data blocks.
+/// All keys live in a single arena, each stored as the length of the prefix shared with the
+/// previous key plus the remaining suffix. Every `RESTART_INTERVAL`-th key is stored in full, so a
+/// search binary-searches those restart keys and then rebuilds at most `RESTART_INTERVAL` keys on
+/// the fly.
+///
+/// The index is built from the metas on demand, e.g. by a caller keeping many tables' indexes
+/// around once their metas are dropped. `SsTable` itself still holds its `BlockMeta`s and does
+/// not use it.
+pub struct BlockIndex {
+    arena: Vec<u8>,
+    /// The shared prefix length and the end offset in `arena` of each key's suffix.
+    /// The suffix starts where the suffix of the previous key ends.
+    entries: Vec<(u16, u32)>,
+}
+
+impl BlockIndex {
+    /// Build the index from the block metas of a table, whose index must be dense.
+    pub fn from_metas(block_metas: &[BlockMeta]) -> Self {
+        let mut arena = Vec::new();
+        let mut entries = Vec::with_capacity(block_metas.len());
+        let mut prev_key: &[u8] = &[];
+        for (idx, meta) in block_metas.iter().enumerate() {
+            let shared_len = if idx % RESTART_INTERVAL == 0 {
+                0
+            } else {
+                shared_prefix_len(prev_key, &meta.first_key)
+            };
+            arena.extend_from_slice(&meta.first_key[shared_len..]);
+            entries.push((shared_len as u16, arena.len() as u32));
+            prev_key = &meta.first_key;
+        }
+        arena.shrink_to_fit();
+        Self { arena, entries }
+    }
+
+    /// Get the number of blocks in the index.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if the index is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Get the number of bytes held by the key arena.
+    pub fn arena_size(&self) -> usize {
+        self.arena.len()
+    }
+
+    fn suffix(&self, idx: usize) -> &[u8] {
+        let start = if idx == 0 {
+            0
+        } else {
+            self.entries[idx - 1].1 as usize
+        };
+        &self.arena[start..self.entries[idx].1 as usize]
+    }
+
+    /// Rebuild the `first_key` of block `idx` into `buf`.
+    pub fn first_key(&self, idx: usize, buf: &mut Vec<u8>) {
+        buf.clear();
+        for idx in idx / RESTART_INTERVAL * RESTART_INTERVAL..=idx {
+            buf.truncate(self.entries[idx].0 as usize);
+            buf.extend_from_slice(self.suffix(idx));
+        }
+    }
+
+    /// Find the block that may contain `key`, with the same result as `SsTable::find_block_idx`.
+    pub fn find_block_idx(&self, key: &[u8]) -> usize {
+        let num_of_restarts = (self.entries.len() + RESTART_INTERVAL - 1) / RESTART_INTERVAL;
+        let mut low = 0;
+        let mut high = num_of_restarts;
+        while low < high {
+            let mid = (low + high) / 2;
+            if self.suffix(mid * RESTART_INTERVAL) <= key {
+                low = mid + 1;
+            } else {
+                high = mid;
+            }
+        }
+        let restart = low.saturating_sub(1) * RESTART_INTERVAL;
+        let end = (restart + RESTART_INTERVAL).min(self.entries.len());
+        let mut buf = Vec::new();
+        let mut block_idx = restart;
+        for idx in restart..end {
+            buf.truncate(self.entries[idx].0 as usize);
+            buf.extend_from_slice(self.suffix(idx));
+            if buf.as_slice() > key {
+                break;
+            }
+            block_idx = idx;
+        }
+        block_idx
+    }
+}
@@ -1,52 +1,163 @@
 #![allow(unused_variables)] // TODO(you): remove this lint after implementing this mod
 #![allow(dead_code)] // TODO(you): remove this lint after implementing this mod
 
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
 
-use super::SsTable;
-use crate::{block::BlockIterator, iterators::StorageIterator};
+use super::{SsTable, TableError};
+use crate::block::{Block, BlockIterator};
+use crate::iterators::StorageIterator;
+use crate::key::{self, TS_LEN};
 
 /// An iterator over the contents of an SSTable.
 pub struct SsTableIterator {
     table: Arc<SsTable>,
     block_idx: usize,
     cur_block_iterator: BlockIterator,
+    /// The error of the last failed block read, the iterator stays invalid until the next seek.
+    error: Option<anyhow::Error>,
+    /// Whether values are only read from the block when `value` is called.
+    lazy_values: bool,
+    /// Whether blocks are read through the block cache of the table, see `SsTable::scan_cached`.
+    cached_reads: bool,
+    /// The flag that cancels the iterator once set, see `set_cancellation`.
+    cancellation: Option<Arc<AtomicBool>>,
 }
 
 impl SsTableIterator {
     /// Create a new iterator and seek to the first key-value pair in the first data block.
+    /// The iterator is invalid if the table has no data block.
     pub fn create_and_seek_to_first(table: Arc<SsTable>) -> Result<Self> {
-        let block = table.read_block(0)?;
+        let block = if table.num_of_blocks() == 0 {
+            // the encoding of a block without any entry
+            Arc::new(Block::decode(&[0, 0]))
+        } else {
+            table.read_block(0)?
+        };
         let cur_block_iterator = BlockIterator::create_and_seek_to_first(block);
         Ok(Self {
             table,
             block_idx: 0,
             cur_block_iterator,
+            error: None,
+            lazy_values: false,
+            cached_reads: false,
+            cancellation: None,
+        })
+    }
+
+    /// Create an iterator reading every block through the block cache, and seek to the first
+    /// key-value pair, see `SsTable::scan_cached`.
+    pub(super) fn create_cached_and_seek_to_first(table: Arc<SsTable>) -> Result<Self> {
+        let mut iter = Self {
+            table,
+            block_idx: 0,
+            // the encoding of a block without any entry
+            cur_block_iterator: BlockIterator::new(Arc::new(Block::decode(&[0, 0]))),
+            error: None,
+            lazy_values: false,
+            cached_reads: true,
+            cancellation: None,
+        };
+        iter.seek_to_first()?;
+        Ok(iter)
+    }
+
+    /// Set whether `next` and the seeks skip copying values, off by default.
+    /// In this mode `value` reads the value from the current block on demand, which saves the copy
+    /// for every entry a scan filtering on keys skips over. Takes effect from the next move.
+    pub fn set_lazy_values(&mut self, lazy_values: bool) {
+        self.lazy_values = lazy_values;
+        self.cur_block_iterator.set_lazy_value(lazy_values);
+    }
+
+    /// Returns the timestamp of the current MVCC key, see `BlockIterator::timestamp`.
+    /// Returns 0 unless the table was built with timestamps, i.e. with a `max_ts` set by
+    /// `SsTableBuilder::set_max_ts`, as well as for keys shorter than `TS_LEN` and when the
+    /// iterator is invalid.
+    pub fn timestamp(&self) -> u64 {
+        let key = self.cur_block_iterator.key();
+        if self.table.footer().max_ts == 0 || key.len() < TS_LEN {
+            return 0;
+        }
+        key::split_ts(key).1
+    }
+
+    /// Returns the length of the current value without copying it, see `BlockIterator::value_len`.
+    pub fn value_len(&self) -> usize {
+        self.cur_block_iterator.value_len()
+    }
+
+    /// Returns the current key and value sharing the buffer of the current block, so that they can
+    /// be kept across `next` without copying them, see `BlockIterator::current_entry`.
+    /// The iterator must be valid.
+    pub fn current_entry(&self) -> (Bytes, Bytes) {
+        self.cur_block_iterator.current_entry()
+    }
+
+    /// Create an iterator over `block` in the value mode of this iterator, without seeking it.
+    fn block_iterator(&self, block: Arc<Block>) -> BlockIterator {
+        let mut iter = BlockIterator::new(block);
+        iter.set_lazy_value(self.lazy_values);
+        iter
+    }
+
+    /// Get the error that made the iterator invalid, if a block read has failed.
+    pub fn error(&self) -> Option<&anyhow::Error> {
+        self.error.as_ref()
+    }
+
+    /// Cancel the iterator once `cancellation` is set, e.g. when the consumer of a long scan has
+    /// gone away. The flag is checked before each block read, so that a cancelled iterator stops
+    /// reading right away: the read fails with `TableError::Cancelled`, and the iterator stays
+    /// invalid like after any failed read.
+    pub fn set_cancellation(&mut self, cancellation: Arc<AtomicBool>) {
+        self.cancellation = Some(cancellation);
+    }
+
+    /// Read a block from the table, latching the error if the read fails. `sequential` tells
+    /// whether the block follows the one read before, see `SsTable::read_block_sequential`.
+    fn read_block(&mut self, block_idx: usize, sequential: bool) -> Result<Arc<Block>> {
+        let cancelled = self
+            .cancellation
+            .as_ref()
+            .map_or(false, |cancellation| cancellation.load(Ordering::Relaxed));
+        let block = if cancelled {
+            Err(TableError::Cancelled.into())
+        } else if self.cached_reads {
+            self.table.read_block_cached(block_idx)
+        } else if sequential {
+            self.table
+                .read_block_sequential(block_idx)
+                .map_err(Into::into)
+        } else {
+            self.table.read_block(block_idx).map_err(Into::into)
+        };
+        block.map_err(|err| {
+            self.error = Some(anyhow!("failed to read block {}: {:#}", block_idx, err));
+            err
         })
     }
 
     /// Seek to the first key-value pair in the first data block.
     pub fn seek_to_first(&mut self) -> Result<()> {
-        let block = self.table.read_block(0)?;
+        self.error = None;
+        if self.table.num_of_blocks() == 0 {
+            return Ok(());
+        }
+        let block = self.read_block(0, false)?;
         self.block_idx = 0;
-        self.cur_block_iterator = BlockIterator::create_and_seek_to_first(block);
+        self.cur_block_iterator = self.block_iterator(block);
+        self.cur_block_iterator.seek_to_first();
         Ok(())
     }
 
     /// Create a new iterator and seek to the first key-value pair which >= `key`.
     pub fn create_and_seek_to_key(table: Arc<SsTable>, key: &[u8]) -> Result<Self> {
-        let mut low = 0;
-        let mut high = table.block_metas.len();
-        while low < high {
-            let mid = (low + high) / 2;
-            if table.block_metas[mid].first_key.as_ref() > key {
-                high = mid;
-            } else {
-                low = mid + 1;
-            }
-        }
+        let low = Self::blocks_up_to(&table, key);
         if low == 0 {
             return Self::create_and_seek_to_first(table);
         }
@@ -54,11 +165,15 @@ impl SsTableIterator {
         let mut cur_block_iterator = BlockIterator::create_and_seek_to_key(block, key);
         let mut block_idx = low - 1;
         if !cur_block_iterator.is_valid() {
-            if low >= table.block_metas.len() {
+            if low >= table.num_of_blocks() {
                 return Ok(Self {
                     table,
                     block_idx: low,
                     cur_block_iterator,
+                    error: None,
+                    lazy_values: false,
+                    cached_reads: false,
+                    cancellation: None,
                 });
             }
             block_idx += 1;
@@ -69,39 +184,200 @@ impl SsTableIterator {
             table,
             block_idx,
             cur_block_iterator,
+            error: None,
+            lazy_values: false,
+            cached_reads: false,
+            cancellation: None,
         })
     }
 
+    /// Create a new iterator and seek to the first key-value pair which >= `key`, starting from
+    /// block `block_idx` instead of searching the index for it, e.g. for scan workers handed
+    /// `(block_idx, key)` start points by a coordinator. If every key of the block is smaller than
+    /// `key`, the iterator moves on to the first key of the next block. Fails with
+    /// `TableError::BlockIndexOutOfRange` if the table has no block `block_idx`.
+    pub fn create_and_seek_to_key_in_block(
+        table: Arc<SsTable>,
+        block_idx: usize,
+        key: &[u8],
+    ) -> Result<Self> {
+        if block_idx >= table.num_of_blocks() {
+            return Err(TableError::BlockIndexOutOfRange {
+                block_idx,
+                num_of_blocks: table.num_of_blocks(),
+            }
+            .into());
+        }
+        let mut iter = Self {
+            table,
+            block_idx,
+            // the encoding of a block without any entry
+            cur_block_iterator: BlockIterator::new(Arc::new(Block::decode(&[0, 0]))),
+            error: None,
+            lazy_values: false,
+            cached_reads: false,
+            cancellation: None,
+        };
+        let block = iter.read_block(block_idx, false)?;
+        iter.cur_block_iterator = iter.block_iterator(block);
+        iter.cur_block_iterator.seek_to_key(key);
+        if !iter.cur_block_iterator.is_valid() && block_idx + 1 < iter.table.num_of_blocks() {
+            let block = iter.read_block(block_idx + 1, false)?;
+            iter.block_idx += 1;
+            iter.cur_block_iterator = iter.block_iterator(block);
+            iter.cur_block_iterator.seek_to_first();
+        }
+        Ok(iter)
+    }
+
+    /// Create a new iterator and seek to the first key-value pair which > `key`, i.e. with `key` as
+    /// an exclusive lower bound.
+    pub fn create_and_seek_after(table: Arc<SsTable>, key: &[u8]) -> Result<Self> {
+        let mut iter = Self::create_and_seek_to_key(table, key)?;
+        if iter.is_valid() && iter.key() == key {
+            // `next` moves on to the following block if `key` is the last key of its block
+            iter.next()?;
+        }
+        Ok(iter)
+    }
+
+    /// Get the number of blocks whose first key is <= `key`, i.e. the block that may hold `key`
+    /// plus one, or 0 if `key` comes before the table.
+    fn blocks_up_to(table: &SsTable, key: &[u8]) -> usize {
+        match table.first_key() {
+            Some(first_key) if first_key.as_ref() <= key => table.find_block_idx(key) + 1,
+            _ => 0,
+        }
+    }
+
     /// Seek to the first key-value pair which >= `key`.
     /// Note: You probably want to review the handout for detailed explanation when implementing this function.
+    /// If `key` lies within the current block, the seek stays in it without reading any block,
+    /// which makes runs of forward seeks into the same block cheap.
     pub fn seek_to_key(&mut self, key: &[u8]) -> Result<()> {
-        let mut low = 0;
-        let mut high = self.table.block_metas.len();
-        while low < high {
-            let mid = (low + high) / 2;
-            if self.table.block_metas[mid].first_key.as_ref() > key {
-                high = mid;
-            } else {
-                low = mid + 1;
+        if self.error.is_none() && self.table.is_indexed(self.block_idx) {
+            if let Ok(meta) = self.table.try_block_meta(self.block_idx) {
+                if meta.first_key.as_ref() <= key && key <= meta.last_key.as_ref() {
+                    self.cur_block_iterator.seek_to_key(key);
+                    return Ok(());
+                }
             }
         }
+        self.error = None;
+        let low = Self::blocks_up_to(&self.table, key);
         if low == 0 {
             self.seek_to_first()?;
             return Ok(());
         }
-        let mut block = self.table.read_block(low - 1)?;
+        let mut block = self.read_block(low - 1, false)?;
         self.block_idx = low - 1;
-        self.cur_block_iterator = BlockIterator::create_and_seek_to_key(block, key);
+        self.cur_block_iterator = self.block_iterator(block);
+        self.cur_block_iterator.seek_to_key(key);
         if !self.cur_block_iterator.is_valid() {
-            if low >= self.table.block_metas.len() {
+            if low >= self.table.num_of_blocks() {
                 return Ok(());
             }
-            block = self.table.read_block(low)?;
+            block = self.read_block(low, false)?;
             self.block_idx += 1;
-            self.cur_block_iterator = BlockIterator::create_and_seek_to_first(block);
+            self.cur_block_iterator = self.block_iterator(block);
+            self.cur_block_iterator.seek_to_first();
         }
         Ok(())
     }
+
+    /// Seek to the first key-value pair which >= `key` like `seek_to_key`, and return whether it
+    /// holds `key` itself rather than the next greater key, e.g. for existence checks. Returns
+    /// `false` if the iterator ends up invalid.
+    pub fn seek_to_key_exact(&mut self, key: &[u8]) -> Result<bool> {
+        self.seek_to_key(key)?;
+        // landing in the next block means every key of the block before it was smaller
+        Ok(self.cur_block_iterator.is_valid() && self.cur_block_iterator.key() == key)
+    }
+
+    /// Seek to the first key-value pair which >= `key`, where `key` >= the current key, e.g. for a
+    /// merge join advancing one side to catch up with the other. Rather than searching the index,
+    /// the seek carries on from the current entry, then from the next block if `key` is past the
+    /// current one and within the next one, which is read like by `next`. Only a `key` past the
+    /// next block, or before the current key, falls back to `seek_to_key`.
+    pub fn seek_forward_to(&mut self, key: &[u8]) -> Result<()> {
+        if !self.is_valid() || key < self.key() {
+            return self.seek_to_key(key);
+        }
+        self.cur_block_iterator.seek_forward_to_key(key);
+        if self.cur_block_iterator.is_valid() {
+            return Ok(());
+        }
+        let next_idx = self.block_idx + 1;
+        if next_idx >= self.table.num_of_blocks() {
+            return Ok(());
+        }
+        if self.table.is_indexed(next_idx)
+            && key <= self.table.try_block_meta(next_idx)?.last_key.as_ref()
+        {
+            let block = self.read_block(next_idx, true)?;
+            self.block_idx = next_idx;
+            self.cur_block_iterator = self.block_iterator(block);
+            self.cur_block_iterator.seek_to_key(key);
+            return Ok(());
+        }
+        self.seek_to_key(key)
+    }
+
+    /// Advance `n` entries, like calling `next` `n` times. Within a block, the iterator jumps
+    /// straight to the target entry without decoding the entries in between. Blocks skipped as a
+    /// whole are counted from `BlockMeta::num_entries` and never read.
+    /// The iterator becomes invalid if there are fewer than `n` entries left.
+    pub fn skip(&mut self, n: usize) -> Result<()> {
+        if let Some(err) = &self.error {
+            bail!("{:#}", err);
+        }
+        if !self.cur_block_iterator.is_valid() {
+            return Ok(());
+        }
+        let mut n = n + self.cur_block_iterator.idx();
+        let mut num_of_entries = self.cur_block_iterator.num_of_entries();
+        let mut block_idx = self.block_idx;
+        while n >= num_of_entries {
+            n -= num_of_entries;
+            block_idx += 1;
+            if block_idx >= self.table.num_of_blocks() {
+                self.cur_block_iterator.seek_to_idx(usize::MAX);
+                return Ok(());
+            }
+            num_of_entries = self.table.try_block_meta(block_idx)?.num_entries as usize;
+        }
+        if block_idx != self.block_idx {
+            let block = self.read_block(block_idx, false)?;
+            self.block_idx = block_idx;
+            self.cur_block_iterator = self.block_iterator(block);
+        }
+        self.cur_block_iterator.seek_to_idx(n);
+        Ok(())
+    }
+
+    /// Seek to the last key-value pair which <= `key`. The iterator is invalid if every key in the
+    /// table is greater than `key`.
+    pub fn seek_for_prev(&mut self, key: &[u8]) -> Result<()> {
+        self.error = None;
+        match self.table.first_key() {
+            Some(first_key) if key >= first_key.as_ref() => {}
+            _ => {
+                self.cur_block_iterator.seek_to_idx(usize::MAX);
+                return Ok(());
+            }
+        }
+        let block_idx = self.table.find_block_idx(key);
+        let block = self.read_block(block_idx, false)?;
+        let mut iter = self.block_iterator(block);
+        iter.seek_to_key(key);
+        if !iter.is_valid() || iter.key() != key {
+            // the first key of the block is <= `key`, so the landed entry is never the first one
+            iter.seek_to_idx(iter.idx() - 1);
+        }
+        self.block_idx = block_idx;
+        self.cur_block_iterator = iter;
+        Ok(())
+    }
 }
 
 impl StorageIterator for SsTableIterator {
@@ -116,21 +392,26 @@ impl StorageIterator for SsTableIterator {
     }
 
     /// Return whether the current block iterator is valid or not.
+    /// The iterator is never valid after a failed block read.
     fn is_valid(&self) -> bool {
-        self.cur_block_iterator.is_valid()
+        self.error.is_none() && self.cur_block_iterator.is_valid()
     }
 
     /// Move to the next `key` in the block.
     /// Note: You may want to check if the current block iterator is valid after the move.
     fn next(&mut self) -> Result<()> {
+        if let Some(err) = &self.error {
+            bail!("{:#}", err);
+        }
         self.cur_block_iterator.next();
         if !self.cur_block_iterator.is_valid() {
-            if self.block_idx >= self.table.block_metas.len() - 1 {
+            if self.block_idx + 1 >= self.table.num_of_blocks() {
                 return Ok(());
             }
-            let block = self.table.read_block(self.block_idx + 1)?;
+            let block = self.read_block(self.block_idx + 1, true)?;
             self.block_idx += 1;
-            self.cur_block_iterator = BlockIterator::create_and_seek_to_first(block);
+            self.cur_block_iterator = self.block_iterator(block);
+            self.cur_block_iterator.seek_to_first();
         }
         Ok(())
     }
@@ -0,0 +1,100 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use bytes::{Buf, Bytes};
+
+use super::{FileObject, SsTable};
+use crate::lsm_storage::BlockCache;
+
+/// Packs several SSTables into a single file, so that a level with many tiny tables does not need
+/// a file handle per table. The tables are stored back to back, followed by an outer index.
+/// ------------------------------------------------------------------------------------------------
+/// |          SSTables           |                 Outer Index                 |      Extra      |
+/// ------------------------------------------------------------------------------------------------
+/// | SST #1 | ... | SST #N | (sst_id, offset, len) #1 | ... | (sst_id, offset, len) #N | index offset (u32) |
+/// ------------------------------------------------------------------------------------------------
+/// Each index entry stores `sst_id`, `offset` and `len` as u32.
+#[derive(Default)]
+pub struct MultiSstWriter {
+    data: Vec<u8>,
+    index: Vec<(u32, u32, u32)>,
+}
+
+impl MultiSstWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append an SSTable to the packed file.
+    pub fn add(&mut self, id: usize, sst: &SsTable) -> Result<()> {
+        let offset = self.data.len() as u32;
+        let len = sst.file.size();
+        self.data.extend_from_slice(&sst.file.read(0, len)?);
+        self.index.push((id as u32, offset, len as u32));
+        Ok(())
+    }
+
+    /// Write the packed SSTables and the outer index to `path`.
+    pub fn finish(self, path: impl AsRef<Path>) -> Result<()> {
+        let mut data = self.data;
+        let index_offset = data.len() as u32;
+        for (id, offset, len) in self.index {
+            data.extend_from_slice(&id.to_be_bytes());
+            data.extend_from_slice(&offset.to_be_bytes());
+            data.extend_from_slice(&len.to_be_bytes());
+        }
+        data.extend_from_slice(&index_offset.to_be_bytes());
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+}
+
+/// A file written by `MultiSstWriter`.
+pub struct MultiSstFile {
+    data: Bytes,
+    /// Maps each packed `sst_id` to its `(offset, len)` in the file.
+    index: Vec<(usize, u64, u64)>,
+}
+
+impl MultiSstFile {
+    /// Open a packed file and decode its outer index.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let data = Bytes::from(std::fs::read(path)?);
+        if data.len() < 4 {
+            bail!("packed sst file too small");
+        }
+        let index_offset = (&data[data.len() - 4..]).get_u32() as usize;
+        if index_offset > data.len() - 4 {
+            bail!("packed sst index offset {} out of range", index_offset);
+        }
+        let mut buf = &data[index_offset..data.len() - 4];
+        let mut index = Vec::new();
+        while buf.has_remaining() {
+            let id = buf.get_u32() as usize;
+            let offset = buf.get_u32() as u64;
+            let len = buf.get_u32() as u64;
+            if offset + len > index_offset as u64 {
+                bail!("packed sst {} out of range", id);
+            }
+            index.push((id, offset, len));
+        }
+        Ok(Self { data, index })
+    }
+
+    /// Get the ids of the packed SSTables, in the order they were written.
+    pub fn sst_ids(&self) -> impl Iterator<Item = usize> + '_ {
+        self.index.iter().map(|(id, _, _)| *id)
+    }
+
+    /// Open one of the packed SSTables.
+    pub fn open_sst(&self, id: usize, block_cache: Option<Arc<BlockCache>>) -> Result<SsTable> {
+        let (_, offset, len) = self
+            .index
+            .iter()
+            .find(|(sst_id, _, _)| *sst_id == id)
+            .ok_or_else(|| anyhow!("sst {} not found in packed file", id))?;
+        let file = FileObject::from_bytes(self.data.slice(*offset as usize..));
+        Ok(SsTable::open_with_len(id, block_cache, file, *len)?)
+    }
+}
@@ -0,0 +1,52 @@
+use std::collections::BTreeMap;
+
+use bytes::{Buf, Bytes};
+
+use super::TableError;
+
+/// The property holding the level of a table, see `SsTableBuilder::set_level`.
+pub(super) const LEVEL_PROPERTY: &str = "level";
+
+/// The property holding the big-endian CRC32 of each data block in order, 0 for a block
+/// reference, see `SsTableBuilder::set_block_checksums`.
+pub(super) const BLOCK_CHECKSUMS_PROPERTY: &str = "block_checksums";
+
+/// Append the user-defined properties of a table, see `SsTableBuilder::set_property`, to `buf`.
+/// They are laid out in key order as:
+/// -------------------------------------------------------------------------
+/// | key_len (u16) | key | value_len (u32) | value | key_len (u16) | ... |
+/// -------------------------------------------------------------------------
+pub(super) fn encode_properties(properties: &BTreeMap<String, Bytes>, buf: &mut Vec<u8>) {
+    for (key, value) in properties {
+        buf.extend_from_slice(&(key.len() as u16).to_be_bytes());
+        buf.extend_from_slice(key.as_bytes());
+        buf.extend_from_slice(&(value.len() as u32).to_be_bytes());
+        buf.extend_from_slice(value);
+    }
+}
+
+/// Decode the properties written by `encode_properties`, failing with `TableError::Corruption` if
+/// they are truncated or a key is not valid UTF-8.
+pub(super) fn decode_properties(mut buf: &[u8]) -> Result<BTreeMap<String, Bytes>, TableError> {
+    let truncated = || TableError::corruption(None, "properties truncated");
+    let mut properties = BTreeMap::new();
+    while buf.has_remaining() {
+        if buf.remaining() < 2 {
+            return Err(truncated());
+        }
+        let key_len = buf.get_u16() as usize;
+        if buf.remaining() < key_len + 4 {
+            return Err(truncated());
+        }
+        let key = String::from_utf8(buf[..key_len].to_vec())
+            .map_err(|_| TableError::corruption(None, "property key is not valid UTF-8"))?;
+        buf.advance(key_len);
+        let value_len = buf.get_u32() as usize;
+        if buf.remaining() < value_len {
+            return Err(truncated());
+        }
+        properties.insert(key, Bytes::copy_from_slice(&buf[..value_len]));
+        buf.advance(value_len);
+    }
+    Ok(properties)
+}
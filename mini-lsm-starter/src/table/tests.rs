@@ -10,7 +10,7 @@ use crate::table::SsTableBuilder;
 #[test]
 fn test_sst_build_single_key() {
     let mut builder = SsTableBuilder::new(16);
-    builder.add(b"233", b"233333");
+    assert!(builder.add(b"233", b"233333"));
     let dir = tempdir().unwrap();
     builder.build_for_test(dir.path().join("1.sst")).unwrap();
 }
@@ -18,12 +18,12 @@ fn test_sst_build_single_key() {
 #[test]
 fn test_sst_build_two_blocks() {
     let mut builder = SsTableBuilder::new(16);
-    builder.add(b"11", b"11");
-    builder.add(b"22", b"22");
-    builder.add(b"33", b"11");
-    builder.add(b"44", b"22");
-    builder.add(b"55", b"11");
-    builder.add(b"66", b"22");
+    assert!(builder.add(b"11", b"11"));
+    assert!(builder.add(b"22", b"22"));
+    assert!(builder.add(b"33", b"11"));
+    assert!(builder.add(b"44", b"22"));
+    assert!(builder.add(b"55", b"11"));
+    assert!(builder.add(b"66", b"22"));
     assert!(builder.meta.len() >= 2);
     let dir = tempdir().unwrap();
     builder.build_for_test(dir.path().join("1.sst")).unwrap();
@@ -46,7 +46,7 @@ fn generate_sst() -> (TempDir, SsTable) {
     for idx in 0..num_of_keys() {
         let key = key_of(idx);
         let value = value_of(idx);
-        builder.add(&key[..], &value[..]);
+        assert!(builder.add(&key[..], &value[..]));
     }
     let dir = tempdir().unwrap();
     let path = dir.path().join("1.sst");
@@ -61,9 +61,9 @@ fn test_sst_build_all() {
 #[test]
 fn test_sst_decode() {
     let (_dir, sst) = generate_sst();
-    let meta = sst.block_metas.clone();
+    let meta = sst.block_metas().to_vec();
     let new_sst = SsTable::open_for_test(sst.file).unwrap();
-    assert_eq!(new_sst.block_metas, meta);
+    assert_eq!(new_sst.block_metas(), meta);
 }
 
 fn as_bytes(x: &[u8]) -> Bytes {
@@ -128,3 +128,3375 @@ fn test_sst_seek_key() {
         iter.seek_to_key(b"k").unwrap();
     }
 }
+
+struct SliceIterator<'a> {
+    data: &'a [(Bytes, Bytes)],
+    idx: usize,
+}
+
+impl StorageIterator for SliceIterator<'_> {
+    fn key(&self) -> &[u8] {
+        &self.data[self.idx].0
+    }
+
+    fn value(&self) -> &[u8] {
+        &self.data[self.idx].1
+    }
+
+    fn is_valid(&self) -> bool {
+        self.idx < self.data.len()
+    }
+
+    fn next(&mut self) -> anyhow::Result<()> {
+        self.idx += 1;
+        Ok(())
+    }
+}
+
+fn collect_sst(sst: SsTable) -> Vec<(Bytes, Bytes)> {
+    let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
+    let mut entries = Vec::new();
+    while iter.is_valid() {
+        entries.push((as_bytes(iter.key()), as_bytes(iter.value())));
+        iter.next().unwrap();
+    }
+    entries
+}
+
+#[test]
+fn test_sst_build_merged() {
+    use crate::iterators::two_merge_iterator::TwoMergeIterator;
+
+    let (dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    // overwrite every 7th key and insert keys between the existing ones
+    let memtable: Vec<(Bytes, Bytes)> = (0..num_of_keys() * 5 + 3)
+        .filter(|idx| idx % 7 == 0)
+        .map(|idx| {
+            (
+                Bytes::from(format!("key_{:03}", idx)),
+                Bytes::from(format!("mem_{:03}", idx)),
+            )
+        })
+        .collect();
+
+    let merged = SsTableBuilder::new(128)
+        .build_merged(&memtable, &sst, 0, None, dir.path().join("2.sst"))
+        .unwrap();
+
+    let mut iter = TwoMergeIterator::create(
+        SliceIterator {
+            data: &memtable,
+            idx: 0,
+        },
+        SsTableIterator::create_and_seek_to_first(sst).unwrap(),
+    )
+    .unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    while iter.is_valid() {
+        assert!(builder.add(iter.key(), iter.value()));
+        iter.next().unwrap();
+    }
+    let expected = builder.build_for_test(dir.path().join("3.sst")).unwrap();
+
+    assert_eq!(merged.block_metas, expected.block_metas);
+    assert_eq!(collect_sst(merged), collect_sst(expected));
+}
+
+#[test]
+fn test_block_meta_shared_prefix() {
+    let prefix = "a_very_long_shared_prefix_for_every_first_key_".repeat(4);
+    let metas: Vec<BlockMeta> = (0..100)
+        .map(|idx| {
+            let first_key = Bytes::from(format!("{}{:05}", prefix, idx));
+            BlockMeta {
+                offset: idx * 4196 + 100,
+                num_entries: 10,
+                key_len: first_key.len() as u16,
+                first_key,
+                last_key: Bytes::from(format!("{}{:05}_last", prefix, idx)),
+                reference: None,
+                start: idx * 4196,
+                packed: false,
+            }
+        })
+        .collect();
+    let mut buf = Vec::new();
+    BlockMeta::encode_block_meta(&metas, &mut buf);
+    let plain_size: usize = metas
+        .iter()
+        .map(|meta| 8 + meta.first_key.len() + meta.last_key.len())
+        .sum();
+    assert!(buf.len() < plain_size / 10);
+    assert_eq!(BlockMeta::decode_block_meta(&buf[..]).unwrap(), metas);
+
+    // the prefix compression is optional, the keys are then stored in full
+    let mut full = Vec::new();
+    BlockMeta::encode_block_meta_with(&metas, &mut full, false);
+    assert!(full.len() > plain_size);
+    assert_eq!(BlockMeta::decode_block_meta(&full[..]).unwrap(), metas);
+
+    // the first meta has no key to share a prefix with, and a last key shares at most its first
+    // key; the version byte, the offset, num_entries and the flags come before `shared_len`
+    for (pos, shared_len) in [(8, 1), (8 + 4 + metas[0].first_key.len(), 1000)] {
+        let mut corrupted = full.clone();
+        corrupted[pos..pos + 2].copy_from_slice(&(shared_len as u16).to_be_bytes());
+        match BlockMeta::decode_block_meta(&corrupted[..]) {
+            Err(TableError::Corruption { detail, .. }) => assert!(detail.contains("shares")),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn test_sst_multi_get() {
+    let (_dir, sst) = generate_sst();
+    // every present key is followed by an absent one, in reverse order to exercise the sorting
+    let keys: Vec<Vec<u8>> = (0..num_of_keys() / 2)
+        .rev()
+        .flat_map(|idx| {
+            [
+                key_of(idx * 2),
+                format!("key_{:03}", idx * 10 + 1).into_bytes(),
+            ]
+        })
+        .collect();
+    let key_refs: Vec<&[u8]> = keys.iter().map(|key| key.as_slice()).collect();
+    let reads_before = sst.file.read_count();
+    let values = sst.multi_get(&key_refs).unwrap();
+    // the present keys span every block, each read once
+    assert_eq!(sst.file.read_count() - reads_before, sst.num_of_blocks());
+    for (pos, idx) in (0..num_of_keys() / 2).rev().enumerate() {
+        assert_eq!(values[pos * 2], Some(as_bytes(&value_of(idx * 2))));
+        assert_eq!(values[pos * 2 + 1], None);
+    }
+
+    // keys between two blocks, or outside the table, are ruled out without reading anything
+    let mut gaps: Vec<Vec<u8>> = (0..sst.num_of_blocks())
+        .map(|block_idx| {
+            let mut key = sst.block_metas()[block_idx].last_key.to_vec();
+            key.push(0);
+            key
+        })
+        .collect();
+    gaps.push(b"key".to_vec());
+    let key_refs: Vec<&[u8]> = gaps.iter().map(|key| key.as_slice()).collect();
+    let reads_before = sst.file.read_count();
+    assert!(sst
+        .multi_get(&key_refs)
+        .unwrap()
+        .iter()
+        .all(Option::is_none));
+    assert_eq!(sst.file.read_count(), reads_before);
+    // only the blocks of the first and the last key are read
+    let last_key = key_of(num_of_keys() - 1);
+    let keys: [&[u8]; 3] = [&last_key, &key_of(0), &key_of(1)];
+    let values = sst.multi_get(&keys).unwrap();
+    assert_eq!(values[0], Some(as_bytes(&value_of(num_of_keys() - 1))));
+    assert_eq!(sst.file.read_count() - reads_before, 2);
+}
+
+#[test]
+fn test_sst_open_with_len() {
+    let (dir, sst) = generate_sst();
+    let logical_len = sst.file.size();
+    let mut data = sst.file.read(0, logical_len).unwrap();
+    data.extend_from_slice(&[0xab; 1000]);
+    let file = FileObject::create(&dir.path().join("2.sst"), data).unwrap();
+    let new_sst = SsTable::open_with_len(0, None, file, logical_len).unwrap();
+    assert_eq!(new_sst.block_metas, sst.block_metas);
+    let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(new_sst)).unwrap();
+    for i in 0..num_of_keys() {
+        assert_eq!(iter.key(), key_of(i));
+        assert_eq!(iter.value(), value_of(i));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_multi_sst_file() {
+    let dir = tempdir().unwrap();
+    let mut writer = MultiSstWriter::new();
+    for id in 1..=3 {
+        let mut builder = SsTableBuilder::new(128);
+        for idx in 0..num_of_keys() {
+            assert!(builder.add(&key_of(idx), format!("sst_{}_{}", id, idx).as_bytes()));
+        }
+        let sst = builder
+            .build_for_test(dir.path().join(format!("{}.sst", id)))
+            .unwrap();
+        writer.add(id, &sst).unwrap();
+    }
+    let path = dir.path().join("packed.sst");
+    writer.finish(&path).unwrap();
+
+    let packed = MultiSstFile::open(&path).unwrap();
+    assert_eq!(packed.sst_ids().collect::<Vec<_>>(), vec![1, 2, 3]);
+    for id in [2, 3, 1] {
+        let sst = Arc::new(packed.open_sst(id, None).unwrap());
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        for idx in 0..num_of_keys() {
+            assert_eq!(iter.key(), key_of(idx));
+            assert_eq!(iter.value(), format!("sst_{}_{}", id, idx).as_bytes());
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    }
+    assert!(packed.open_sst(4, None).is_err());
+}
+
+#[test]
+fn test_sst_iterator_read_error() {
+    let (dir, sst) = generate_sst();
+    // a file that only holds the first two data blocks, so reading block 2 fails
+    let data = sst.file.read(0, 2 * 4196).unwrap();
+    let sst = Arc::new(SsTable {
+        file: FileObject::create(&dir.path().join("2.sst"), data).unwrap(),
+        block_metas: sst.block_metas().to_vec().into(),
+        block_meta_offset: sst.block_meta_offset,
+        id: 0,
+        block_cache: None,
+        footer: Footer::default(),
+        properties: BTreeMap::new().into(),
+        footer_offset: 0,
+        block_sources: BTreeMap::new(),
+        disk_index: None,
+    });
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    let mut result = Ok(());
+    while iter.is_valid() {
+        assert!(iter.error().is_none());
+        result = iter.next();
+        if result.is_err() {
+            break;
+        }
+    }
+    assert!(result.is_err());
+    assert!(!iter.is_valid());
+    assert!(iter.error().is_some());
+    assert!(iter.next().is_err());
+    iter.seek_to_first().unwrap();
+    assert!(iter.is_valid());
+    assert!(iter.error().is_none());
+}
+
+#[test]
+fn test_sst_read_block_with_len() {
+    let (_dir, sst) = generate_sst();
+    for (idx, meta) in sst.block_metas().iter().enumerate() {
+        let len = meta.offset as usize - idx * 4196;
+        assert_eq!(
+            sst.read_block_with_len(idx, len).unwrap().encode(),
+            sst.read_block(idx).unwrap().encode()
+        );
+    }
+    assert!(sst.read_block_with_len(0, 4197).is_err());
+    assert!(sst.read_block_with_len(sst.num_of_blocks(), 100).is_err());
+    assert!(sst.read_block_with_len(0, 1).is_err());
+}
+
+#[test]
+fn test_sst_sample_keys() {
+    let (_dir, sst) = generate_sst();
+    let keys: Vec<Vec<u8>> = (0..num_of_keys()).map(key_of).collect();
+    for n in [1, 3, 5, sst.num_of_blocks(), sst.num_of_blocks() * 2] {
+        let samples = sst.sample_keys(n);
+        assert_eq!(samples.len(), n.min(sst.num_of_blocks()));
+        assert!(samples.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(samples
+            .iter()
+            .all(|sample| keys.iter().any(|key| key == sample)));
+    }
+    assert!(sst.sample_keys(0).is_empty());
+}
+
+#[test]
+fn test_sst_pad_byte() {
+    let mut builder = SsTableBuilder::new(128);
+    builder.set_pad_byte(0xcc);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_of(idx), &value_of(idx)));
+    }
+    let dir = tempdir().unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    for (idx, meta) in sst.block_metas().iter().enumerate() {
+        let padding_len = (idx + 1) * 4196 - meta.offset as usize;
+        let padding = sst
+            .file
+            .read(meta.offset as u64, padding_len as u64)
+            .unwrap();
+        assert!(padding.iter().all(|&b| b == 0xcc));
+    }
+    let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
+    for idx in 0..num_of_keys() {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_block_metas() {
+    let (_dir, sst) = generate_sst();
+    let metas = sst.block_metas();
+    assert_eq!(metas.len(), sst.num_of_blocks());
+    assert!(metas
+        .windows(2)
+        .all(|pair| pair[0].first_key < pair[1].first_key));
+    for (idx, meta) in metas.iter().enumerate() {
+        assert_eq!(sst.block_meta(idx), Some(meta));
+    }
+    assert_eq!(sst.block_meta(metas.len()), None);
+}
+
+#[test]
+fn test_sst_write_buffer_size() {
+    let dir = tempdir().unwrap();
+    let mut files = Vec::new();
+    for write_buffer_size in [1, 4196, 1 << 20] {
+        let mut builder = SsTableBuilder::new(128);
+        builder.set_write_buffer_size(write_buffer_size);
+        for idx in 0..num_of_keys() * 10 {
+            assert!(builder.add(format!("key_{:05}", idx).as_bytes(), &value_of(idx)));
+        }
+        let path = dir.path().join(format!("{}.sst", write_buffer_size));
+        let sst = Arc::new(builder.build_for_test(&path).unwrap());
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        for idx in 0..num_of_keys() * 10 {
+            assert_eq!(iter.key(), format!("key_{:05}", idx).as_bytes());
+            assert_eq!(iter.value(), value_of(idx));
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+        files.push(std::fs::read(&path).unwrap());
+    }
+    assert!(files.windows(2).all(|pair| pair[0] == pair[1]));
+
+    /// Counts the writes reaching the file through the buffer.
+    #[derive(Debug)]
+    struct CountingWriter(usize);
+    impl std::io::Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += 1;
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    let mut writes = Vec::new();
+    for write_buffer_size in [1, 1 << 20] {
+        let mut builder = SsTableBuilder::new(128);
+        builder.set_write_buffer_size(write_buffer_size);
+        for idx in 0..num_of_keys() * 10 {
+            assert!(builder.add(format!("key_{:05}", idx).as_bytes(), &value_of(idx)));
+        }
+        // the blocks cut so far, the current one aside
+        let mut writer = builder.buffered(CountingWriter(0));
+        builder.write_data_blocks(&mut writer).unwrap();
+        writes.push((builder.meta.len(), writer.into_inner().unwrap().0));
+    }
+    // unbuffered, each block and its padding is a write of its own; buffered, a single one
+    assert!(writes[0].1 >= writes[0].0 * 2);
+    assert_eq!(writes[1].1, 1);
+}
+
+#[test]
+fn test_sst_rebuild() {
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        // every 10th key is a tombstone
+        let value = if idx % 10 == 0 { vec![] } else { value_of(idx) };
+        assert!(builder.add(&key_of(idx), &value));
+    }
+    let dir = tempdir().unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    let rebuilt = sst
+        .rebuild(1024, 2, None, dir.path().join("2.sst"))
+        .unwrap();
+    assert!(rebuilt.num_of_blocks() < sst.num_of_blocks());
+    assert_eq!(collect_sst(rebuilt), collect_sst(sst));
+
+    // blocks cannot outgrow their 4196-byte slots
+    let mut builder = SsTableBuilder::new(4096);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_of(idx), &value_of(idx).repeat(10)));
+    }
+    let sst = builder.build_for_test(dir.path().join("3.sst")).unwrap();
+    let path = dir.path().join("4.sst");
+    let err = sst.rebuild(16 * 1024, 4, None, &path).err().unwrap();
+    assert!(err.to_string().contains("16384"), "{err}");
+    assert!(!path.exists());
+}
+
+fn cursor_next(cursor: &mut SsTableCursor) -> Option<(Vec<u8>, Vec<u8>)> {
+    cursor.next().map(|entry| {
+        entry
+            .map(|(key, value)| (key.to_vec(), value.to_vec()))
+            .unwrap()
+    })
+}
+
+#[test]
+fn test_sst_cursor() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    let mut cursor = sst.cursor();
+    for idx in 0..num_of_keys() {
+        assert_eq!(cursor_next(&mut cursor), Some((key_of(idx), value_of(idx))));
+    }
+    assert_eq!(cursor_next(&mut cursor), None);
+
+    // forward seeks to present and absent keys
+    cursor.seek(&key_of(42)).unwrap();
+    assert_eq!(cursor_next(&mut cursor), Some((key_of(42), value_of(42))));
+    assert_eq!(cursor_next(&mut cursor), Some((key_of(43), value_of(43))));
+    cursor.seek(b"key_101").unwrap();
+    assert_eq!(cursor_next(&mut cursor), Some((key_of(21), value_of(21))));
+    cursor.seek(b"key_999").unwrap();
+    assert_eq!(cursor_next(&mut cursor), None);
+
+    // backward seeks to present and absent keys, including across block boundaries
+    for idx in 0..num_of_keys() {
+        cursor.seek_for_prev(&key_of(idx)).unwrap();
+        assert_eq!(cursor_next(&mut cursor), Some((key_of(idx), value_of(idx))));
+        cursor
+            .seek_for_prev(format!("key_{:03}", idx * 5 + 4).as_bytes())
+            .unwrap();
+        assert_eq!(cursor_next(&mut cursor), Some((key_of(idx), value_of(idx))));
+        if idx + 1 < num_of_keys() {
+            assert_eq!(
+                cursor_next(&mut cursor),
+                Some((key_of(idx + 1), value_of(idx + 1)))
+            );
+        }
+    }
+    cursor.seek_for_prev(b"key").unwrap();
+    assert_eq!(cursor_next(&mut cursor), None);
+    cursor.seek_for_prev(b"key_999").unwrap();
+    assert_eq!(
+        cursor_next(&mut cursor),
+        Some((key_of(num_of_keys() - 1), value_of(num_of_keys() - 1)))
+    );
+
+    // a table without any block has nothing before or after any key
+    let empty = Arc::new(SsTable::from_blocks(1, Vec::new()).unwrap());
+    let mut cursor = empty.cursor();
+    cursor.seek_for_prev(b"key_999").unwrap();
+    assert_eq!(cursor_next(&mut cursor), None);
+    cursor.seek(b"key").unwrap();
+    assert_eq!(cursor_next(&mut cursor), None);
+    assert_eq!(cursor_next(&mut empty.cursor()), None);
+}
+
+#[test]
+fn test_sst_memory_footprint() {
+    let dir = tempdir().unwrap();
+    let mut footprints = Vec::new();
+    for num in [10, 100, 1000] {
+        let mut builder = SsTableBuilder::new(128);
+        for idx in 0..num {
+            assert!(builder.add(format!("key_{:05}", idx).as_bytes(), &value_of(idx)));
+        }
+        let sst = builder
+            .build_for_test(dir.path().join(format!("{}.sst", num)))
+            .unwrap();
+        assert!(sst.memory_footprint() >= sst.file.size() as usize);
+        footprints.push((sst.num_of_blocks(), sst.memory_footprint()));
+    }
+    assert!(footprints
+        .windows(2)
+        .all(|pair| pair[0].0 < pair[1].0 && pair[0].1 < pair[1].1));
+
+    // a table left on disk does not count its file
+    let path = dir.path().join("1000.sst");
+    let resident = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    let cache = Arc::new(FileHandleCache::new(1));
+    let file = FileObject::open_with_handle_cache(&path, cache).unwrap();
+    assert_eq!(file.resident_size(), 0);
+    let on_disk = SsTable::open_for_test(file).unwrap();
+    resident.block_metas();
+    on_disk.block_metas();
+    assert_eq!(
+        on_disk.memory_footprint() + resident.file.size() as usize,
+        resident.memory_footprint()
+    );
+}
+
+#[test]
+fn test_sst_build_validated() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new_validated(128);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_of(idx), &value_of(idx)));
+    }
+    builder.build_for_test(dir.path().join("1.sst")).unwrap();
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "block 1 starts with")]
+fn test_sst_build_validated_mismatch() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new_validated(128);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_of(idx), &value_of(idx)));
+    }
+    builder.meta[1].first_key = Bytes::from_static(b"key_000");
+    let _ = builder.build_for_test(dir.path().join("1.sst"));
+}
+
+#[test]
+fn test_block_index() {
+    let metas: Vec<BlockMeta> = (0..10000)
+        .map(|idx| {
+            let first_key = Bytes::from(format!("a_long_common_key_prefix_{:08}", idx * 10));
+            BlockMeta {
+                offset: idx * 4196 + 100,
+                num_entries: 10,
+                key_len: first_key.len() as u16,
+                first_key: first_key.clone(),
+                last_key: first_key,
+                reference: None,
+                start: idx * 4196,
+                packed: false,
+            }
+        })
+        .collect();
+    let sst = SsTable {
+        file: FileObject::from_bytes(Bytes::new()),
+        block_metas: metas.into(),
+        block_meta_offset: 0,
+        id: 0,
+        block_cache: None,
+        footer: Footer::default(),
+        properties: BTreeMap::new().into(),
+        footer_offset: 0,
+        block_sources: BTreeMap::new(),
+        disk_index: None,
+    };
+    let index = BlockIndex::from_metas(sst.block_metas());
+    assert_eq!(index.len(), sst.num_of_blocks());
+    // the shared prefixes are stored once per restart, so the arena is a fraction of the key bytes
+    let keys_size: usize = sst
+        .block_metas()
+        .iter()
+        .map(|meta| meta.first_key.len())
+        .sum();
+    assert!(index.arena_size() < keys_size / 2);
+    let mut buf = Vec::new();
+    for (idx, meta) in sst.block_metas().iter().enumerate() {
+        index.first_key(idx, &mut buf);
+        assert_eq!(buf, meta.first_key);
+    }
+    for idx in (0..100005).step_by(7) {
+        let key = format!("a_long_common_key_prefix_{:08}", idx);
+        assert_eq!(
+            index.find_block_idx(key.as_bytes()),
+            sst.find_block_idx(key.as_bytes())
+        );
+    }
+    assert_eq!(index.find_block_idx(b"a"), 0);
+    assert_eq!(index.find_block_idx(b"b"), sst.num_of_blocks() - 1);
+}
+
+fn reference_sst_path() -> std::path::PathBuf {
+    std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/table/fixtures/reference.sst")
+}
+
+/// `fixtures/reference.sst` was written in the upstream mini-lsm layout with a block size of 256,
+/// holding `key_{idx * 3:05}` => `value_{idx}` for `idx` in `0..300`, and a saturated bloom filter.
+#[test]
+fn test_sst_open_reference() {
+    let sst = Arc::new(SsTable::open_reference(reference_sst_path()).unwrap());
+    assert_eq!(sst.first_key().unwrap(), "key_00000");
+    assert_eq!(sst.last_key().unwrap(), "key_00897");
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    for idx in 0..300 {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), format!("key_{:05}", idx * 3).as_bytes());
+        assert_eq!(iter.value(), format!("value_{}", idx).as_bytes());
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    assert_eq!(
+        sst.get(b"key_00450").unwrap(),
+        Some(Bytes::from_static(b"value_150"))
+    );
+    assert_eq!(sst.get(b"key_00451").unwrap(), None);
+}
+
+#[test]
+fn test_sst_open_reference_checksum_mismatch() {
+    let mut data = std::fs::read(reference_sst_path()).unwrap();
+    data[10] ^= 0xff;
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    std::fs::write(&path, data).unwrap();
+    let err = SsTable::open_reference(&path).err().unwrap();
+    assert!(
+        matches!(
+            err.downcast_ref::<TableError>(),
+            Some(TableError::ChecksumMismatch {
+                what: "data block",
+                ..
+            })
+        ),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_sst_build_deterministic() {
+    let dir = tempdir().unwrap();
+    let files: Vec<Vec<u8>> = (0..2)
+        .map(|id| {
+            let mut builder = SsTableBuilder::new(128);
+            for idx in 0..num_of_keys() {
+                assert!(builder.add(&key_of(idx), &value_of(idx)));
+            }
+            let path = dir.path().join(format!("{}.sst", id));
+            builder.build(1, None, &path).unwrap();
+            std::fs::read(&path).unwrap()
+        })
+        .collect();
+    assert_eq!(files[0], files[1]);
+}
+
+#[test]
+fn test_sst_compress_values() {
+    let dir = tempdir().unwrap();
+    let mut tables = Vec::new();
+    for compress_values in [false, true] {
+        let mut builder = SsTableBuilder::new(4096);
+        builder.set_compress_values(compress_values);
+        for idx in 0..num_of_keys() {
+            assert!(builder.add(&key_of(idx), &value_of(idx).repeat(10)));
+        }
+        let path = dir.path().join(format!("{}.sst", compress_values));
+        tables.push(Arc::new(builder.build_for_test(path).unwrap()));
+    }
+    assert!(tables[1].num_of_blocks() < tables[0].num_of_blocks());
+    assert!(tables[1].file.size() < tables[0].file.size());
+    let mut iter = SsTableIterator::create_and_seek_to_key(tables[1].clone(), &key_of(42)).unwrap();
+    for idx in 42..num_of_keys() {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx).repeat(10));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_iterator_lazy_values() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    let scan = |lazy_values: bool| {
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        iter.set_lazy_values(lazy_values);
+        let mut rows = Vec::new();
+        while iter.is_valid() {
+            if iter.key().ends_with(b"5") {
+                rows.push((as_bytes(iter.key()), as_bytes(iter.value())));
+            }
+            iter.next().unwrap();
+        }
+        rows
+    };
+    let rows = scan(true);
+    assert_eq!(rows.len(), num_of_keys() / 2);
+    assert_eq!(rows, scan(false));
+    for (key, value) in rows {
+        let idx = std::str::from_utf8(&key[4..])
+            .unwrap()
+            .parse::<usize>()
+            .unwrap()
+            / 5;
+        assert_eq!(value, value_of(idx));
+    }
+
+    let mut iter = SsTableIterator::create_and_seek_to_key(sst, &key_of(20)).unwrap();
+    iter.set_lazy_values(true);
+    iter.seek_to_key(&key_of(30)).unwrap();
+    iter.set_lazy_values(false);
+    assert_eq!(iter.value(), value_of(30));
+    iter.next().unwrap();
+    assert_eq!(iter.value(), value_of(31));
+    iter.seek_for_prev(&key_of(60)).unwrap();
+    assert_eq!(iter.value(), value_of(60));
+}
+
+#[test]
+fn test_sst_block_for_offset() {
+    let (_dir, sst) = generate_sst();
+    let num_of_blocks = sst.num_of_blocks();
+    assert!(num_of_blocks >= 3);
+    assert_eq!(sst.block_for_offset(0), Some(0));
+    assert_eq!(sst.block_for_offset(10), Some(0));
+    assert_eq!(sst.block_for_offset(4195), Some(0));
+    assert_eq!(sst.block_for_offset(4196), Some(1));
+    assert_eq!(sst.block_for_offset(2 * 4196 + 100), Some(2));
+    let last_offset = sst.block_metas()[num_of_blocks - 1].offset as u64 - 1;
+    assert_eq!(sst.block_for_offset(last_offset), Some(num_of_blocks - 1));
+    assert_eq!(sst.block_for_offset(sst.block_meta_offset as u64), None);
+    assert_eq!(sst.block_for_offset(sst.file.size() - 1), None);
+    assert_eq!(sst.block_for_offset(sst.file.size() + 100), None);
+}
+
+#[test]
+fn test_sst_add_tracked() {
+    let mut builder = SsTableBuilder::new(128);
+    assert_eq!(builder.add_tracked(b"", b"value"), None);
+    let block_indices: Vec<usize> = (0..num_of_keys())
+        .map(|idx| builder.add_tracked(&key_of(idx), &value_of(idx)).unwrap())
+        .collect();
+    assert_eq!(builder.add_tracked(b"", b"value"), None);
+    let dir = tempdir().unwrap();
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    assert!(block_indices.windows(2).all(|pair| pair[0] <= pair[1]));
+    assert_eq!(block_indices[0], 0);
+    assert_eq!(block_indices[num_of_keys() - 1], sst.num_of_blocks() - 1);
+    for (idx, &block_idx) in block_indices.iter().enumerate() {
+        let meta = sst.block_meta(block_idx).unwrap();
+        assert!(meta.first_key <= key_of(idx) && key_of(idx) <= meta.last_key);
+        assert_eq!(sst.find_block_idx(&key_of(idx)), block_idx);
+    }
+    assert_eq!(sst.footer.entry_count, num_of_keys() as u64);
+}
+
+#[test]
+fn test_sst_scan_with_block_idx() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    let entries: Vec<(usize, Bytes, Bytes)> = sst
+        .scan_with_block_idx()
+        .collect::<anyhow::Result<_>>()
+        .unwrap();
+    assert_eq!(entries.len(), num_of_keys());
+    for (idx, (block_idx, key, value)) in entries.iter().enumerate() {
+        assert_eq!(key, &key_of(idx));
+        assert_eq!(value, &value_of(idx));
+        let meta = sst.block_meta(*block_idx).unwrap();
+        assert!(meta.first_key <= key && key <= &meta.last_key);
+    }
+    for (block_idx, meta) in sst.block_metas().iter().enumerate() {
+        let block: Vec<&Bytes> = entries
+            .iter()
+            .filter(|(idx, _, _)| *idx == block_idx)
+            .map(|(_, key, _)| key)
+            .collect();
+        assert_eq!(block.first(), Some(&&meta.first_key));
+        assert_eq!(block.last(), Some(&&meta.last_key));
+    }
+    assert!(entries.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+}
+
+#[test]
+fn test_sst_compact_all_tombstones() {
+    use crate::iterators::gc_iterator::GcIterator;
+    use crate::key::key_with_ts;
+
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_with_ts(&key_of(idx), 5), b""));
+    }
+    let dir = tempdir().unwrap();
+    let tombstones = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+    assert!(tombstones.num_of_blocks() > 1);
+
+    let iter = GcIterator::create(
+        SsTableIterator::create_and_seek_to_first(tombstones).unwrap(),
+        10,
+    )
+    .unwrap();
+    let builder = SsTableBuilder::new(128);
+    assert_eq!(builder.estimated_size(), 0);
+    let path = dir.path().join("2.sst");
+    let sst = builder.build_from_iter(iter, 2, None, &path).unwrap();
+    assert_eq!(sst.num_of_blocks(), 0);
+
+    let sst = Arc::new(SsTable::open(2, None, FileObject::open(&path).unwrap()).unwrap());
+    assert_eq!(sst.num_of_blocks(), 0);
+    assert_eq!(sst.first_key(), None);
+    assert_eq!(sst.get(&key_with_ts(&key_of(0), 5)).unwrap(), None);
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    assert!(!iter.is_valid());
+    iter.next().unwrap();
+    assert!(!iter.is_valid());
+    iter.seek_to_key(&key_of(0)).unwrap();
+    assert!(!iter.is_valid());
+    iter.seek_for_prev(b"z").unwrap();
+    assert!(!iter.is_valid());
+    assert_eq!(sst.scan_with_block_idx().count(), 0);
+}
+
+#[test]
+fn test_sst_open_with_prefetch() {
+    let (_dir, sst) = generate_sst();
+    let data = sst.file.read(0, sst.file.size()).unwrap();
+    let last_block = sst.num_of_blocks() - 1;
+    for prefetch_boundary_blocks in [false, true] {
+        let block_cache = Arc::new(BlockCache::new(1024));
+        let sst = SsTable::open_with_prefetch(
+            7,
+            Some(block_cache.clone()),
+            FileObject::from_bytes(Bytes::from(data.clone())),
+            prefetch_boundary_blocks,
+        )
+        .unwrap();
+        assert_eq!(block_cache.contains_key(&(7, 0)), prefetch_boundary_blocks);
+        assert_eq!(
+            block_cache.contains_key(&(7, last_block)),
+            prefetch_boundary_blocks
+        );
+        let reads = sst.file.read_count();
+        sst.read_block_cached(0).unwrap();
+        sst.read_block_cached(last_block).unwrap();
+        let cold_reads = if prefetch_boundary_blocks { 0 } else { 2 };
+        assert_eq!(sst.file.read_count(), reads + cold_reads);
+        sst.read_block_cached(0).unwrap();
+        assert_eq!(sst.file.read_count(), reads + cold_reads);
+    }
+
+    // a corrupted boundary block fails the open with an error that can be matched on
+    let mut corrupted = data;
+    let end = sst.encoded_block_len(0).unwrap();
+    corrupted[end - 2..end].copy_from_slice(&u16::MAX.to_be_bytes());
+    assert!(matches!(
+        SsTable::open_with_prefetch(
+            7,
+            Some(Arc::new(BlockCache::new(1024))),
+            FileObject::from_bytes(Bytes::from(corrupted)),
+            true,
+        ),
+        Err(TableError::Corruption {
+            block_idx: Some(0),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_sst_split_points() {
+    let (_dir, sst) = generate_sst();
+    let num_of_blocks = sst.num_of_blocks();
+    let block_len = (0..num_of_blocks)
+        .map(|idx| {
+            let mut iter = BlockIterator::create_and_seek_to_first(sst.read_block(idx).unwrap());
+            let mut len = 0;
+            while iter.is_valid() {
+                len += 1;
+                iter.next();
+            }
+            len
+        })
+        .max()
+        .unwrap();
+    for n in [1, 2, 3, 5, num_of_blocks, num_of_blocks + 10] {
+        let points = sst.split_points(n);
+        assert_eq!(points.len(), n.min(num_of_blocks) - 1);
+        assert!(points.windows(2).all(|pair| pair[0] < pair[1]));
+        let mut sizes = vec![0; points.len() + 1];
+        for idx in 0..num_of_keys() {
+            sizes[points.partition_point(|point| point.as_ref() <= key_of(idx).as_slice())] += 1;
+        }
+        let max = sizes.iter().max().unwrap();
+        let min = sizes.iter().min().unwrap();
+        assert!(max - min <= block_len, "{:?}", sizes);
+    }
+    assert!(sst.split_points(0).is_empty());
+}
+
+#[test]
+fn test_sst_seek_after() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    let iter = SsTableIterator::create_and_seek_after(sst.clone(), &key_of(10)).unwrap();
+    assert_eq!(iter.key(), key_of(11));
+    assert_eq!(iter.value(), value_of(11));
+    let iter = SsTableIterator::create_and_seek_after(sst.clone(), b"key_051").unwrap();
+    assert_eq!(iter.key(), key_of(11));
+    let iter = SsTableIterator::create_and_seek_after(sst.clone(), b"").unwrap();
+    assert_eq!(iter.key(), key_of(0));
+    // the last key of a block lands on the first key of the next block
+    let last_key = sst.block_meta(0).unwrap().last_key.clone();
+    let iter = SsTableIterator::create_and_seek_after(sst.clone(), &last_key).unwrap();
+    assert_eq!(iter.key(), sst.block_meta(1).unwrap().first_key);
+    let iter =
+        SsTableIterator::create_and_seek_after(sst.clone(), &key_of(num_of_keys() - 1)).unwrap();
+    assert!(!iter.is_valid());
+    let iter = SsTableIterator::create_and_seek_after(sst, b"z").unwrap();
+    assert!(!iter.is_valid());
+}
+
+fn build_sst_from(dir: &TempDir, name: &str, entries: &[(Vec<u8>, Vec<u8>)]) -> Arc<SsTable> {
+    let mut builder = SsTableBuilder::new(128);
+    for (key, value) in entries {
+        assert!(builder.add(key, value));
+    }
+    Arc::new(builder.build_for_test(dir.path().join(name)).unwrap())
+}
+
+fn check_sst_entries(sst: SsTable, expected: &[(Vec<u8>, Vec<u8>)]) {
+    let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
+    for (key, value) in expected {
+        assert_eq!(iter.key(), key.as_slice());
+        assert_eq!(iter.value(), value.as_slice());
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_compact_disjoint() {
+    let dir = tempdir().unwrap();
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..num_of_keys() * 2)
+        .map(|idx| (key_of(idx), value_of(idx)))
+        .collect();
+    let lower = build_sst_from(&dir, "1.sst", &entries[..num_of_keys()]);
+    let upper = build_sst_from(&dir, "2.sst", &entries[num_of_keys()..]);
+    let num_of_blocks = lower.num_of_blocks() + upper.num_of_blocks();
+    let (sst, stats) = compact(&[upper, lower], 128, 3, None, dir.path().join("3.sst")).unwrap();
+    assert_eq!(stats.copied_blocks, num_of_blocks);
+    assert_eq!(stats.decoded_entries, 0);
+    assert_eq!(sst.num_of_blocks(), num_of_blocks);
+    check_sst_entries(sst, &entries);
+}
+
+#[test]
+fn test_compact_overlapping() {
+    let dir = tempdir().unwrap();
+    let older: Vec<(Vec<u8>, Vec<u8>)> = (0..num_of_keys())
+        .map(|idx| (key_of(idx), value_of(idx)))
+        .collect();
+    // overwrite a few keys in the middle, and add keys after the older table
+    let newer: Vec<(Vec<u8>, Vec<u8>)> = (40..45)
+        .chain(num_of_keys()..num_of_keys() * 2)
+        .map(|idx| (key_of(idx), b"new".to_vec()))
+        .collect();
+    let older_sst = build_sst_from(&dir, "1.sst", &older);
+    let newer_sst = build_sst_from(&dir, "2.sst", &newer);
+    let (sst, stats) = compact(
+        &[newer_sst, older_sst],
+        128,
+        3,
+        None,
+        dir.path().join("3.sst"),
+    )
+    .unwrap();
+    assert!(stats.copied_blocks > 0);
+    assert!(stats.decoded_blocks > 0);
+    assert!(stats.decoded_entries < num_of_keys());
+    let mut expected = older;
+    for (key, value) in newer {
+        match expected.binary_search_by(|(k, _)| k.cmp(&key)) {
+            Ok(idx) => expected[idx].1 = value,
+            Err(idx) => expected.insert(idx, (key, value)),
+        }
+    }
+    check_sst_entries(sst, &expected);
+}
+
+#[test]
+fn test_sst_get_with_partial_read() {
+    let (_dir, sst) = generate_sst();
+    for idx in 0..num_of_keys() {
+        let key = key_of(idx);
+        let value = sst.get_with_partial_read(&key).unwrap();
+        assert_eq!(value, sst.get(&key).unwrap());
+        assert_eq!(value, Some(Bytes::from(value_of(idx))));
+        let absent = format!("key_{:03}", idx * 5 + 1).into_bytes();
+        assert_eq!(sst.get_with_partial_read(&absent).unwrap(), None);
+    }
+    assert_eq!(sst.get_with_partial_read(b"a").unwrap(), None);
+    assert_eq!(sst.get_with_partial_read(b"z").unwrap(), None);
+
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(4096);
+    builder.set_compress_values(true);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_of(idx), &value_of(idx).repeat(10)));
+    }
+    let sst = builder.build_for_test(dir.path().join("2.sst")).unwrap();
+    for idx in 0..num_of_keys() {
+        assert_eq!(
+            sst.get_with_partial_read(&key_of(idx)).unwrap(),
+            Some(Bytes::from(value_of(idx).repeat(10)))
+        );
+    }
+}
+
+#[test]
+fn test_sst_build_split() {
+    let dir = tempdir().unwrap();
+    let data_path = dir.path().join("1.data");
+    let index_path = dir.path().join("1.index");
+    let mut builder = SsTableBuilder::new_validated(128);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_of(idx), &value_of(idx)));
+    }
+    let built = builder
+        .build_split(1, None, &data_path, &index_path)
+        .unwrap();
+    let (_dir, sst) = generate_sst();
+    assert_eq!(built.block_metas, sst.block_metas);
+    // the data file holds exactly the data blocks of a single-file table
+    assert_eq!(
+        std::fs::read(&data_path).unwrap(),
+        sst.file.read(0, sst.block_meta_offset as u64).unwrap()
+    );
+
+    let sst = Arc::new(SsTable::open_split(1, None, &data_path, &index_path).unwrap());
+    assert_eq!(sst.block_metas, built.block_metas);
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    for idx in 0..num_of_keys() {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    assert_eq!(
+        sst.get(&key_of(42)).unwrap(),
+        Some(Bytes::from(value_of(42)))
+    );
+
+    // a truncated data file is rejected
+    let data = std::fs::read(&data_path).unwrap();
+    std::fs::write(&data_path, &data[..data.len() - 1]).unwrap();
+    assert!(matches!(
+        SsTable::open_split(1, None, &data_path, &index_path),
+        Err(TableError::Corruption {
+            block_idx: None,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_sst_iterator_skip() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    for start in [0, 3, 50] {
+        for n in [
+            0,
+            1,
+            5,
+            17,
+            40,
+            num_of_keys() - start - 1,
+            num_of_keys() - start,
+        ] {
+            let mut iter =
+                SsTableIterator::create_and_seek_to_key(sst.clone(), &key_of(start)).unwrap();
+            let mut expected =
+                SsTableIterator::create_and_seek_to_key(sst.clone(), &key_of(start)).unwrap();
+            iter.skip(n).unwrap();
+            for _ in 0..n {
+                expected.next().unwrap();
+            }
+            assert_eq!(iter.is_valid(), expected.is_valid());
+            if expected.is_valid() {
+                assert_eq!(iter.key(), expected.key());
+                assert_eq!(iter.value(), expected.value());
+                iter.next().unwrap();
+                expected.next().unwrap();
+                assert_eq!(iter.key(), expected.key());
+            }
+        }
+    }
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    iter.skip(num_of_keys() * 2).unwrap();
+    assert!(!iter.is_valid());
+    iter.skip(1).unwrap();
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_estimate_range_count() {
+    let (_dir, sst) = generate_sst();
+    let keys: Vec<Vec<u8>> = (0..num_of_keys()).map(key_of).collect();
+    let bounds: Vec<Vec<u8>> = (0..num_of_keys() * 5 + 10)
+        .step_by(3)
+        .map(|idx| format!("key_{:03}", idx).into_bytes())
+        .chain([b"a".to_vec(), b"z".to_vec()])
+        .collect();
+    for lower in &bounds {
+        for upper in &bounds {
+            let actual = keys
+                .iter()
+                .filter(|key| lower <= *key && *key <= upper)
+                .count();
+            assert_eq!(
+                sst.estimate_range_count(lower, upper).unwrap(),
+                actual,
+                "{:?} {:?}",
+                as_bytes(lower),
+                as_bytes(upper)
+            );
+        }
+    }
+    assert_eq!(sst.estimate_range_count(b"a", b"z").unwrap(), num_of_keys());
+}
+
+#[test]
+fn test_block_meta_num_entries() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    for (block_idx, meta) in sst.block_metas().iter().enumerate() {
+        let block = sst.read_block(block_idx).unwrap();
+        assert_eq!(meta.num_entries as usize, block.num_of_entries());
+    }
+    let total: usize = sst
+        .block_metas()
+        .iter()
+        .map(|meta| meta.num_entries as usize)
+        .sum();
+    assert_eq!(total, num_of_keys());
+    let reopened = SsTable::open_for_test(FileObject::from_bytes(Bytes::from(
+        sst.file.read(0, sst.file.size()).unwrap(),
+    )))
+    .unwrap();
+    assert_eq!(reopened.block_metas, sst.block_metas);
+
+    // skipping over whole blocks only reads the block it lands in
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    let reads = sst.file.read_count();
+    iter.skip(num_of_keys() - 1).unwrap();
+    assert_eq!(iter.key(), key_of(num_of_keys() - 1));
+    assert_eq!(sst.file.read_count(), reads + 1);
+
+    // metas of another version are rejected
+    let mut buf = Vec::new();
+    BlockMeta::encode_block_meta(sst.block_metas(), &mut buf);
+    buf[0] = 1;
+    assert!(matches!(
+        BlockMeta::decode_block_meta(&buf[..]),
+        Err(TableError::UnsupportedVersion {
+            what: "block meta",
+            found: 1,
+            expected: 4
+        })
+    ));
+
+    // as are the unversioned metas from before, here with the `last_key` of each block
+    let mut buf = Vec::new();
+    for meta in sst.block_metas() {
+        buf.extend_from_slice(&meta.offset.to_be_bytes());
+        buf.extend_from_slice(&0u16.to_be_bytes());
+        buf.extend_from_slice(&(meta.first_key.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&meta.first_key);
+        buf.extend_from_slice(&0u16.to_be_bytes());
+        buf.extend_from_slice(&(meta.last_key.len() as u16).to_be_bytes());
+        buf.extend_from_slice(&meta.last_key);
+    }
+    assert!(matches!(
+        BlockMeta::decode_block_meta(&buf[..]),
+        Err(TableError::UnsupportedVersion { found: 0, .. })
+    ));
+}
+
+#[test]
+fn test_sst_build_empty_keys() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    assert!(!builder.add(b"", b"empty"));
+    assert!(builder.add(b"11", b"11"));
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    assert_eq!(sst.block_metas()[0].first_key, &b"11"[..]);
+    assert_eq!(sst.block_metas()[0].num_entries, 1);
+
+    let mut builder = SsTableBuilder::new(128);
+    builder.set_allow_empty_keys(true);
+    assert!(builder.add(b"", b"empty"));
+    assert!(builder.add(b"11", b"11"));
+    let sst = builder.build_for_test(dir.path().join("2.sst")).unwrap();
+    assert_eq!(sst.block_metas()[0].first_key, &b""[..]);
+    assert_eq!(sst.block_metas()[0].last_key, &b"11"[..]);
+    assert_eq!(sst.block_metas()[0].num_entries, 2);
+}
+
+#[test]
+fn test_sst_scan_filtered() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    assert!(sst.num_of_blocks() > 2);
+    let is_even = |key: &[u8], _: &[u8]| {
+        let num: usize = std::str::from_utf8(&key[4..]).unwrap().parse().unwrap();
+        num % 2 == 0
+    };
+    let scanned = sst
+        .scan_filtered(b"key_033", &key_of(90), is_even)
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+    let expected = (7..=90)
+        .filter(|idx| idx % 2 == 0)
+        .map(|idx| (as_bytes(&key_of(idx)), as_bytes(&value_of(idx))))
+        .collect::<Vec<_>>();
+    assert_eq!(scanned, expected);
+
+    let all = sst
+        .scan_filtered(b"", b"key_999", |_, _| true)
+        .collect::<Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(all.len(), num_of_keys());
+    assert_eq!(
+        sst.scan_filtered(b"key_999", b"key_999", |_, _| true)
+            .count(),
+        0
+    );
+}
+
+#[test]
+fn test_sst_read_corrupted_entries() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    // built without block checksums, so only decoding the blocks can catch the corruption
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_of(idx), &value_of(idx)));
+    }
+    let sst = builder.build_for_test(&path).unwrap();
+    let len = sst.encoded_block_len(0).unwrap();
+    let num_of_entries = sst.read_block(0).unwrap().num_of_entries();
+    let data = std::fs::read(&path).unwrap();
+    let first_offset = len - 2 - num_of_entries * 2;
+
+    let check = |corrupted: Vec<u8>| {
+        std::fs::write(&path, corrupted).unwrap();
+        let sst = Arc::new(SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap());
+        assert!(matches!(
+            sst.read_block(0),
+            Err(TableError::Corruption {
+                block_idx: Some(0),
+                ..
+            })
+        ));
+        let pool = Arc::new(BlockPool::new(4));
+        let options = ReadOptions {
+            block_pool: Some(pool),
+        };
+        assert!(matches!(
+            sst.read_block_with_options(0, &options),
+            Err(TableError::Corruption {
+                block_idx: Some(0),
+                ..
+            })
+        ));
+        assert!(sst.read_block(1).is_ok());
+        assert!(SsTableIterator::create_and_seek_to_first(sst).is_err());
+    };
+    // the offset of the first entry points past the data section
+    let mut corrupted = data.clone();
+    corrupted[first_offset..first_offset + 2].copy_from_slice(&0xfff0u16.to_be_bytes());
+    check(corrupted);
+    // the key of the first entry runs past the data section
+    let mut corrupted = data;
+    corrupted[0..2].copy_from_slice(&u16::MAX.to_be_bytes());
+    check(corrupted);
+}
+
+#[test]
+fn test_sst_table_errors() {
+    let (_dir, sst) = generate_sst();
+    let data = sst.file.read(0, sst.file.size()).unwrap();
+    let open = |data: Vec<u8>| SsTable::open_for_test(FileObject::from_bytes(Bytes::from(data)));
+
+    let mut unsupported = data.clone();
+    unsupported[sst.block_meta_offset as usize] = 5;
+    assert!(matches!(
+        open(unsupported),
+        Err(TableError::UnsupportedVersion { found: 5, .. })
+    ));
+
+    // cut the metas short while keeping a valid footer
+    let footer = *sst.footer();
+    let mut truncated = data[..footer.bloom_offset as usize - 10].to_vec();
+    Footer {
+        bloom_offset: footer.bloom_offset - 10,
+        ..footer
+    }
+    .encode(&mut truncated);
+    assert!(matches!(
+        open(truncated),
+        Err(TableError::Corruption {
+            block_idx: None,
+            ..
+        })
+    ));
+
+    // a block claiming more entries than it can hold
+    let mut corrupted = data.clone();
+    let end = sst.block_metas()[1].offset as usize;
+    corrupted[end - 2..end].copy_from_slice(&u16::MAX.to_be_bytes());
+    let corrupted = open(corrupted).unwrap();
+    assert!(corrupted.read_block(0).is_ok());
+    assert!(matches!(
+        corrupted.read_block(1),
+        Err(TableError::Corruption {
+            block_idx: Some(1),
+            ..
+        })
+    ));
+    assert!(matches!(
+        corrupted.read_block(corrupted.num_of_blocks()),
+        Err(TableError::BlockIndexOutOfRange { .. })
+    ));
+
+    // a file missing the blocks that its metas point to
+    let short = SsTable {
+        file: FileObject::from_bytes(Bytes::from(data[..4196].to_vec())),
+        block_metas: sst.block_metas().to_vec().into(),
+        block_meta_offset: sst.block_meta_offset,
+        id: 0,
+        block_cache: None,
+        footer: Footer::default(),
+        properties: BTreeMap::new().into(),
+        footer_offset: 0,
+        block_sources: BTreeMap::new(),
+        disk_index: None,
+    };
+    assert!(short.read_block(0).is_ok());
+    match short.read_block(1) {
+        Err(TableError::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof),
+        Err(err) => panic!("expected an I/O error, got {}", err),
+        Ok(_) => panic!("expected an I/O error"),
+    }
+    let err = anyhow::Error::from(short.read_block(1).err().unwrap());
+    assert!(matches!(
+        err.downcast_ref::<TableError>(),
+        Some(TableError::Io(_))
+    ));
+}
+
+#[test]
+fn test_sst_compression_ratio() {
+    let dir = tempdir().unwrap();
+    let (_dir, plain) = generate_sst();
+    assert_eq!(plain.compression_ratio(), 1.0);
+
+    let mut builder = SsTableBuilder::new(1024);
+    builder.set_compress_values(true);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_of(idx), &[b'a' + (idx % 26) as u8; 200]));
+    }
+    let path = dir.path().join("1.sst");
+    let sst = builder.build_for_test(&path).unwrap();
+    assert!(sst.compression_ratio() < 0.3, "{}", sst.compression_ratio());
+    let reopened = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(reopened.compression_ratio(), sst.compression_ratio());
+
+    let mut rng = 0x2545_f491_4f6c_dd1du64;
+    let mut builder = SsTableBuilder::new(1024);
+    builder.set_compress_values(true);
+    for idx in 0..num_of_keys() {
+        let value: Vec<u8> = (0..200)
+            .map(|_| {
+                rng ^= rng << 13;
+                rng ^= rng >> 7;
+                rng ^= rng << 17;
+                rng as u8
+            })
+            .collect();
+        assert!(builder.add(&key_of(idx), &value));
+    }
+    let sst = builder.build_for_test(dir.path().join("2.sst")).unwrap();
+    assert!(
+        sst.compression_ratio() > 0.95,
+        "{}",
+        sst.compression_ratio()
+    );
+    assert!(sst.compression_ratio() <= 1.0);
+}
+
+#[test]
+fn test_sst_iterator_value_len() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    iter.set_lazy_values(true);
+    let mut total = 0;
+    while iter.is_valid() {
+        total += iter.value_len();
+        iter.next().unwrap();
+    }
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    let mut expected = 0;
+    while iter.is_valid() {
+        assert_eq!(iter.value_len(), iter.value().len());
+        expected += iter.value().len();
+        iter.next().unwrap();
+    }
+    assert_eq!(total, expected);
+    assert_eq!(
+        total,
+        (0..num_of_keys()).map(|idx| value_of(idx).len()).sum()
+    );
+}
+
+#[test]
+fn test_sst_read_block_pooled() {
+    let (_dir, sst) = generate_sst();
+    let pool = Arc::new(BlockPool::new(4));
+    let options = ReadOptions {
+        block_pool: Some(pool.clone()),
+    };
+    let mut block_idx = 0;
+    for _ in 0..1000 {
+        block_idx = (block_idx * 7 + 3) % sst.num_of_blocks();
+        let block = sst.read_block_with_options(block_idx, &options).unwrap();
+        assert_eq!(block.encode(), sst.read_block(block_idx).unwrap().encode());
+        let iter = BlockIterator::create_and_seek_to_first(block);
+        assert_eq!(iter.key(), sst.block_metas()[block_idx].first_key);
+    }
+    // every block was dropped before the next read, so a single buffer served all of them
+    assert_eq!(pool.allocations(), 1);
+    assert_eq!(pool.idle_buffers(), 1);
+
+    let held: Vec<_> = (0..3)
+        .map(|idx| sst.read_block_with_options(idx, &options).unwrap())
+        .collect();
+    assert_eq!(pool.allocations(), 3);
+    assert_eq!(pool.idle_buffers(), 0);
+    drop(held);
+    assert_eq!(pool.idle_buffers(), 3);
+
+    // a failed read hands the buffer back
+    assert!(sst
+        .read_block_with_options(sst.num_of_blocks(), &options)
+        .is_err());
+    assert_eq!(pool.idle_buffers(), 3);
+    assert_eq!(pool.allocations(), 3);
+}
+
+#[test]
+fn test_sst_contains_range() {
+    let (_dir, sst) = generate_sst();
+    let check = |lower: &[u8], upper: &[u8]| {
+        let reads = sst.file.read_count();
+        let contains = sst.contains_range(lower, upper).unwrap();
+        assert!(sst.file.read_count() <= reads + 1);
+        contains
+    };
+    // keys are `key_000`, `key_005`, ..., `key_495`
+    assert!(check(&key_of(10), &key_of(20)));
+    assert!(check(b"key_041", b"key_046"));
+    assert!(check(b"", b"key_001"));
+    assert!(check(&key_of(num_of_keys() - 1), b"key_999"));
+    // an empty gap between two keys, and ranges that end right before or start right after a key
+    assert!(!check(b"key_041", b"key_045"));
+    assert!(!check(b"key_046", b"key_050"));
+    for block_idx in 0..sst.num_of_blocks() - 1 {
+        let mut after_last = sst.block_metas()[block_idx].last_key.to_vec();
+        after_last.push(0);
+        assert!(!check(
+            &after_last,
+            &sst.block_metas()[block_idx + 1].first_key
+        ));
+        assert!(check(&after_last, b"key_999"));
+    }
+    // ranges outside of the table, or empty
+    assert!(!check(b"key_496", b"key_999"));
+    assert!(!check(b"a", b"key_000"));
+    assert!(!check(&key_of(20), &key_of(20)));
+    assert!(!check(&key_of(20), &key_of(10)));
+}
+
+#[test]
+fn test_concat_metas() {
+    let dir = tempdir().unwrap();
+    let tables: Vec<Arc<SsTable>> = (0..3)
+        .map(|table_idx| {
+            let mut builder = SsTableBuilder::new(128);
+            for idx in table_idx * 30..(table_idx + 1) * 30 {
+                assert!(builder.add(&key_of(idx), &value_of(idx)));
+            }
+            let path = dir.path().join(format!("{}.sst", table_idx));
+            Arc::new(builder.build_for_test(path).unwrap())
+        })
+        .collect();
+    let metas = concat_metas(&tables);
+    assert_eq!(
+        metas.len(),
+        tables
+            .iter()
+            .map(|table| table.num_of_blocks())
+            .sum::<usize>()
+    );
+    for (idx, meta) in metas.iter().enumerate() {
+        // each block ends within its own slot, right after the previous one
+        assert!(meta.offset as usize > idx * 4196);
+        assert!(meta.offset as usize <= (idx + 1) * 4196);
+    }
+    assert_eq!(metas[0], tables[0].block_metas()[0]);
+    let second = tables[0].num_of_blocks();
+    assert_eq!(
+        metas[second],
+        tables[1].block_metas()[0].rebase(tables[0].block_meta_offset)
+    );
+
+    let mut data = Vec::new();
+    for table in &tables {
+        data.extend(table.file.read(0, table.block_meta_offset as u64).unwrap());
+    }
+    let block_meta_offset = data.len() as u32;
+    BlockMeta::encode_block_meta(&metas, &mut data);
+    Footer {
+        block_meta_offset,
+        bloom_offset: data.len() as u32,
+        ..Default::default()
+    }
+    .encode(&mut data);
+    let sst = SsTable::open_for_test(FileObject::from_bytes(Bytes::from(data))).unwrap();
+    let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
+    for idx in 0..90 {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_disk_index() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..1000 {
+        assert!(builder.add(format!("key_{:05}", idx).as_bytes(), &value_of(idx)));
+    }
+    let path = dir.path().join("1.sst");
+    let sst = builder.build_for_test(&path).unwrap();
+    assert!(sst.num_of_blocks() > 100);
+    let options = OpenOptions {
+        disk_index: Some(DiskIndexOptions {
+            chunk_len: 8,
+            cached_chunks: 2,
+        }),
+    };
+    let open = || SsTable::open_with_options(0, None, FileObject::open(&path).unwrap(), &options);
+    let table = open().unwrap();
+    let disk_index = table.disk_index().unwrap();
+    assert_eq!(table.num_of_blocks(), sst.num_of_blocks());
+    assert_eq!(disk_index.num_of_chunks(), (sst.num_of_blocks() + 7) / 8);
+    assert_eq!(disk_index.meta_reads(), 0);
+    assert_eq!(table.first_key(), sst.first_key());
+    assert_eq!(table.last_key(), sst.last_key());
+    for (block_idx, meta) in sst.block_metas().iter().enumerate() {
+        assert_eq!(table.try_block_meta(block_idx).unwrap().as_ref(), meta);
+    }
+
+    // every lookup reads at most one chunk of metas, wherever the key is
+    let mut idx = 0;
+    for _ in 0..200 {
+        idx = (idx * 31 + 17) % 1000;
+        let key = format!("key_{:05}", idx);
+        let reads = disk_index.meta_reads();
+        assert_eq!(
+            table.find_block_idx(key.as_bytes()),
+            sst.find_block_idx(key.as_bytes())
+        );
+        assert_eq!(
+            table.get(key.as_bytes()).unwrap(),
+            Some(as_bytes(&value_of(idx)))
+        );
+        assert!(disk_index.meta_reads() <= reads + 1);
+    }
+    assert_eq!(table.get(b"key_00000_").unwrap(), None);
+    assert_eq!(table.get(b"a").unwrap(), None);
+    assert_eq!(table.get(b"key_99999").unwrap(), None);
+    assert!(table.try_block_meta(sst.num_of_blocks()).is_err());
+
+    // iterators read the chunks they go through, without loading every meta
+    let table = Arc::new(open().unwrap());
+    let mut iter = SsTableIterator::create_and_seek_to_key(table.clone(), b"key_00500").unwrap();
+    for idx in 500..1000 {
+        assert_eq!(iter.key(), format!("key_{:05}", idx).as_bytes());
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    let mut iter = SsTableIterator::create_and_seek_to_first(table.clone()).unwrap();
+    iter.skip(250).unwrap();
+    assert_eq!(iter.key(), b"key_00250");
+    assert!(table.block_metas.get().is_none());
+    // the whole index is only loaded when asked for
+    assert_eq!(table.block_metas(), sst.block_metas());
+
+    let zero_chunk_len = OpenOptions {
+        disk_index: Some(DiskIndexOptions {
+            chunk_len: 0,
+            cached_chunks: 2,
+        }),
+    };
+    let result =
+        SsTable::open_with_options(0, None, FileObject::open(&path).unwrap(), &zero_chunk_len);
+    assert!(matches!(result, Err(TableError::Unsupported(_))));
+}
+
+#[test]
+fn test_sst_disk_index_large_meta_section() {
+    // the meta section spans many reads of the open scan, with metas cut between them
+    let mut builder = SsTableBuilder::new(4096);
+    for idx in 0..400 {
+        let key = format!("{:05}{}", idx, "k".repeat(3000));
+        assert!(builder.add(key.as_bytes(), b"value"));
+    }
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let sst = builder.build_for_test(&path).unwrap();
+    let options = OpenOptions {
+        disk_index: Some(DiskIndexOptions::default()),
+    };
+    let table =
+        SsTable::open_with_options(0, None, FileObject::open(&path).unwrap(), &options).unwrap();
+    assert_eq!(table.num_of_blocks(), sst.num_of_blocks());
+    assert_eq!(table.last_key(), sst.last_key());
+    for idx in (0..400).step_by(37) {
+        let key = format!("{:05}{}", idx, "k".repeat(3000));
+        assert_eq!(table.get(key.as_bytes()).unwrap(), Some(as_bytes(b"value")));
+    }
+}
+
+#[test]
+fn test_sst_iterator_timestamp() {
+    use crate::key::key_with_ts;
+
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    builder.set_max_ts((num_of_keys() as u64 - 1) * 10 + 3);
+    for idx in 0..num_of_keys() {
+        for ts in [3, 2, 1] {
+            assert!(builder.add(&key_with_ts(&key_of(idx), idx as u64 * 10 + ts), b"v"));
+        }
+    }
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    assert!(sst.num_of_blocks() > 1);
+    let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
+    for idx in 0..num_of_keys() {
+        for ts in [3, 2, 1] {
+            assert_eq!(iter.timestamp(), idx as u64 * 10 + ts);
+            iter.next().unwrap();
+        }
+    }
+    assert!(!iter.is_valid());
+    assert_eq!(iter.timestamp(), 0);
+
+    // keys of 8 bytes or more in a table built without timestamps are not MVCC keys
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(format!("long_key_{:05}", idx).as_bytes(), b"v"));
+    }
+    let sst = builder.build_for_test(dir.path().join("2.sst")).unwrap();
+    let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
+    while iter.is_valid() {
+        assert_eq!(iter.timestamp(), 0);
+        iter.next().unwrap();
+    }
+}
+
+#[test]
+fn test_sst_footer() {
+    let footer = Footer {
+        block_meta_offset: 4196 * 3,
+        bloom_offset: 4196 * 3 + 100,
+        entry_count: 42,
+        max_ts: u64::MAX - 1,
+        uncompressed_bytes: 9000,
+        compressed_bytes: 3000,
+        table_id: 7,
+        index_sparsity: 4,
+        properties_len: 20,
+    };
+    let mut buf = vec![0xab; 5];
+    footer.encode(&mut buf);
+    assert_eq!(buf.len(), 5 + 65);
+    assert_eq!(Footer::decode(&buf[5..]).unwrap(), footer);
+    assert!(Footer::decode(&buf[6..]).is_err());
+
+    for pos in 5..buf.len() - 8 {
+        let mut corrupted = buf.clone();
+        corrupted[pos] ^= 1;
+        assert!(matches!(
+            Footer::decode(&corrupted[5..]),
+            Err(TableError::ChecksumMismatch { what: "footer", .. })
+        ));
+    }
+    let mut corrupted = buf.clone();
+    *corrupted.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+        Footer::decode(&corrupted[5..]),
+        Err(TableError::Corruption { .. })
+    ));
+
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    builder.set_max_ts(233);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_of(idx), &value_of(idx)));
+    }
+    let path = dir.path().join("1.sst");
+    let sst = builder.build_for_test(&path).unwrap();
+    let reopened = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(reopened.footer(), sst.footer());
+    assert_eq!(sst.footer().entry_count, num_of_keys() as u64);
+    assert_eq!(sst.footer().max_ts, 233);
+    assert_eq!(sst.footer().block_meta_offset, sst.block_meta_offset);
+    assert_eq!(
+        sst.footer().bloom_offset as u64,
+        sst.file.size() - buf.len() as u64 + 5
+    );
+}
+
+#[test]
+fn test_sst_open_expected_id() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_of(idx), &value_of(idx)));
+    }
+    let path = dir.path().join("5.sst");
+    let sst = builder.build(5, None, &path).unwrap();
+    assert_eq!(sst.id(), 5);
+    assert_eq!(sst.footer().table_id, 5);
+
+    let open = |expected_id| {
+        SsTable::open_with_expected_id(expected_id, None, FileObject::open(&path).unwrap())
+    };
+    assert_eq!(open(Some(5)).unwrap().id(), 5);
+    assert_eq!(open(None).unwrap().id(), 5);
+    assert!(matches!(
+        open(Some(6)),
+        Err(TableError::IdMismatch {
+            expected: 6,
+            found: 5
+        })
+    ));
+}
+
+#[test]
+fn test_sst_seek_within_block() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    let mut iter = SsTableIterator::create_and_seek_to_key(sst.clone(), &key_of(0)).unwrap();
+    let meta = &sst.block_metas()[0];
+    let last_idx = (0..num_of_keys())
+        .take_while(|&idx| key_of(idx) <= meta.last_key)
+        .last()
+        .unwrap();
+    assert!(last_idx > 1);
+
+    let reads = sst.file.read_count();
+    for idx in [1, last_idx, 0, last_idx - 1] {
+        iter.seek_to_key(&key_of(idx)).unwrap();
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+    }
+    assert_eq!(sst.file.read_count(), reads);
+
+    // a key past the block still moves on to the next one
+    iter.seek_to_key(&key_of(last_idx + 1)).unwrap();
+    assert_eq!(iter.key(), key_of(last_idx + 1));
+    assert_eq!(sst.file.read_count(), reads + 1);
+}
+
+#[test]
+fn test_sst_from_blocks() {
+    let block_of = |range: std::ops::Range<usize>| {
+        let mut builder = crate::block::BlockBuilder::new(4096);
+        for idx in range {
+            assert!(builder.add(&key_of(idx), &value_of(idx)));
+        }
+        builder.build()
+    };
+    // blocks of uneven sizes, ending with an empty one
+    let blocks = vec![
+        block_of(0..1),
+        block_of(1..40),
+        block_of(40..45),
+        block_of(0..0),
+    ];
+    let sst = Arc::new(SsTable::from_blocks(3, blocks).unwrap());
+    assert_eq!(sst.id(), 3);
+    assert_eq!(sst.num_of_blocks(), 4);
+    assert_eq!(sst.footer().entry_count, 45);
+    assert_eq!(sst.block_metas()[3].num_entries, 0);
+    assert_eq!(sst.block_metas()[3].first_key, key_of(44));
+
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    for idx in 0..45 {
+        assert!(iter.is_valid());
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    assert_eq!(sst.get(&key_of(20)).unwrap().unwrap(), value_of(20));
+}
+
+#[test]
+fn test_sst_reopen() {
+    let (_dir, sst) = generate_sst();
+    let reads = sst.file.read_count();
+    let block_cache = Arc::new(BlockCache::new(1024));
+    let sst = sst.reopen(Some(block_cache.clone()));
+    assert_eq!(sst.file.read_count(), reads);
+
+    let sst = Arc::new(sst);
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    for idx in 0..num_of_keys() {
+        assert_eq!(iter.key(), key_of(idx));
+        assert_eq!(iter.value(), value_of(idx));
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+    sst.read_block_cached(0).unwrap();
+    assert!(block_cache.contains_key(&(sst.id(), 0)));
+}
+
+#[test]
+fn test_sst_sparse_index() {
+    let dir = tempdir().unwrap();
+    // long keys, so that the keys make up most of the metas
+    let long_key = |idx: usize| {
+        let mut key = format!("{:05}", idx * 5).into_bytes();
+        key.extend((0..200).map(|i| b'a' + ((idx * 7 + i * 13) % 26) as u8));
+        key
+    };
+    let mut tables = Vec::new();
+    for index_sparsity in [1, 4] {
+        let mut builder = SsTableBuilder::new_validated(512);
+        builder.set_index_sparsity(index_sparsity);
+        for idx in 0..num_of_keys() {
+            assert!(builder.add(&long_key(idx), &value_of(idx)));
+        }
+        let path = dir.path().join(format!("{}.sst", index_sparsity));
+        builder.build(0, None, &path).unwrap();
+        tables.push(Arc::new(
+            SsTable::open(0, None, FileObject::open(&path).unwrap()).unwrap(),
+        ));
+    }
+    let (dense, sparse) = (&tables[0], &tables[1]);
+    assert_eq!(sparse.index_sparsity(), 4);
+    assert!(sparse.num_of_blocks() > 8);
+    let meta_len = |sst: &SsTable| sst.footer().bloom_offset - sst.footer().block_meta_offset;
+    assert!(meta_len(sparse) * 3 < meta_len(dense));
+    assert!(sparse.memory_footprint() < dense.memory_footprint());
+    assert_eq!(sparse.first_key(), dense.first_key());
+    assert_eq!(sparse.last_key(), dense.last_key());
+
+    for idx in 0..num_of_keys() {
+        let key = long_key(idx);
+        assert_eq!(sparse.find_block_idx(&key), dense.find_block_idx(&key));
+        assert_eq!(sparse.get(&key).unwrap().unwrap(), value_of(idx));
+        // a key between two keys of the table
+        let mut next_key = key.clone();
+        next_key.push(0);
+        assert_eq!(
+            sparse.find_block_idx(&next_key),
+            dense.find_block_idx(&next_key)
+        );
+        let upper = long_key(idx + 1);
+        assert_eq!(
+            sparse.contains_range(&next_key, &upper).unwrap(),
+            dense.contains_range(&next_key, &upper).unwrap()
+        );
+        let iter = SsTableIterator::create_and_seek_to_key(sparse.clone(), &next_key).unwrap();
+        if idx + 1 < num_of_keys() {
+            assert_eq!(iter.key(), upper);
+        } else {
+            assert!(!iter.is_valid());
+        }
+    }
+    assert!(sparse.get(b"0").unwrap().is_none());
+    for key in sparse.split_points(4) {
+        assert!(sparse.is_indexed(sparse.find_block_idx(&key)));
+    }
+
+    // a compaction reads the blocks left out of the index to get their keys
+    let (compacted, _) =
+        compact(&[sparse.clone()], 512, 1, None, dir.path().join("1.sst")).unwrap();
+    assert_eq!(compacted.footer().entry_count, num_of_keys() as u64);
+}
+
+#[test]
+fn test_sst_build_spilled() {
+    let dir = tempdir().unwrap();
+    let spilled_path = dir.path().join("1.sst");
+    let mut spilling = SsTableBuilder::new_spilling(128, &spilled_path).unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        assert!(spilling.add(&key_of(idx), &value_of(idx)));
+        assert!(builder.add(&key_of(idx), &value_of(idx)));
+    }
+    assert_eq!(spilling.estimated_size(), builder.estimated_size());
+    assert!(spilling.data_blocks.is_empty());
+    let spilled = spilling.build_spilled(1, None).unwrap();
+    let built_path = dir.path().join("2.sst");
+    builder.build(1, None, &built_path).unwrap();
+    assert_eq!(
+        std::fs::read(&spilled_path).unwrap(),
+        std::fs::read(&built_path).unwrap()
+    );
+    assert_eq!(spilled.get(&key_of(7)).unwrap().unwrap(), value_of(7));
+}
+
+#[test]
+fn test_compact_many_inputs() {
+    let dir = tempdir().unwrap();
+    let num_of_inputs = 16;
+    let entries: Vec<(Vec<u8>, Vec<u8>)> = (0..num_of_keys() * 2)
+        .map(|idx| (key_of(idx), value_of(idx)))
+        .collect();
+    // every input holds every `num_of_inputs`-th key, so that all of them interleave
+    let tables: Vec<Arc<SsTable>> = (0..num_of_inputs)
+        .map(|input| {
+            let entries: Vec<_> = entries
+                .iter()
+                .skip(input)
+                .step_by(num_of_inputs)
+                .cloned()
+                .collect();
+            build_sst_from(&dir, &format!("{}.sst", input), &entries)
+        })
+        .collect();
+    let (sst, stats) = compact(&tables, 128, 100, None, dir.path().join("100.sst")).unwrap();
+    assert!(stats.peak_loaded_blocks <= num_of_inputs);
+    assert!(stats.peak_loaded_blocks < sst.num_of_blocks());
+    check_sst_entries(sst, &entries);
+}
+
+#[test]
+fn test_sst_open_verify_index() {
+    let (_dir, sst) = generate_sst();
+    let data = sst.file.read(0, sst.file.size()).unwrap();
+    let open = |data: Vec<u8>, verify_index| {
+        SsTable::open_with_verify_index(
+            0,
+            None,
+            FileObject::from_bytes(Bytes::from(data)),
+            verify_index,
+        )
+    };
+    assert!(open(data.clone(), true).is_ok());
+
+    // a first_key still between the keys of the neighbouring blocks, so only reading the block
+    // tells it apart
+    let mut metas = sst.block_metas().to_vec();
+    let mut first_key = metas[2].first_key.to_vec();
+    first_key.push(b'0');
+    metas[2].first_key = Bytes::from(first_key);
+    let mut data = data[..sst.block_meta_offset as usize].to_vec();
+    BlockMeta::encode_block_meta(&metas, &mut data);
+    Footer {
+        block_meta_offset: sst.block_meta_offset,
+        bloom_offset: data.len() as u32,
+        ..Default::default()
+    }
+    .encode(&mut data);
+    assert!(open(data.clone(), false).is_ok());
+    assert!(matches!(
+        open(data, true),
+        Err(TableError::Corruption {
+            block_idx: Some(2),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_sst_read_block_cache_hint() {
+    let (_dir, sst) = generate_sst();
+    let block_cache = Arc::new(BlockCache::new(4));
+    let sst = sst.reopen(Some(block_cache.clone()));
+    let hot = sst.num_of_blocks() / 2;
+    sst.read_block_cached_with_hint(hot, CacheHint::PointLookup)
+        .unwrap();
+    assert!(block_cache.contains_key(&(sst.id(), hot)));
+
+    let reads = sst.file.read_count();
+    for block_idx in 0..sst.num_of_blocks() {
+        sst.read_block_cached_with_hint(block_idx, CacheHint::Scan)
+            .unwrap();
+    }
+    // the hot block is served from the cache, and no scanned block took its place
+    assert_eq!(sst.file.read_count(), reads + sst.num_of_blocks() - 1);
+    for block_idx in 0..sst.num_of_blocks() {
+        assert_eq!(
+            block_cache.contains_key(&(sst.id(), block_idx)),
+            block_idx == hot
+        );
+    }
+}
+
+#[test]
+fn test_sst_scan_prefix_rev() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    // keys are `key_000`, `key_005`, ..., `key_495`
+    for prefix in [
+        &b"key_0"[..],
+        b"key_1",
+        b"key_12",
+        b"key_49",
+        b"key_495",
+        b"key_",
+        b"",
+        b"key_5",
+        b"a",
+        b"z",
+        b"key_\xff",
+    ] {
+        let mut forward = Vec::new();
+        let mut iter = SsTableIterator::create_and_seek_to_key(sst.clone(), prefix).unwrap();
+        while iter.is_valid() && iter.key().starts_with(prefix) {
+            forward.push((
+                Bytes::copy_from_slice(iter.key()),
+                Bytes::copy_from_slice(iter.value()),
+            ));
+            iter.next().unwrap();
+        }
+        let mut reversed: Vec<(Bytes, Bytes)> =
+            sst.scan_prefix_rev(prefix).collect::<Result<_>>().unwrap();
+        reversed.reverse();
+        assert_eq!(reversed, forward, "prefix {:?}", Bytes::from(prefix));
+    }
+    assert_eq!(sst.scan_prefix_rev(b"key_0").count(), 20);
+}
+
+#[test]
+fn test_sst_properties() {
+    let dir = tempdir().unwrap();
+    let build = |with_properties: bool| {
+        let mut builder = SsTableBuilder::new(128);
+        for idx in 0..num_of_keys() {
+            assert!(builder.add(&key_of(idx), &value_of(idx)));
+        }
+        if with_properties {
+            builder.set_property("creator", b"compaction");
+            builder.set_property("level", &[2]);
+            builder.set_property("schema", b"v1");
+            builder.set_property("schema", b"v2");
+            builder.set_property("empty", b"");
+        }
+        builder
+    };
+    let path = dir.path().join("1.sst");
+    let built = build(true).build(1, None, &path).unwrap();
+    let plain = build(false)
+        .build(2, None, dir.path().join("2.sst"))
+        .unwrap();
+    assert_eq!(built.block_meta_offset, plain.block_meta_offset);
+    assert_eq!(built.footer().bloom_offset, plain.footer().bloom_offset);
+    assert_eq!(plain.footer().properties_len, 0);
+    assert_eq!(plain.property("creator"), None);
+
+    let data_path = dir.path().join("3.data");
+    let index_path = dir.path().join("3.index");
+    let split = build(true)
+        .build_split(3, None, &data_path, &index_path)
+        .unwrap();
+    let tables = [
+        built,
+        SsTable::open(1, None, FileObject::open(&path).unwrap()).unwrap(),
+        split,
+        SsTable::open_split(3, None, &data_path, &index_path).unwrap(),
+    ];
+    for sst in &tables {
+        assert_eq!(sst.property("creator"), Some(&b"compaction"[..]));
+        assert_eq!(sst.property("level"), Some(&[2][..]));
+        assert_eq!(sst.property("schema"), Some(&b"v2"[..]));
+        assert_eq!(sst.property("empty"), Some(&b""[..]));
+        assert_eq!(sst.property("missing"), None);
+        assert_eq!(sst.get(&key_of(3)).unwrap().unwrap(), value_of(3));
+    }
+}
+
+#[test]
+fn test_sst_open_lazy() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_of(idx), &value_of(idx)));
+    }
+    builder.set_property("creator", b"flush");
+    let eager = builder.build(1, None, &path).unwrap();
+
+    let lazy = SsTable::open_lazy(1, None, FileObject::open(&path).unwrap()).unwrap();
+    // only the footer is read
+    assert_eq!(lazy.table.file.read_count(), 1);
+    assert_eq!(lazy.footer(), eager.footer());
+    assert_eq!(lazy.id(), 1);
+    // the metas and the properties come in a single read
+    let sst = lazy.table().unwrap();
+    assert_eq!(sst.file.read_count(), 2);
+    let key = key_of(num_of_keys() / 2);
+    assert_eq!(sst.find_block_idx(&key), eager.find_block_idx(&key));
+    assert_eq!(sst.block_metas(), eager.block_metas());
+    assert_eq!(sst.property("creator"), Some(&b"flush"[..]));
+    assert_eq!(sst.memory_footprint(), eager.memory_footprint());
+    lazy.table().unwrap();
+    assert_eq!(lazy.table.file.read_count(), 2);
+
+    let lazy = SsTable::open_lazy(1, None, FileObject::open(&path).unwrap()).unwrap();
+    let lazy = Arc::new(lazy.into_table().unwrap());
+    lazy.read_block(0).unwrap();
+    assert_eq!(lazy.file.read_count(), 3);
+    let mut iter = SsTableIterator::create_and_seek_to_key(lazy, &key).unwrap();
+    assert_eq!(iter.key(), key);
+    iter.next().unwrap();
+    assert_eq!(iter.key(), key_of(num_of_keys() / 2 + 1));
+
+    // a failed read of the metas is returned instead of panicking later
+    let mut data = std::fs::read(&path).unwrap();
+    // the version in front of the metas
+    data[eager.block_meta_offset as usize] = 0xff;
+    let corrupted = dir.path().join("2.sst");
+    std::fs::write(&corrupted, &data).unwrap();
+    let lazy = SsTable::open_lazy(2, None, FileObject::open(&corrupted).unwrap()).unwrap();
+    assert!(matches!(
+        lazy.table(),
+        Err(TableError::UnsupportedVersion { found: 0xff, .. })
+    ));
+    assert!(lazy.into_table().is_err());
+}
+
+#[test]
+fn test_sst_import_csv() {
+    let dir = tempdir().unwrap();
+    let csv = "id,name,score\r\n\
+               apple,red,3\r\n\
+               banana,yellow,7\r\n\
+               \r\n\
+               cherry,,1\r\n";
+    let mut builder = SsTableBuilder::new(128);
+    // skip the header row by importing from the second line
+    let body = csv.split_once('\n').unwrap().1;
+    assert_eq!(builder.import_csv(body.as_bytes(), b',', 0, 1).unwrap(), 3);
+    let sst = Arc::new(builder.build(1, None, dir.path().join("1.sst")).unwrap());
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+    for (key, value) in [("apple", "red"), ("banana", "yellow"), ("cherry", "")] {
+        assert_eq!(iter.key(), key.as_bytes());
+        assert_eq!(iter.value(), value.as_bytes());
+        iter.next().unwrap();
+    }
+    assert!(!iter.is_valid());
+
+    let tsv = "1\tb\n2\ta\n3\tc\n";
+    let mut builder = SsTableBuilder::new(128);
+    let err = builder.import_csv(tsv.as_bytes(), b'\t', 1, 0).unwrap_err();
+    assert!(err.to_string().starts_with("line 2:"), "{}", err);
+    let mut builder = SsTableBuilder::new(128);
+    let err = builder
+        .import_csv("a\tb\nc\n".as_bytes(), b'\t', 0, 1)
+        .unwrap_err();
+    assert!(err.to_string().starts_with("line 2:"), "{}", err);
+
+    // the rows must come after the keys added before, by `add` or an earlier import
+    let mut builder = SsTableBuilder::new(128);
+    assert!(builder.add(b"banana", b"yellow"));
+    let err = builder
+        .import_csv("apple,red\n".as_bytes(), b',', 0, 1)
+        .unwrap_err();
+    assert!(err.to_string().starts_with("line 1:"), "{}", err);
+    for idx in 0..num_of_keys() / 2 {
+        assert!(builder.add(format!("cherry_{:03}", idx).as_bytes(), &value_of(idx)));
+    }
+    assert_eq!(
+        builder
+            .import_csv("date,brown\n".as_bytes(), b',', 0, 1)
+            .unwrap(),
+        1
+    );
+    let err = builder
+        .import_csv("cherry_999,red\n".as_bytes(), b',', 0, 1)
+        .unwrap_err();
+    assert!(err.to_string().starts_with("line 1:"), "{}", err);
+}
+
+#[test]
+fn test_sst_scan_cached_concurrent() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_of(idx), &value_of(idx)));
+    }
+    builder.build(1, None, &path).unwrap();
+    let block_cache = Arc::new(BlockCache::new(1024));
+    let sst =
+        Arc::new(SsTable::open(1, Some(block_cache), FileObject::open(&path).unwrap()).unwrap());
+    assert!(sst.num_of_blocks() > 1);
+    let reads = sst.file.read_count();
+    std::thread::scope(|scope| {
+        for _ in 0..8 {
+            scope.spawn(|| {
+                let mut iter = sst.scan_cached().unwrap();
+                for idx in 0..num_of_keys() {
+                    assert_eq!(iter.key(), key_of(idx));
+                    assert_eq!(iter.value(), value_of(idx));
+                    iter.next().unwrap();
+                }
+                assert!(!iter.is_valid());
+            });
+        }
+    });
+    // each block was read from the disk once, by whichever scan missed it first
+    assert_eq!(sst.file.read_count() - reads, sst.num_of_blocks());
+}
+
+#[test]
+fn test_sst_delta_keys() {
+    let dir = tempdir().unwrap();
+    let build = |delta_keys: bool, path: &str| {
+        let mut builder = SsTableBuilder::new(1024);
+        builder.set_delta_keys(delta_keys);
+        for seq in 0..2000u64 {
+            assert!(builder.add(&(seq * 2).to_be_bytes(), b"v"));
+        }
+        builder.build(1, None, dir.path().join(path)).unwrap()
+    };
+    let plain = build(false, "1.sst");
+    let delta = build(true, "2.sst");
+    assert!(delta.num_of_blocks() * 2 < plain.num_of_blocks());
+    let delta = SsTable::open(
+        1,
+        None,
+        FileObject::open(&dir.path().join("2.sst")).unwrap(),
+    )
+    .unwrap();
+    for seq in [0u64, 1, 777, 3999] {
+        let expected = (seq % 2 == 0).then(|| Bytes::from_static(b"v"));
+        assert_eq!(delta.get(&seq.to_be_bytes()).unwrap(), expected);
+        assert_eq!(
+            delta.get_with_partial_read(&seq.to_be_bytes()).unwrap(),
+            expected
+        );
+    }
+    let options = ReadOptions {
+        block_pool: Some(Arc::new(BlockPool::new(4))),
+    };
+    let block = delta.read_block_with_options(1, &options).unwrap();
+    assert_eq!(block.key_at(0), delta.block_metas()[1].first_key);
+
+    // copied blocks keep their entry counts, without the delta flag
+    let delta = Arc::new(delta);
+    let (compacted, stats) =
+        compact(&[delta.clone()], 1024, 3, None, dir.path().join("3.sst")).unwrap();
+    assert_eq!(stats.copied_blocks, delta.num_of_blocks());
+    assert_eq!(compacted.block_metas(), delta.block_metas());
+    assert_eq!(compacted.footer().entry_count, 2000);
+    assert_eq!(
+        compacted.estimate_range_count(b"", &[0xff; 8]).unwrap(),
+        2000
+    );
+    let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(compacted)).unwrap();
+    iter.skip(1999).unwrap();
+    assert_eq!(iter.key(), 3998u64.to_be_bytes());
+
+    // a corrupted block is rejected instead of copied
+    let mut encoded = delta.read_encoded_block(0).unwrap();
+    let len = encoded.len();
+    encoded[len - 3] = 9;
+    let mut builder = SsTableBuilder::new(1024);
+    let meta = &delta.block_metas()[0];
+    assert!(matches!(
+        builder.add_encoded_block(Bytes::from(encoded), &meta.first_key, &meta.last_key),
+        Err(TableError::Corruption { .. })
+    ));
+    assert_eq!(builder.estimated_size(), 0);
+}
+
+#[test]
+fn test_sst_block_size_histogram() {
+    let (_dir, sst) = generate_sst();
+    let histogram = sst.block_size_histogram();
+    assert_eq!(histogram.len(), sst.num_of_blocks());
+    assert_eq!(
+        histogram.iter().map(|&len| len as u64).sum::<u64>(),
+        sst.footer().compressed_bytes
+    );
+    assert!(histogram.iter().all(|&len| (2..=128).contains(&len)));
+}
+
+#[test]
+fn test_sst_open_truncated_at() {
+    let (dir, full) = generate_sst();
+    let path = dir.path().join("1.sst");
+    let keep = full.num_of_blocks() / 2;
+    let boundary = full.block_metas()[keep - 1].offset as u64;
+    // the end of a block, and a length cutting into the next block
+    for valid_data_len in [boundary, keep as u64 * 4196 + 5] {
+        let sst =
+            SsTable::open_truncated_at(0, None, FileObject::open(&path).unwrap(), valid_data_len)
+                .unwrap();
+        assert_eq!(sst.num_of_blocks(), keep);
+        assert_eq!(sst.last_key(), Some(&full.block_metas()[keep - 1].last_key));
+        let kept: usize = sst
+            .block_metas()
+            .iter()
+            .map(|meta| meta.num_entries as usize)
+            .sum();
+        let mut iter = SsTableIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
+        for idx in 0..kept {
+            assert_eq!(iter.key(), key_of(idx));
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+    }
+    let sst = SsTable::open_truncated_at(0, None, FileObject::open(&path).unwrap(), 0).unwrap();
+    assert_eq!(sst.num_of_blocks(), 0);
+
+    // a sparse index keeps the keys of the new last block
+    let mut builder = SsTableBuilder::new(128);
+    builder.set_index_sparsity(4);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_of(idx), &value_of(idx)));
+    }
+    let sparse = builder.build(1, None, dir.path().join("2.sst")).unwrap();
+    let sst = SsTable::open_truncated_at(
+        1,
+        None,
+        FileObject::open(&dir.path().join("2.sst")).unwrap(),
+        sparse.block_metas()[1].offset as u64,
+    )
+    .unwrap();
+    let last_key = sst.read_block(1).unwrap().key_at(0).to_vec();
+    assert_eq!(sst.block_metas()[1].first_key, last_key);
+    assert!(sst.get(&last_key).unwrap().is_some());
+}
+
+#[test]
+fn test_sst_scan_with_overlay() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    let mut overlay = BTreeMap::new();
+    // a new key before all the others, one between two keys and one after all of them
+    overlay.insert(Bytes::from_static(b"a"), Some(Bytes::from_static(b"new")));
+    overlay.insert(
+        Bytes::from([key_of(4), b"0".to_vec()].concat()),
+        Some(Bytes::from_static(b"between")),
+    );
+    overlay.insert(Bytes::from_static(b"z"), Some(Bytes::from_static(b"last")));
+    overlay.insert(
+        Bytes::from(key_of(2)),
+        Some(Bytes::from_static(b"overwritten")),
+    );
+    overlay.insert(Bytes::from(key_of(3)), None);
+    overlay.insert(Bytes::from_static(b"missing"), None);
+
+    let mut expected: BTreeMap<Bytes, Bytes> = (0..num_of_keys())
+        .map(|idx| (Bytes::from(key_of(idx)), Bytes::from(value_of(idx))))
+        .collect();
+    for (key, value) in &overlay {
+        match value {
+            Some(value) => expected.insert(key.clone(), value.clone()),
+            None => expected.remove(key),
+        };
+    }
+    let merged: Vec<(Bytes, Bytes)> = sst
+        .scan_with_overlay(&overlay)
+        .collect::<Result<_>>()
+        .unwrap();
+    assert_eq!(merged, expected.into_iter().collect::<Vec<_>>());
+
+    let merged = sst.scan_with_overlay(&BTreeMap::new()).count();
+    assert_eq!(merged, num_of_keys());
+}
+
+#[test]
+fn test_sst_all_keys() {
+    let (_dir, sst) = generate_sst();
+    let expected: Vec<Bytes> = (0..num_of_keys())
+        .map(|idx| Bytes::from(key_of(idx)))
+        .collect();
+    assert_eq!(sst.all_keys().unwrap(), expected);
+    let mut count = 0;
+    sst.for_each_key(|key| {
+        assert_eq!(key, key_of(count));
+        count += 1;
+    })
+    .unwrap();
+    assert_eq!(count, num_of_keys());
+
+    let table = SsTable::from_blocks(1, vec![]).unwrap();
+    assert!(table.all_keys().unwrap().is_empty());
+}
+
+#[test]
+fn test_sst_scan_readahead() {
+    let (dir, _) = generate_sst();
+    let path = dir.path().join("1.sst");
+    let scan_reads = |file: FileObject| {
+        let sst = Arc::new(SsTable::open(0, None, file).unwrap());
+        let reads = sst.file.read_count();
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+        for idx in 0..num_of_keys() {
+            assert_eq!(iter.key(), key_of(idx));
+            assert_eq!(iter.value(), value_of(idx));
+            iter.next().unwrap();
+        }
+        assert!(!iter.is_valid());
+        (sst.num_of_blocks(), sst.file.read_count() - reads)
+    };
+    let (num_of_blocks, plain_reads) = scan_reads(FileObject::open(&path).unwrap());
+    assert_eq!(plain_reads, num_of_blocks);
+    // the first block is read by a seek, then each read fetches the next 8 block slots
+    let file = FileObject::open(&path).unwrap().with_readahead(8 * 4196);
+    let (_, readahead_reads) = scan_reads(file);
+    assert_eq!(readahead_reads, 1 + (num_of_blocks - 1 + 7) / 8);
+}
+
+#[test]
+fn test_sst_verify_parallel() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..2000 {
+        assert!(builder.add(format!("key_{:05}", idx).as_bytes(), &value_of(idx)));
+    }
+    let sst = builder.build(1, None, &path).unwrap();
+    sst.verify_parallel().unwrap();
+
+    let bad_block = sst.num_of_blocks() / 2;
+    let corrupt = |edit: &dyn Fn(&mut [u8])| {
+        let mut data = std::fs::read(&path).unwrap();
+        let end = sst.block_metas()[bad_block].offset as usize;
+        edit(&mut data[bad_block * 4196..end]);
+        SsTable::open(1, None, FileObject::from_bytes(Bytes::from(data))).unwrap()
+    };
+    // too many entries for the block, and a key changed out of order
+    let tables = [
+        corrupt(&|block: &mut [u8]| {
+            let len = block.len();
+            block[len - 2..].copy_from_slice(&0x7fffu16.to_be_bytes());
+        }),
+        corrupt(&|block: &mut [u8]| block[2..2 + 5].copy_from_slice(b"key_9")),
+    ];
+    for table in tables {
+        match table.verify_parallel() {
+            Err(TableError::Corruption { block_idx, .. }) => assert_eq!(block_idx, Some(bad_block)),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn test_sst_verify_checksums_only() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("1.sst");
+    let mut builder = SsTableBuilder::new(128);
+    builder.set_block_checksums(true);
+    for idx in 0..2000 {
+        assert!(builder.add(format!("key_{:05}", idx).as_bytes(), &value_of(idx)));
+    }
+    let sst = builder.build(1, None, &path).unwrap();
+    sst.verify_checksums_only().unwrap();
+    let file = FileObject::open(&path).unwrap().with_readahead(8 * 4196);
+    let reopened = SsTable::open(1, None, file).unwrap();
+    reopened.verify_checksums_only().unwrap();
+    // the blocks are streamed, several per read
+    assert!(reopened.file.read_count() < sst.num_of_blocks());
+
+    // a data byte changed, the checksums left as they are
+    let bad_block = sst.num_of_blocks() / 2;
+    let mut data = std::fs::read(&path).unwrap();
+    data[bad_block * 4196 + 3] ^= 1;
+    let corrupted = SsTable::open(1, None, FileObject::from_bytes(Bytes::from(data))).unwrap();
+    match corrupted.verify_checksums_only() {
+        Err(TableError::Corruption { block_idx, .. }) => assert_eq!(block_idx, Some(bad_block)),
+        other => panic!("unexpected result {:?}", other),
+    }
+
+    let (_dir, sst) = generate_sst();
+    assert!(matches!(
+        sst.verify_checksums_only(),
+        Err(TableError::Unsupported(_))
+    ));
+}
+
+#[test]
+fn test_sst_add_with_rollover() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    builder.set_compress_values(true);
+    builder.set_rollover(8 * 4196, dir.path(), 10);
+    let mut tables = Vec::new();
+    // enough entries for two full tables, then a partial one
+    let mut num_of_keys = 0;
+    while tables.len() < 2 {
+        let key = format!("key_{:05}", num_of_keys);
+        assert!(builder
+            .add_with_rollover(key.as_bytes(), &value_of(num_of_keys), |sst| tables
+                .push(sst))
+            .unwrap());
+        num_of_keys += 1;
+    }
+    assert_eq!(tables.len(), 2);
+    for _ in 0..10 {
+        let key = format!("key_{:05}", num_of_keys);
+        assert!(builder
+            .add_with_rollover(key.as_bytes(), &value_of(num_of_keys), |_| unreachable!())
+            .unwrap());
+        num_of_keys += 1;
+    }
+    builder.finish_rollover(|sst| tables.push(sst)).unwrap();
+    assert_eq!(tables.len(), 3);
+
+    let mut expected = 0;
+    for (idx, sst) in tables.iter().enumerate() {
+        assert_eq!(sst.id(), 10 + idx);
+        assert!(dir.path().join(format!("{}.sst", sst.id())).exists());
+        if idx < 2 {
+            assert_eq!(sst.num_of_blocks(), 8);
+        }
+        if idx > 0 {
+            assert!(tables[idx - 1].last_key().unwrap() < sst.first_key().unwrap());
+        }
+        for key in sst.all_keys().unwrap() {
+            assert_eq!(key, format!("key_{:05}", expected).as_bytes());
+            expected += 1;
+        }
+    }
+    assert_eq!(expected, num_of_keys);
+
+    // rolling over needs the rollover settings, and a builder writing to a single file
+    let mut builder = SsTableBuilder::new(128);
+    assert!(builder.add_with_rollover(b"key", b"value", drop).is_err());
+    assert_eq!(builder.estimated_size(), 0);
+    assert!(builder.finish_rollover(drop).is_err());
+    let mut builder = SsTableBuilder::new_spilling(128, dir.path().join("spill.sst")).unwrap();
+    builder.set_rollover(8 * 4196, dir.path(), 20);
+    assert!(builder.add_with_rollover(b"key", b"value", drop).is_err());
+}
+
+#[test]
+fn test_compact_with_dedup() {
+    let dir = tempdir().unwrap();
+    let older: Vec<(Vec<u8>, Vec<u8>)> = (0..num_of_keys())
+        .map(|idx| (key_of(idx), value_of(idx)))
+        .collect();
+    let mut builder = SsTableBuilder::new(128);
+    for (key, value) in &older {
+        assert!(builder.add(key, value));
+    }
+    let older_sst = Arc::new(builder.build(1, None, dir.path().join("1.sst")).unwrap());
+    let mut dedup = DedupStore::new();
+    dedup.register(&older_sst).unwrap();
+    assert_eq!(dedup.num_of_blocks(), older_sst.num_of_blocks());
+
+    // overwrite a few keys in the middle, leaving the other blocks of the older table unchanged
+    let newer: Vec<(Vec<u8>, Vec<u8>)> =
+        (40..45).map(|idx| (key_of(idx), b"new".to_vec())).collect();
+    let newer_sst = build_sst_from(&dir, "2.sst", &newer);
+    let path = dir.path().join("3.sst");
+    let (sst, stats) = compact_with_dedup(
+        &[newer_sst, older_sst.clone()],
+        128,
+        3,
+        None,
+        &path,
+        Some(&dedup),
+    )
+    .unwrap();
+    assert!(stats.deduped_blocks > sst.num_of_blocks() / 2);
+    assert_eq!(stats.deduped_blocks, stats.copied_blocks);
+    let references = sst
+        .block_metas()
+        .iter()
+        .filter(|meta| meta.reference.is_some())
+        .count();
+    assert_eq!(references, stats.deduped_blocks);
+    // only the rebuilt blocks are stored
+    assert_eq!(
+        sst.block_meta_offset as usize,
+        (sst.num_of_blocks() - references) * 4196
+    );
+    assert!(sst.file.size() < older_sst.file.size() / 2);
+    let histogram = sst.block_size_histogram();
+    assert_eq!(
+        histogram.iter().filter(|&&len| len == 0).count(),
+        references
+    );
+    assert_eq!(
+        histogram.iter().map(|&len| len as u64).sum::<u64>(),
+        sst.footer().compressed_bytes
+    );
+
+    let mut expected = older;
+    for (key, value) in newer {
+        let idx = expected.binary_search_by(|(k, _)| k.cmp(&key)).unwrap();
+        expected[idx].1 = value;
+    }
+    assert_eq!(
+        sst.get_with_partial_read(&key_of(0)).unwrap(),
+        Some(as_bytes(&value_of(0)))
+    );
+    sst.verify_parallel().unwrap();
+    check_sst_entries(sst, &expected);
+
+    // the references are resolved again once their table is registered on reopening
+    let reopened = SsTable::open(3, None, FileObject::open(&path).unwrap()).unwrap();
+    let block_idx = reopened
+        .block_metas()
+        .iter()
+        .position(|meta| meta.reference.is_some())
+        .unwrap();
+    assert!(matches!(
+        reopened.read_block(block_idx),
+        Err(TableError::MissingBlockSource { table_id: 1, .. })
+    ));
+    check_sst_entries(reopened.with_block_sources([older_sst]), &expected);
+}
+
+#[test]
+fn test_sst_seek_to_key_in_block() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    assert!(sst.num_of_blocks() > 2);
+    // split mid-block, so that both workers read the block holding the split key
+    let split_idx = num_of_keys() / 2 + 1;
+    let split_key = key_of(split_idx);
+    let split_block = sst.find_block_idx(&split_key);
+    assert_ne!(sst.block_metas()[split_block].first_key, split_key);
+    let starts = [(0, key_of(0)), (split_block, split_key.clone())];
+    let mut scanned = Vec::new();
+    for (worker, (block_idx, start_key)) in starts.iter().enumerate() {
+        let mut iter =
+            SsTableIterator::create_and_seek_to_key_in_block(sst.clone(), *block_idx, start_key)
+                .unwrap();
+        assert_eq!(iter.key(), start_key.as_slice());
+        while iter.is_valid() && (worker == 1 || iter.key() < split_key.as_slice()) {
+            scanned.push((as_bytes(iter.key()), as_bytes(iter.value())));
+            iter.next().unwrap();
+        }
+    }
+    let full: Vec<(Bytes, Bytes)> = (0..num_of_keys())
+        .map(|idx| (as_bytes(&key_of(idx)), as_bytes(&value_of(idx))))
+        .collect();
+    assert_eq!(scanned, full);
+
+    // a key past the block moves on to the next block
+    let last_key = sst.block_metas()[0].last_key.clone();
+    let mut after_last = last_key.to_vec();
+    after_last.push(0);
+    let iter =
+        SsTableIterator::create_and_seek_to_key_in_block(sst.clone(), 0, &after_last).unwrap();
+    assert_eq!(iter.key(), sst.block_metas()[1].first_key);
+
+    assert!(matches!(
+        SsTableIterator::create_and_seek_to_key_in_block(sst.clone(), sst.num_of_blocks(), b"")
+            .err()
+            .and_then(|err| err.downcast::<TableError>().ok()),
+        Some(TableError::BlockIndexOutOfRange { .. })
+    ));
+}
+
+#[test]
+fn test_sst_uncompressed_data_size() {
+    let (_dir, plain) = generate_sst();
+    assert_eq!(plain.uncompressed_data_size(), plain.data_size());
+    assert_eq!(
+        plain.data_size(),
+        plain
+            .block_size_histogram()
+            .iter()
+            .map(|&len| len as u64)
+            .sum::<u64>()
+    );
+
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(1024);
+    builder.set_compress_values(true);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_of(idx), &[b'a' + (idx % 26) as u8; 200]));
+    }
+    let path = dir.path().join("1.sst");
+    let sst = builder.build_for_test(&path).unwrap();
+    assert!(sst.uncompressed_data_size() > sst.data_size());
+    // every value is 200 bytes once decompressed
+    assert!(sst.uncompressed_data_size() > num_of_keys() as u64 * 200);
+    let reopened = SsTable::open_for_test(FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(
+        reopened.uncompressed_data_size(),
+        sst.uncompressed_data_size()
+    );
+}
+
+#[test]
+fn test_sst_builder_offsets_increasing() {
+    let dir = tempdir().unwrap();
+    // blocks filled up to the whole slot, so a block ends right where the next slot starts
+    let mut builder = SsTableBuilder::new(4196);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_of(idx), &[idx as u8; 1000]));
+    }
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+    assert!(sst.num_of_blocks() > 2);
+    assert!(sst.block_size_histogram().iter().any(|&len| len > 4000));
+    for (block_idx, pair) in sst.block_metas().windows(2).enumerate() {
+        assert!(pair[0].offset < pair[1].offset);
+        assert!(pair[0].offset as u64 <= (block_idx as u64 + 1) * 4196);
+    }
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "not after the block before it")]
+fn test_sst_builder_offsets_not_increasing() {
+    let mut builder = SsTableBuilder::new(128);
+    assert!(builder.add(&key_of(0), &value_of(0)));
+    // a meta past where the builder will place its next block, as if the slots advanced wrongly
+    builder.meta.push(BlockMeta {
+        offset: 10 * 4196 + 100,
+        num_entries: 1,
+        key_len: 0,
+        first_key: Bytes::new(),
+        last_key: Bytes::new(),
+        reference: None,
+        start: 10 * 4196,
+        packed: false,
+    });
+    for idx in 1..num_of_keys() {
+        assert!(builder.add(&key_of(idx), &value_of(idx)));
+    }
+}
+
+#[test]
+fn test_sst_block_byte_range() {
+    let (_dir, sst) = generate_sst();
+    let ranges: Vec<_> = (0..sst.num_of_blocks())
+        .map(|block_idx| sst.block_byte_range(block_idx).unwrap())
+        .collect();
+    // each range starts where the slot of the block before it ends, the rest being padding
+    let mut slot_start = 0;
+    for (block_idx, range) in ranges.iter().enumerate() {
+        assert_eq!(range.start, slot_start);
+        assert!(range.end > range.start && range.end <= range.start + 4196);
+        slot_start += 4196;
+        let data = sst.file.read(range.start, range.end - range.start).unwrap();
+        let block = Block::decode(&data);
+        assert_eq!(
+            block.key_at(0),
+            sst.block_metas()[block_idx].first_key.as_ref()
+        );
+    }
+    assert_eq!(slot_start, sst.block_meta_offset as u64);
+    assert_eq!(
+        ranges
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum::<u64>(),
+        sst.data_size()
+    );
+    assert_eq!(sst.block_byte_range(sst.num_of_blocks()), None);
+}
+
+#[test]
+fn test_sst_decode_block_from() {
+    let (_dir, sst) = generate_sst();
+    let data = sst.file.read(0, sst.file.size()).unwrap();
+    let block_cache = Arc::new(BlockCache::new(1024));
+    let sst = SsTable::open(
+        7,
+        Some(block_cache.clone()),
+        FileObject::from_bytes(Bytes::from(data)),
+    )
+    .unwrap();
+    for block_idx in 0..sst.num_of_blocks() {
+        let range = sst.block_byte_range(block_idx).unwrap();
+        let bytes = sst.file.read(range.start, range.end - range.start).unwrap();
+        let block = sst.decode_block_from(block_idx, &bytes).unwrap();
+        assert_eq!(block.encode(), sst.read_block(block_idx).unwrap().encode());
+        assert!(block_cache.get(&(7, block_idx)).is_some());
+    }
+
+    let range = sst.block_byte_range(0).unwrap();
+    let bytes = sst
+        .file
+        .read(range.start, range.end - range.start + 1)
+        .unwrap();
+    assert!(matches!(
+        sst.decode_block_from(0, &bytes),
+        Err(TableError::Corruption {
+            block_idx: Some(0),
+            ..
+        })
+    ));
+    assert!(matches!(
+        sst.decode_block_from(sst.num_of_blocks(), &bytes),
+        Err(TableError::BlockIndexOutOfRange { .. })
+    ));
+}
+
+#[test]
+fn test_sst_iterator_cancellation() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    let cancellation = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    iter.set_cancellation(cancellation.clone());
+    let first_block_entries = sst.block_metas()[0].num_entries as usize;
+    for _ in 1..first_block_entries {
+        iter.next().unwrap();
+    }
+    assert!(iter.is_valid());
+    cancellation.store(true, std::sync::atomic::Ordering::Relaxed);
+    let reads = sst.file.read_count();
+    // the next entry is in the second block, which is never read
+    let err = iter.next().unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<TableError>(),
+        Some(TableError::Cancelled)
+    ));
+    assert_eq!(sst.file.read_count(), reads);
+    assert!(!iter.is_valid());
+    assert!(iter.next().is_err());
+}
+
+#[test]
+fn test_sst_from_unsorted() {
+    let dir = tempdir().unwrap();
+    let mut entries: Vec<(Bytes, Bytes)> = (0..num_of_keys())
+        .map(|idx| (as_bytes(&key_of(idx)), as_bytes(&value_of(idx))))
+        .collect();
+    // overwrite every tenth key twice
+    for idx in (0..num_of_keys()).step_by(10) {
+        entries.push((as_bytes(&key_of(idx)), Bytes::from("first")));
+        entries.push((as_bytes(&key_of(idx)), Bytes::from("second")));
+    }
+    // a deterministic shuffle
+    let mut rng = 0x2545_f491_4f6c_dd1du64;
+    for idx in (1..entries.len()).rev() {
+        rng ^= rng << 13;
+        rng ^= rng >> 7;
+        rng ^= rng << 17;
+        entries.swap(idx, rng as usize % (idx + 1));
+    }
+    // the value of each key is the one that comes last in the shuffled input
+    let expected: Vec<(Bytes, Bytes)> = entries
+        .iter()
+        .cloned()
+        .collect::<BTreeMap<Bytes, Bytes>>()
+        .into_iter()
+        .collect();
+    assert_eq!(expected.len(), num_of_keys());
+    let sst =
+        SsTableBuilder::from_unsorted(entries, 128, 1, None, dir.path().join("1.sst")).unwrap();
+    assert_eq!(collect_sst(sst), expected);
+
+    let empty_key = vec![(Bytes::new(), Bytes::from("value"))];
+    assert!(
+        SsTableBuilder::from_unsorted(empty_key, 128, 2, None, dir.path().join("2.sst")).is_err()
+    );
+    // an entry larger than a block is rejected instead of panicking
+    let mut builder = SsTableBuilder::new(4096);
+    assert!(builder.add(&key_of(0), &value_of(0)));
+    assert!(!builder.add(&key_of(1), &vec![b'x'; 0x8000]));
+    assert!(!builder.add(&key_of(1), &vec![b'x'; 5000]));
+    assert!(builder.add(&key_of(1), &value_of(1)));
+    let sst = builder.build_for_test(dir.path().join("3.sst")).unwrap();
+    assert_eq!(sst.num_of_blocks(), 1);
+    let large = vec![(as_bytes(&key_of(0)), Bytes::from(vec![b'x'; 0x8000]))];
+    assert!(SsTableBuilder::from_unsorted(large, 4096, 4, None, dir.path().join("4.sst")).is_err());
+}
+
+#[test]
+fn test_sst_append_blocks() {
+    let (dir, sst) = generate_sst();
+    let appended: Vec<(Bytes, Bytes)> = (num_of_keys()..num_of_keys() + 50)
+        .map(|idx| (as_bytes(&key_of(idx)), as_bytes(&value_of(idx))))
+        .collect();
+    let new_sst = sst
+        .append_blocks(
+            SliceIterator {
+                data: &appended,
+                idx: 0,
+            },
+            128,
+            2,
+            None,
+            dir.path().join("2.sst"),
+        )
+        .unwrap();
+    // the original blocks are copied as they are
+    assert!(new_sst.num_of_blocks() > sst.num_of_blocks());
+    for block_idx in 0..sst.num_of_blocks() {
+        assert_eq!(
+            new_sst.block_byte_range(block_idx),
+            sst.block_byte_range(block_idx)
+        );
+        assert_eq!(
+            new_sst.read_encoded_block(block_idx).unwrap(),
+            sst.read_encoded_block(block_idx).unwrap()
+        );
+    }
+    let expected: Vec<(Bytes, Bytes)> = (0..num_of_keys() + 50)
+        .map(|idx| (as_bytes(&key_of(idx)), as_bytes(&value_of(idx))))
+        .collect();
+    assert_eq!(collect_sst(new_sst), expected);
+
+    let overlapping = vec![(as_bytes(&key_of(num_of_keys() - 1)), Bytes::from("value"))];
+    assert!(sst
+        .append_blocks(
+            SliceIterator {
+                data: &overlapping,
+                idx: 0,
+            },
+            128,
+            3,
+            None,
+            dir.path().join("3.sst"),
+        )
+        .is_err());
+
+    // the blocks left out of a sparse index get their keys back in the new table
+    let mut builder = SsTableBuilder::new(128);
+    builder.set_index_sparsity(4);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_of(idx), &value_of(idx)));
+    }
+    let sparse = builder.build(4, None, dir.path().join("4.sst")).unwrap();
+    assert!(!sparse.is_indexed(1));
+    let new_sst = sparse
+        .append_blocks(
+            SliceIterator {
+                data: &appended,
+                idx: 0,
+            },
+            128,
+            5,
+            None,
+            dir.path().join("5.sst"),
+        )
+        .unwrap();
+    assert!(new_sst.is_indexed(1));
+    assert_eq!(
+        new_sst.block_metas()[1].first_key,
+        sparse.read_block(1).unwrap().key_at(0)
+    );
+    for (key, value) in &expected {
+        assert_eq!(new_sst.get(key).unwrap().as_ref(), Some(value));
+    }
+    assert_eq!(collect_sst(new_sst), expected);
+}
+
+#[test]
+fn test_sst_read_footer_bytes() {
+    let (dir, sst) = generate_sst();
+    let raw = SsTable::read_footer_bytes(&sst.file).unwrap();
+    assert_eq!(raw.bytes.len(), FOOTER_SIZE);
+    assert_eq!(raw.decode().unwrap(), *sst.footer());
+    assert_eq!(raw.block_meta_offset, sst.footer().block_meta_offset);
+    assert_eq!(raw.version, 4);
+    assert!(raw.has_valid_magic());
+    assert!(raw.has_valid_checksum());
+
+    // a corrupted footer is still read, so that it can be inspected
+    let mut data = sst.file.read(0, sst.file.size()).unwrap();
+    let len = data.len();
+    data[len - 1] ^= 0xff;
+    data[len - 20] ^= 0xff;
+    let file = FileObject::create(&dir.path().join("2.sst"), data).unwrap();
+    let raw = SsTable::read_footer_bytes(&file).unwrap();
+    assert!(!raw.has_valid_magic());
+    assert!(!raw.has_valid_checksum());
+    assert!(raw.decode().is_err());
+
+    let file = FileObject::create(&dir.path().join("3.sst"), vec![0; 10]).unwrap();
+    assert!(SsTable::read_footer_bytes(&file).is_err());
+}
+
+#[test]
+fn test_sst_pack_small_blocks() {
+    use crate::block::BlockBuilder;
+
+    let dir = tempdir().unwrap();
+    let value = Bytes::from(vec![b'v'; 100]);
+    let mut builder = SsTableBuilder::new(4096);
+    builder.set_pack_threshold(1024);
+    let mut spilling = SsTableBuilder::new_spilling(4096, dir.path().join("2.sst")).unwrap();
+    spilling.set_pack_threshold(1024);
+    let mut expected = Vec::new();
+    // tiny blocks of a single entry and large blocks of 30 entries
+    for num_entries in [1, 1, 30, 1, 30, 1] {
+        let mut block = BlockBuilder::new(4096);
+        let keys: Vec<Bytes> = (expected.len()..expected.len() + num_entries)
+            .map(|idx| as_bytes(&key_of(idx)))
+            .collect();
+        for key in &keys {
+            assert!(block.add(key, &value));
+            expected.push((key.clone(), value.clone()));
+        }
+        let encoded = block.build().encode();
+        let (first_key, last_key) = (&keys[0], &keys[num_entries - 1]);
+        builder
+            .add_encoded_block(encoded.clone(), first_key, last_key)
+            .unwrap();
+        spilling
+            .add_encoded_block(encoded, first_key, last_key)
+            .unwrap();
+    }
+    let sst = builder.build_for_test(dir.path().join("1.sst")).unwrap();
+
+    let mut data_end = 0;
+    for (block_idx, meta) in sst.block_metas().iter().enumerate() {
+        let range = sst.block_byte_range(block_idx).unwrap();
+        if meta.num_entries == 1 {
+            // a tiny block is not padded, and starts right where the block before it ends
+            assert!(meta.is_packed());
+            assert_eq!(range.start, data_end);
+            data_end = range.end;
+        } else {
+            assert!(!meta.is_packed());
+            assert_eq!(range.start % 4196, 0);
+            assert!(range.start >= data_end && range.start < data_end + 4196);
+            data_end = range.start + 4196;
+        }
+        assert_eq!(sst.block_for_offset(range.start), Some(block_idx));
+        assert_eq!(sst.block_for_offset(range.end - 1), Some(block_idx));
+    }
+    // the data section is padded to end on a whole slot, taking 5 slots instead of 6
+    assert_eq!(sst.block_meta_offset, 5 * 4196);
+    assert!(data_end > 4 * 4196);
+    assert_eq!(sst.block_for_offset(data_end), None);
+
+    let reopened =
+        SsTable::open_for_test(FileObject::open(&dir.path().join("1.sst")).unwrap()).unwrap();
+    assert_eq!(reopened.block_metas(), sst.block_metas());
+    assert_eq!(collect_sst(reopened), expected);
+
+    // a spilling builder lays the blocks out the same way
+    let spilled = spilling.build_spilled(0, None).unwrap();
+    assert_eq!(
+        spilled.file.read(0, spilled.file.size()).unwrap(),
+        sst.file.read(0, sst.file.size()).unwrap()
+    );
+}
+
+#[test]
+fn test_merge_range() {
+    let dir = tempdir().unwrap();
+    let entry = |idx: usize, value: &[u8]| (key_of(idx), value.to_vec());
+    // the newer table overwrites every other key, deleting every sixth one
+    let newer: Vec<_> = (0..num_of_keys())
+        .step_by(2)
+        .map(|idx| match idx % 6 {
+            0 => entry(idx, b""),
+            _ => entry(idx, format!("new_{}", idx).as_bytes()),
+        })
+        .collect();
+    let older: Vec<_> = (0..num_of_keys())
+        .map(|idx| entry(idx, &value_of(idx)))
+        .collect();
+    let a = build_sst_from(&dir, "1.sst", &newer);
+    let b = build_sst_from(&dir, "2.sst", &older);
+    let merged = merge_range(&a, &b, &key_of(20), &key_of(60)).unwrap();
+    let expected: Vec<_> = (20..60)
+        .map(|idx| match (idx % 2, idx % 6) {
+            (_, 0) => (as_bytes(&key_of(idx)), Bytes::new()),
+            (0, _) => (as_bytes(&key_of(idx)), Bytes::from(format!("new_{}", idx))),
+            _ => (as_bytes(&key_of(idx)), as_bytes(&value_of(idx))),
+        })
+        .collect();
+    assert_eq!(merged, expected);
+    // the bounds need not be keys of the tables
+    assert_eq!(
+        merge_range(&a, &b, b"key_0999", b"key_2999").unwrap(),
+        expected
+    );
+
+    // disjoint tables are merged in key order
+    let low = build_sst_from(&dir, "3.sst", &older[..50]);
+    let high = build_sst_from(&dir, "4.sst", &older[50..]);
+    let expected: Vec<_> = older[40..60]
+        .iter()
+        .map(|(key, value)| (as_bytes(key), as_bytes(value)))
+        .collect();
+    assert_eq!(
+        merge_range(&high, &low, &key_of(40), &key_of(60)).unwrap(),
+        expected
+    );
+    assert!(merge_range(&high, &low, b"key_999", b"key_9999")
+        .unwrap()
+        .is_empty());
+    assert!(merge_range(&high, &low, &key_of(60), &key_of(40))
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_sst_seek_to_key_exact() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    for idx in 0..num_of_keys() {
+        assert!(iter.seek_to_key_exact(&key_of(idx)).unwrap());
+        assert_eq!(iter.key(), key_of(idx));
+        // between two keys, the seek lands on the next one
+        let absent = format!("key_{:03}", idx * 5 + 1).into_bytes();
+        assert!(!iter.seek_to_key_exact(&absent).unwrap());
+        if idx + 1 < num_of_keys() {
+            assert_eq!(iter.key(), key_of(idx + 1));
+        } else {
+            assert!(!iter.is_valid());
+        }
+    }
+    // right past the last key of each block, the seek lands on the first key of the next one
+    for block_idx in 0..sst.num_of_blocks() - 1 {
+        let mut absent = sst.block_metas()[block_idx].last_key.to_vec();
+        absent.push(0);
+        assert!(!iter.seek_to_key_exact(&absent).unwrap());
+        assert_eq!(iter.key(), sst.block_metas()[block_idx + 1].first_key);
+        let first_key = sst.block_metas()[block_idx + 1].first_key.clone();
+        assert!(iter.seek_to_key_exact(&first_key).unwrap());
+    }
+    assert!(!iter.seek_to_key_exact(b"key").unwrap());
+    assert_eq!(iter.key(), key_of(0));
+    assert!(!iter.seek_to_key_exact(b"key_999").unwrap());
+    assert!(!iter.is_valid());
+}
+
+#[test]
+fn test_sst_iterator_current_entry() {
+    let dir = tempdir().unwrap();
+    for compress_values in [false, true] {
+        let mut builder = SsTableBuilder::new(128);
+        builder.set_compress_values(compress_values);
+        for idx in 0..num_of_keys() {
+            assert!(builder.add(&key_of(idx), &value_of(idx)));
+        }
+        let sst = Arc::new(builder.build_for_test(dir.path().join("1.sst")).unwrap());
+        let mut iter = SsTableIterator::create_and_seek_to_first(sst).unwrap();
+        let mut entries = Vec::new();
+        while iter.is_valid() {
+            let (key, value) = iter.current_entry();
+            if !compress_values {
+                // the value follows its key and value length in the buffer of the block
+                assert_eq!(value.as_ptr(), key.as_ptr().wrapping_add(key.len() + 2));
+            }
+            entries.push((key, value));
+            iter.next().unwrap();
+        }
+        // the entries outlive the iterator, the table and the blocks
+        drop(iter);
+        let expected: Vec<(Bytes, Bytes)> = (0..num_of_keys())
+            .map(|idx| (as_bytes(&key_of(idx)), as_bytes(&value_of(idx))))
+            .collect();
+        assert_eq!(entries, expected);
+    }
+}
+
+#[test]
+fn test_sst_file_handle_cache() {
+    let dir = tempdir().unwrap();
+    let cache = Arc::new(FileHandleCache::new(3));
+    let tables: Vec<Arc<SsTable>> = (0..10)
+        .map(|id| {
+            let mut builder = SsTableBuilder::new(128);
+            for idx in 0..num_of_keys() {
+                assert!(builder.add(&key_of(idx), format!("{}_{}", id, idx).as_bytes()));
+            }
+            let path = dir.path().join(format!("{}.sst", id));
+            builder.build_for_test(&path).unwrap();
+            let file = FileObject::open_with_handle_cache(&path, cache.clone()).unwrap();
+            Arc::new(SsTable::open(id, None, file).unwrap())
+        })
+        .collect();
+    assert!(cache.open_handles() <= 3);
+
+    // reads round-robin over more tables than may be open at once
+    for idx in 0..num_of_keys() {
+        for (id, table) in tables.iter().enumerate() {
+            assert_eq!(
+                table.get(&key_of(idx)).unwrap(),
+                Some(Bytes::from(format!("{}_{}", id, idx)))
+            );
+            assert!(cache.open_handles() <= 3);
+        }
+    }
+    // the handles were closed and reopened along the way
+    assert!(cache.opens() > tables.len());
+
+    assert_eq!(cache.live_handles(), cache.open_handles());
+
+    // dropping a table closes its handle
+    drop(tables);
+    assert_eq!(cache.open_handles(), 0);
+    assert_eq!(cache.live_handles(), 0);
+
+    // a handle in use by a read stays open once closed by the cache, and handles not in use are
+    // closed first
+    let cache = FileHandleCache::new(2);
+    let paths: Vec<_> = (0..3)
+        .map(|id| dir.path().join(format!("{}.sst", id)))
+        .collect();
+    let ids: Vec<u64> = paths.iter().map(|_| cache.register()).collect();
+    let in_use = cache.handle(ids[0], &paths[0]).unwrap();
+    cache.handle(ids[1], &paths[1]).unwrap();
+    cache.handle(ids[2], &paths[2]).unwrap();
+    assert_eq!(cache.open_handles(), 2);
+    assert_eq!(cache.live_handles(), 2);
+    // the handle in use was kept, so reading its file again does not reopen it
+    let opens = cache.opens();
+    drop(cache.handle(ids[0], &paths[0]).unwrap());
+    assert_eq!(cache.opens(), opens);
+    // with every handle in use, the least recently used one is closed while still in use
+    let also_in_use = cache.handle(ids[2], &paths[2]).unwrap();
+    cache.handle(ids[1], &paths[1]).unwrap();
+    assert_eq!(cache.open_handles(), 2);
+    assert_eq!(cache.live_handles(), 3);
+    drop(in_use);
+    assert_eq!(cache.live_handles(), 2);
+    drop(also_in_use);
+    assert_eq!(cache.live_handles(), 2);
+}
+
+#[test]
+fn test_estimate_compaction_output() {
+    let dir = tempdir().unwrap();
+    // the newest table deletes every 4th key of the middle table, which overwrites every other
+    // key of the oldest one, so that there are more overlapping blocks than are sampled
+    let key = |idx: usize| format!("key_{:05}", idx).into_bytes();
+    let oldest: Vec<(Vec<u8>, Vec<u8>)> = (0..1000).map(|idx| (key(idx), value_of(idx))).collect();
+    let middle: Vec<(Vec<u8>, Vec<u8>)> = (0..1000)
+        .step_by(2)
+        .map(|idx| (key(idx), b"new".to_vec()))
+        .collect();
+    let newest: Vec<(Vec<u8>, Vec<u8>)> = (0..1000)
+        .step_by(4)
+        .map(|idx| (key(idx), Vec::new()))
+        .collect();
+    let tables = vec![
+        build_sst_from(&dir, "3.sst", &newest),
+        build_sst_from(&dir, "2.sst", &middle),
+        build_sst_from(&dir, "1.sst", &oldest),
+    ];
+    let estimate = estimate_compaction_output(&tables).unwrap();
+    let (sst, _) = compact(&tables, 128, 4, None, dir.path().join("4.sst")).unwrap();
+    let within = |estimated: f64, actual: f64| (estimated - actual).abs() <= actual * 0.1;
+    assert_eq!(sst.footer.entry_count, 1000);
+    assert!(
+        within(estimate.output_entries as f64, 1000.0),
+        "{estimate:?}"
+    );
+    assert!(
+        within(estimate.output_bytes as f64, sst.data_size() as f64),
+        "{estimate:?} for {} bytes",
+        sst.data_size()
+    );
+    // none of the tombstones are shadowed
+    assert_eq!(estimate.dropped_tombstones, 0);
+
+    // shadowed tombstones are dropped; exact with fewer overlapping blocks than samples
+    let tables = vec![
+        build_sst_from(&dir, "6.sst", &middle[..50]),
+        build_sst_from(&dir, "5.sst", &newest[..25]),
+    ];
+    let estimate = estimate_compaction_output(&tables).unwrap();
+    let (sst, _) = compact(&tables, 128, 7, None, dir.path().join("7.sst")).unwrap();
+    assert_eq!(estimate.output_entries as u64, sst.footer.entry_count);
+    assert_eq!(estimate.output_entries, 50);
+    assert_eq!(estimate.dropped_tombstones, 25);
+
+    assert_eq!(
+        estimate_compaction_output(&[]).unwrap(),
+        CompactionEstimate::default()
+    );
+
+    // interleaved tables whose ranges overlap but whose blocks, of one entry each, do not are
+    // estimated from the metas alone, without reading a block
+    let tables: Vec<_> = (0..4)
+        .map(|table_idx| {
+            let entries: Vec<(Vec<u8>, Vec<u8>)> = (table_idx..400)
+                .step_by(4)
+                .map(|idx| (key(idx), vec![b'v'; 100]))
+                .collect();
+            build_sst_from(&dir, &format!("{}.sst", 10 + table_idx), &entries)
+        })
+        .collect();
+    let reads = |tables: &[Arc<SsTable>]| -> usize {
+        tables.iter().map(|table| table.file.read_count()).sum()
+    };
+    // the metas are read on first use
+    for table in &tables {
+        table.block_metas();
+    }
+    let reads_before = reads(&tables);
+    let estimate = estimate_compaction_output(&tables).unwrap();
+    assert_eq!(reads(&tables), reads_before);
+    assert_eq!(estimate.output_entries, 400);
+    assert_eq!(estimate.dropped_tombstones, 0);
+}
+
+#[test]
+fn test_sst_level() {
+    let dir = tempdir().unwrap();
+    let mut builder = SsTableBuilder::new(128);
+    for idx in 0..num_of_keys() {
+        assert!(builder.add(&key_of(idx), &value_of(idx)));
+    }
+    builder.set_level(3);
+    let path = dir.path().join("1.sst");
+    let built = builder.build(1, None, &path).unwrap();
+    assert_eq!(built.level(), Some(3));
+    assert_eq!(built.property("level"), Some(&3u32.to_be_bytes()[..]));
+    let reopened = SsTable::open(1, None, FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(reopened.level(), Some(3));
+    let lazy = SsTable::open_lazy(1, None, FileObject::open(&path).unwrap()).unwrap();
+    assert_eq!(lazy.table().unwrap().level(), Some(3));
+
+    // without a level, or with a `level` property that is not one
+    let sst = build_sst_from(&dir, "2.sst", &[(key_of(0), value_of(0))]);
+    assert_eq!(sst.level(), None);
+    let mut builder = SsTableBuilder::new(128);
+    assert!(builder.add(&key_of(0), &value_of(0)));
+    builder.set_property("level", b"L1");
+    let sst = builder.build(3, None, dir.path().join("3.sst")).unwrap();
+    assert_eq!(sst.level(), None);
+}
+
+#[test]
+fn test_sst_seek_forward_to() {
+    let (_dir, sst) = generate_sst();
+    let sst = Arc::new(sst);
+    // every key in turn, absent keys in between, and jumps past the next block
+    let mut targets = Vec::new();
+    for idx in 0..num_of_keys() {
+        targets.push(key_of(idx));
+        targets.push(format!("key_{:03}", idx * 5 + 1).into_bytes());
+        if idx % 20 == 0 {
+            targets.push(key_of(idx + 15));
+        }
+    }
+    targets.sort();
+
+    let reads = sst.file.read_count();
+    let mut iter = SsTableIterator::create_and_seek_to_first(sst.clone()).unwrap();
+    let mut landed = Vec::new();
+    for key in &targets {
+        iter.seek_forward_to(key).unwrap();
+        landed.push(iter.is_valid().then(|| iter.key().to_vec()));
+    }
+    let forward_reads = sst.file.read_count() - reads;
+
+    let reads = sst.file.read_count();
+    for (key, landed) in targets.iter().zip(&landed) {
+        let iter = SsTableIterator::create_and_seek_to_key(sst.clone(), key).unwrap();
+        assert_eq!(&iter.is_valid().then(|| iter.key().to_vec()), landed);
+    }
+    let independent_reads = sst.file.read_count() - reads;
+    // each block is read at most once, the ones jumped over never
+    assert!(forward_reads <= sst.num_of_blocks(), "{forward_reads}");
+    assert!(forward_reads * 5 < independent_reads);
+
+    // past the last key, then back to a key before the current one
+    iter.seek_forward_to(b"key_999").unwrap();
+    assert!(!iter.is_valid());
+    iter.seek_forward_to(&key_of(10)).unwrap();
+    assert_eq!(iter.key(), key_of(10));
+    iter.seek_forward_to(&key_of(3)).unwrap();
+    assert_eq!(iter.key(), key_of(3));
+}